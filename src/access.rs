@@ -0,0 +1,427 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::http::StatusCode;
+use ldap3_serde::{
+    asn1::{ASNTag, StructureTag, PL},
+    Ldap, LdapError, LdapResult, ResultEntry, Scope,
+};
+use ssh_key::authorized_keys::ConfigOpts;
+
+use crate::{
+    context,
+    dn::{self, Dn},
+    schema::Subschema,
+    types::{query::QueryCommand, routes::Response},
+};
+
+/// Restrictions attached to an authorized key through its options, e.g.
+/// `attrs-deny="userPassword,sshPublicKey" ssh-ed25519 AAAA...`.
+#[derive(Debug, Clone, Default)]
+pub struct KeyAccess {
+    /// Lowercase attributes the key may read; every attribute if `None`.
+    pub attrs_allow: Option<HashSet<String>>,
+    /// Lowercase attributes the key may never read.
+    pub attrs_deny: HashSet<String>,
+    /// Subtrees the key may operate in; anywhere if empty.
+    pub subtrees: Vec<Dn>,
+}
+
+fn attribute_list(value: &str) -> HashSet<String> {
+    value
+        .split(',')
+        .map(|attr| attr.trim().to_lowercase())
+        .filter(|attr| !attr.is_empty())
+        .collect()
+}
+
+/// Attribute type of an attribute description, without options such as `;binary`.
+fn attribute_type(attr: &str) -> String {
+    match attr.split_once(';') {
+        Some((attr, _)) => attr.to_lowercase(),
+        None => attr.to_lowercase(),
+    }
+}
+
+/// Attribute descriptions an RFC 4515 filter tests, read from the filter as ldap3
+/// parses it, so that bare items such as `cn=a*` are covered too. Extensible
+/// matches without an attribute, which test every attribute, are rejected.
+fn filter_attributes(filter: &str) -> Result<Vec<String>, LdapError> {
+    let tag = match ldap3_serde::parse_filter(filter) {
+        Ok(val) => val.into_structure(),
+        Err(()) => return Err(LdapError::FilterParsing),
+    };
+    let mut attrs = Vec::new();
+    collect_filter_attributes(&tag, &mut attrs)?;
+    Ok(attrs)
+}
+
+fn collect_filter_attributes(tag: &StructureTag, attrs: &mut Vec<String>) -> Result<(), LdapError> {
+    let name = |tag: Option<&StructureTag>| match tag.map(|tag| &tag.payload) {
+        Some(PL::P(name)) => Ok(String::from_utf8_lossy(name).into_owned()),
+        _ => Err(LdapError::FilterParsing),
+    };
+    match (tag.id, &tag.payload) {
+        // and, or, not
+        (0..=2, PL::C(inner)) => {
+            for tag in inner {
+                collect_filter_attributes(tag, attrs)?;
+            }
+        }
+        // present
+        (7, PL::P(_)) => attrs.push(name(Some(tag))?),
+        // extensible match, whose attribute is tagged 2
+        (9, PL::C(inner)) => match inner.iter().find(|tag| tag.id == 2) {
+            Some(attr) => attrs.push(name(Some(attr))?),
+            None => {
+                return Err(insufficient_access(
+                    "Extensible matches without an attribute are not allowed",
+                ))
+            }
+        },
+        // equality, substrings, ordering and approximate matches
+        (_, PL::C(inner)) => attrs.push(name(inner.first())?),
+        (_, PL::P(_)) => return Err(LdapError::FilterParsing),
+    }
+    Ok(())
+}
+
+/// Error returned in place of a result when a key reaches beyond its restrictions.
+pub fn insufficient_access(message: impl Into<String>) -> LdapError {
+    LdapError::LdapResult {
+        result: LdapResult {
+            rc: 50,
+            matched: String::new(),
+            text: message.into(),
+            refs: Vec::new(),
+            ctrls: Vec::new(),
+        },
+    }
+}
+
+impl KeyAccess {
+    /// Reads the restrictions from the options of an authorized_keys entry, ignoring
+    /// options it does not know.
+    pub fn from_options(options: &ConfigOpts) -> Result<Self, String> {
+        let mut access = KeyAccess::default();
+        for option in options.iter() {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, value.trim_matches('"')),
+                None => continue,
+            };
+            match name {
+                "attrs-allow" => {
+                    let attrs = attribute_list(value);
+                    if attrs.is_empty() {
+                        return Err("attrs-allow must list at least one attribute".to_string());
+                    }
+                    access.attrs_allow = Some(attrs);
+                }
+                "attrs-deny" => access.attrs_deny.extend(attribute_list(value)),
+                "subtree" => match dn::parse(value) {
+                    Ok(val) => access.subtrees.push(val),
+                    Err(err) => return Err(format!("Invalid subtree: {}", err)),
+                },
+                _ => {}
+            }
+        }
+        Ok(access)
+    }
+
+    pub fn can_read(&self, attr: &str) -> bool {
+        let attr = attribute_type(attr);
+        if self.attrs_deny.contains(&attr) {
+            return false;
+        }
+        match &self.attrs_allow {
+            Some(allow) => allow.contains(&attr),
+            None => true,
+        }
+    }
+
+    fn filters_attributes(&self) -> bool {
+        self.attrs_allow.is_some() || !self.attrs_deny.is_empty()
+    }
+
+    /// Extends the allowed and denied attributes with the other names and the OID
+    /// the directory knows them by, so that an alias cannot stand in for them.
+    pub fn with_subschema(&self, subschema: &Subschema) -> KeyAccess {
+        let expand = |attrs: &HashSet<String>| {
+            attrs
+                .iter()
+                .flat_map(|attr| subschema.names(attr))
+                .collect::<HashSet<_>>()
+        };
+        KeyAccess {
+            attrs_allow: self.attrs_allow.as_ref().map(expand),
+            attrs_deny: expand(&self.attrs_deny),
+            subtrees: self.subtrees.clone(),
+        }
+    }
+
+    /// Rejects filters that test attributes the key may not read, which would
+    /// otherwise reveal their values.
+    pub fn check_filter(&self, filter: &str) -> Result<(), LdapError> {
+        if !self.filters_attributes() {
+            return Ok(());
+        }
+        match filter_attributes(filter)?
+            .into_iter()
+            .find(|attr| !self.can_read(attr))
+        {
+            Some(attr) => Err(insufficient_access(format!(
+                "Not allowed to read attribute \"{}\"",
+                attr
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Narrows the attributes requested by a search to those the key may read.
+    /// `*` and `+` stand for the allowed attributes when there is an allow list.
+    pub fn requested_attrs(&self, attrs: &[String]) -> Vec<String> {
+        if !self.filters_attributes() {
+            return attrs.to_vec();
+        }
+
+        let mut requested = Vec::new();
+        let wildcard = attrs.is_empty() || attrs.iter().any(|attr| attr == "*" || attr == "+");
+        match (&self.attrs_allow, wildcard) {
+            (Some(allow), true) => requested.extend(allow.iter().cloned()),
+            (None, true) => requested.extend(
+                attrs
+                    .iter()
+                    .filter(|attr| *attr == "*" || *attr == "+")
+                    .cloned(),
+            ),
+            (_, false) => {}
+        }
+        requested.extend(
+            attrs
+                .iter()
+                .filter(|attr| *attr != "*" && *attr != "+" && self.can_read(attr))
+                .cloned(),
+        );
+
+        if requested.is_empty() && !attrs.is_empty() {
+            // An empty list would ask for every attribute.
+            requested.push("1.1".to_string());
+        }
+        requested
+    }
+
+    /// Removes the attributes the key may not read from a search result entry.
+    pub fn filter_result_entry(&self, entry: &mut ResultEntry) {
+        if !self.filters_attributes() || entry.0.id != 4 {
+            return;
+        }
+        let parts = match &mut entry.0.payload {
+            PL::C(val) => val,
+            PL::P(_) => return,
+        };
+        let attrs = match parts.get_mut(1) {
+            Some(StructureTag {
+                payload: PL::C(val),
+                ..
+            }) => val,
+            _ => return,
+        };
+        attrs.retain(|attr| match &attr.payload {
+            PL::C(inner) => match inner.first().map(|tag| &tag.payload) {
+                Some(PL::P(name)) => self.can_read(&String::from_utf8_lossy(name)),
+                _ => false,
+            },
+            PL::P(_) => false,
+        });
+    }
+
+    fn is_scoped(&self, dn: &Dn) -> bool {
+        self.subtrees.is_empty() || self.subtrees.iter().any(|base| dn.is_within(base))
+    }
+
+    /// Checks that `dn` lies within the subtrees of the key.
+    pub fn check_dn(&self, dn: &str) -> Result<(), String> {
+        if self.subtrees.is_empty() {
+            return Ok(());
+        }
+        match dn::parse(dn) {
+            Ok(val) if self.is_scoped(&val) => Ok(()),
+            Ok(_) => Err(format!("\"{}\" is outside the subtrees of the key", dn)),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Bases and scopes a search has to be split into to stay within the subtrees
+    /// of the key. A search starting above them is clamped to the subtrees it reaches.
+    pub fn search_bases(&self, base: &str, scope: Scope) -> Result<Vec<(String, Scope)>, String> {
+        if self.subtrees.is_empty() {
+            return Ok(vec![(base.to_string(), scope)]);
+        }
+        let base_dn = match dn::parse(base) {
+            Ok(val) => val,
+            Err(err) => return Err(err.to_string()),
+        };
+        if self.is_scoped(&base_dn) {
+            return Ok(vec![(base.to_string(), scope)]);
+        }
+
+        let bases = self
+            .subtrees
+            .iter()
+            .filter(|subtree| subtree.is_within(&base_dn))
+            .filter_map(|subtree| match scope {
+                Scope::Subtree => Some((subtree.to_string(), Scope::Subtree)),
+                Scope::OneLevel if subtree.0.len() == base_dn.0.len() + 1 => {
+                    Some((subtree.to_string(), Scope::Base))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match bases.is_empty() {
+            true => Err(format!("\"{}\" is outside the subtrees of the key", base)),
+            false => Ok(bases),
+        }
+    }
+
+    /// Checks that every DN a command touches lies within the subtrees of the key.
+    pub fn check_command(&self, command: &QueryCommand) -> Result<(), String> {
+        if self.subtrees.is_empty() {
+            return Ok(());
+        }
+        match command {
+            QueryCommand::Bind(_) | QueryCommand::Unbind(_) | QueryCommand::WhoAmI(_) => Ok(()),
+            QueryCommand::Search(cmd) => self.search_bases(&cmd.base, cmd.scope).map(|_| ()),
+            QueryCommand::Add(cmd) => self.check_dn(&cmd.dn),
+            QueryCommand::Compare(cmd) => self.check_dn(&cmd.dn),
+            QueryCommand::Delete(cmd) => self.check_dn(&cmd.dn),
+            QueryCommand::Modify(cmd) => self.check_dn(&cmd.dn),
+            QueryCommand::ModifyDn(cmd) => {
+                self.check_dn(&cmd.dn)?;
+                if let Some(superior) = &cmd.new_superior {
+                    self.check_dn(superior)?;
+                }
+                self.check_dn(&cmd.new_dn())
+            }
+            QueryCommand::Ensure(cmd) => self.check_dn(&cmd.dn),
+            QueryCommand::Ldif(cmd) => cmd
+                .records
+                .iter()
+                .try_for_each(|record| self.check_command(record)),
+            // Without a user identity the password of the bound user is changed.
+            QueryCommand::PasswordModify(cmd) => match cmd.user_id {
+                Some(user_id) => self.check_dn(user_id.strip_prefix("dn:").unwrap_or(user_id)),
+                None => Ok(()),
+            },
+            QueryCommand::ExtendedOperation(_) => {
+                Err("Extended operations are not allowed for keys limited to subtrees".to_string())
+            }
+        }
+    }
+}
+
+/// Restrictions of the signing key with its attributes resolved through the
+/// subschema of the directory. A key limited in the attributes it may read is
+/// refused when the subschema cannot be read, rather than checked by name alone.
+pub async fn resolve(ldap: &mut Ldap) -> Result<Arc<KeyAccess>, LdapError> {
+    let access = context::access();
+    if !access.filters_attributes() {
+        return Ok(access);
+    }
+    match Subschema::read(ldap).await {
+        Ok(subschema) => Ok(Arc::new(access.with_subschema(&subschema))),
+        Err(err) => {
+            tracing::warn!("Failed to read subschema: {}", err);
+            Err(insufficient_access(
+                "Failed to read the schema to check the attributes of the key",
+            ))
+        }
+    }
+}
+
+/// Rejects a command reaching outside the subtrees of the signing key, before it is
+/// sent to the directory.
+pub fn authorize(command: &QueryCommand) -> Result<(), Response> {
+    match context::access().check_command(command) {
+        Ok(()) => Ok(()),
+        Err(message) => Err(Response::error(StatusCode::FORBIDDEN, message)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deny(attrs: &str) -> KeyAccess {
+        KeyAccess {
+            attrs_deny: attribute_list(attrs),
+            ..KeyAccess::default()
+        }
+    }
+
+    fn subschema() -> Subschema {
+        Subschema::parse(&[
+            "( 2.5.4.3 NAME ( 'cn' 'commonName' ) SUP name )".to_string(),
+            "( 2.5.4.35 NAME 'userPassword' EQUALITY octetStringMatch )".to_string(),
+        ])
+    }
+
+    fn is_denied(res: Result<(), LdapError>) -> bool {
+        matches!(res, Err(LdapError::LdapResult { result }) if result.rc == 50)
+    }
+
+    #[test]
+    fn reads_attributes_of_nested_filters() {
+        assert_eq!(
+            filter_attributes("(&(cn=a*)(!(|(mail=*)(uid>=b)))(sn:dn:=c))").unwrap(),
+            vec!["cn", "mail", "uid", "sn"]
+        );
+    }
+
+    #[test]
+    fn checks_bare_filters() {
+        let access = deny("userPassword");
+        assert!(is_denied(access.check_filter("userPassword=a*")));
+        assert!(is_denied(
+            access.check_filter("(&(cn=a)(!(userPassword=b)))")
+        ));
+        assert!(access.check_filter("cn=a*").is_ok());
+        assert!(access.check_filter("(|(cn=a)(mail=b*))").is_ok());
+    }
+
+    #[test]
+    fn rejects_extensible_matches_without_attribute() {
+        let access = deny("userPassword");
+        assert!(is_denied(access.check_filter("(:2.5.13.17:=x)")));
+        assert!(is_denied(access.check_filter("(:dn:caseExactMatch:=x)")));
+        assert!(access.check_filter("(cn:caseExactMatch:=x)").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        let access = deny("userPassword");
+        assert!(matches!(
+            access.check_filter("(userPassword=a"),
+            Err(LdapError::FilterParsing)
+        ));
+    }
+
+    #[test]
+    fn resolves_aliases_and_oids() {
+        let access = deny("userPassword").with_subschema(&subschema());
+        assert!(!access.can_read("2.5.4.35"));
+        assert!(!access.can_read("2.5.4.35;binary"));
+        assert!(is_denied(access.check_filter("(2.5.4.35=a*)")));
+        assert_eq!(
+            access.requested_attrs(&["2.5.4.35".to_string()]),
+            vec!["1.1"]
+        );
+
+        let access = KeyAccess {
+            attrs_allow: Some(attribute_list("cn")),
+            ..KeyAccess::default()
+        }
+        .with_subschema(&subschema());
+        assert!(access.can_read("commonName"));
+        assert!(access.can_read("2.5.4.3"));
+        assert!(!access.can_read("userPassword"));
+    }
+}
@@ -0,0 +1,203 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    audit,
+    context::{self, RequestContext},
+    policy::{Decision, Effect, Policy},
+};
+
+/// Approval under which a held batch is executed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Approval {
+    pub id: String,
+    pub approved_by: Option<String>,
+    /// Digest of the policy the batch was held under.
+    pub policy: String,
+    /// Rules that held the batch.
+    pub rules: Vec<String>,
+}
+
+impl Approval {
+    /// Whether the approval stands for a command held by `decision` under `policy`:
+    /// a rule that held the batch must still decide, and the policy must not have
+    /// been reloaded since.
+    pub fn covers(&self, policy: &Policy, decision: &Decision) -> bool {
+        self.policy == policy.digest
+            && decision
+                .rule
+                .as_ref()
+                .is_some_and(|rule| self.rules.contains(rule))
+    }
+}
+
+/// Batch of commands held until another key approves it.
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub id: String,
+    /// Identity and restrictions of the key that submitted the batch, which it is
+    /// executed with.
+    pub requester: RequestContext,
+    /// Signed `data` of the `/query` request.
+    pub data: String,
+    pub commands: Vec<Value>,
+    /// Digest of the policy the decisions were made under.
+    pub policy: String,
+    pub decisions: Vec<Decision>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl PendingRequest {
+    /// Decisions that held the batch; an approver must satisfy every one of them.
+    pub fn held(&self) -> impl Iterator<Item = &Decision> {
+        self.decisions
+            .iter()
+            .filter(|decision| decision.effect == Effect::Approve)
+    }
+
+    /// Approval of the batch by `approved_by`, limited to the rules that held it.
+    pub fn approve(&self, approved_by: Option<String>) -> Approval {
+        Approval {
+            id: self.id.clone(),
+            approved_by,
+            policy: self.policy.clone(),
+            rules: self
+                .held()
+                .filter_map(|decision| decision.rule.clone())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PendingResponse {
+    pub id: String,
+    pub requested_by: Option<String>,
+    pub requester_comment: Option<String>,
+    /// Submitted commands, with secrets redacted.
+    pub commands: Vec<Value>,
+    /// Policy decisions that held the batch.
+    pub decisions: Vec<Decision>,
+    #[schemars(with = "String")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<&PendingRequest> for PendingResponse {
+    fn from(value: &PendingRequest) -> Self {
+        PendingResponse {
+            id: value.id.clone(),
+            requested_by: value.requester.key_fingerprint.clone(),
+            requester_comment: value.requester.key_comment.clone(),
+            commands: value.commands.clone(),
+            decisions: value.decisions.clone(),
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+        }
+    }
+}
+
+/// Held batches by ID. Expired ones are dropped whenever the store is accessed.
+#[derive(Default)]
+pub struct ApprovalStore(Mutex<HashMap<String, PendingRequest>>);
+
+/// How long a held batch waits for approval: `APPROVAL_TTL_SECONDS`, default an hour.
+fn ttl() -> Duration {
+    let seconds = dotenv::var("APPROVAL_TTL_SECONDS")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(3600);
+    Duration::seconds(seconds)
+}
+
+impl ApprovalStore {
+    fn expire(&self) -> Vec<PendingRequest> {
+        let mut requests = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Utc::now();
+        let expired = requests
+            .values()
+            .filter(|request| request.expires_at <= now)
+            .map(|request| request.id.clone())
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|id| requests.remove(&id))
+            .collect()
+    }
+
+    /// Drops expired batches, recording each in the audit log without attributing
+    /// it to the current key.
+    async fn purge(&self) {
+        for request in self.expire() {
+            tracing::info!(approval = %request.id, "Approval request expired");
+            context::scope(
+                RequestContext::default(),
+                audit::record_approval("expired", &request),
+            )
+            .await;
+        }
+    }
+
+    /// Holds a batch for approval, returning what was stored.
+    pub async fn hold(
+        &self,
+        requester: RequestContext,
+        data: String,
+        commands: Vec<Value>,
+        policy: &Policy,
+        decisions: Vec<Decision>,
+    ) -> PendingRequest {
+        self.purge().await;
+
+        let created_at = Utc::now();
+        let request = PendingRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            requester: RequestContext {
+                request_id: None,
+                request_signature: None,
+                upstream: None,
+                bind_dn: None,
+                approval: None,
+                ..requester
+            },
+            data,
+            commands,
+            policy: policy.digest.clone(),
+            decisions,
+            created_at,
+            expires_at: created_at + ttl(),
+        };
+        self.0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(request.id.clone(), request.clone());
+
+        tracing::info!(approval = %request.id, "Approval requested");
+        audit::record_approval("requested", &request).await;
+        request
+    }
+
+    pub async fn get(&self, id: &str) -> Option<PendingRequest> {
+        self.purge().await;
+        self.0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(id)
+            .cloned()
+    }
+
+    /// Removes a batch to approve or reject it.
+    pub async fn take(&self, id: &str) -> Option<PendingRequest> {
+        self.purge().await;
+        self.0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(id)
+    }
+}
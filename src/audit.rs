@@ -0,0 +1,755 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    io::{BufRead, BufReader},
+    sync::OnceLock,
+};
+
+use chrono::{DateTime, Utc};
+use ldap3_serde::{Ldap, LdapError, LdapResult, Scope, SearchEntry};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    approvals::{Approval, PendingRequest},
+    context,
+    types::{
+        query::{QueryCommand, QueryResult},
+        secret::{is_sensitive, REDACTED},
+    },
+};
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttributeChange {
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditResult {
+    pub rc: Option<u32>,
+    pub matched: Option<String>,
+    pub message: String,
+}
+
+/// One line of the audit log. `hash` is the SHA-256 of the record without it, so
+/// each record commits to all previous ones through `prev_hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub request_id: Option<String>,
+    pub key_fingerprint: Option<String>,
+    pub upstream: Option<String>,
+    pub bind_dn: Option<String>,
+    pub dn: Option<String>,
+    pub command: Value,
+    pub result: AuditResult,
+    pub changes: BTreeMap<String, AttributeChange>,
+    /// Approval the command was executed under.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<Approval>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Debug)]
+pub enum AuditError {
+    Io(std::io::Error),
+    Malformed {
+        line: u64,
+        message: String,
+    },
+    Gap {
+        line: u64,
+        expected: u64,
+        found: u64,
+    },
+    BrokenChain {
+        line: u64,
+    },
+    HashMismatch {
+        line: u64,
+    },
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditError::Io(err) => write!(f, "Failed to access audit log: {}", err),
+            AuditError::Malformed { line, message } => {
+                write!(f, "Line {}: malformed record: {}", line, message)
+            }
+            AuditError::Gap {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {}: expected record {} but found {}",
+                line, expected, found
+            ),
+            AuditError::BrokenChain { line } => {
+                write!(f, "Line {}: previous hash does not match", line)
+            }
+            AuditError::HashMismatch { line } => {
+                write!(f, "Line {}: record was modified", line)
+            }
+        }
+    }
+}
+
+/// Hash of a record in its JSON form, ignoring its `hash` field. Going through
+/// `Value` sorts the keys, so it can be recomputed from a parsed line.
+fn record_hash(mut value: Value) -> String {
+    if let Some(object) = value.as_object_mut() {
+        object.remove("hash");
+    }
+    hex::encode(Sha256::digest(value.to_string().as_bytes()))
+}
+
+struct Chain {
+    file: File,
+    seq: u64,
+    hash: String,
+    /// Length of the log up to the end of the last record.
+    len: u64,
+    /// Set once a record could not be written, after which no more writes are
+    /// executed until the service is restarted.
+    failed: bool,
+}
+
+static AUDIT_LOG: OnceLock<Mutex<Chain>> = OnceLock::new();
+
+/// Checks the chain of records in `path`, returning the number of records and the
+/// hash of the last one.
+pub fn verify(path: &str) -> Result<(u64, String), AuditError> {
+    let file = match std::fs::File::open(path) {
+        Ok(val) => val,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((0, GENESIS_HASH.to_string()))
+        }
+        Err(err) => return Err(AuditError::Io(err)),
+    };
+
+    let mut seq = 0;
+    let mut hash = GENESIS_HASH.to_string();
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line_no = idx as u64 + 1;
+        let line = match line {
+            Ok(val) => val,
+            Err(err) => return Err(AuditError::Io(err)),
+        };
+
+        let value = match serde_json::from_str::<Value>(&line) {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(AuditError::Malformed {
+                    line: line_no,
+                    message: err.to_string(),
+                })
+            }
+        };
+        let record = match serde_json::from_value::<AuditRecord>(value.clone()) {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(AuditError::Malformed {
+                    line: line_no,
+                    message: err.to_string(),
+                })
+            }
+        };
+
+        if record.seq != seq + 1 {
+            return Err(AuditError::Gap {
+                line: line_no,
+                expected: seq + 1,
+                found: record.seq,
+            });
+        }
+        if record.prev_hash != hash {
+            return Err(AuditError::BrokenChain { line: line_no });
+        }
+        if record_hash(value) != record.hash {
+            return Err(AuditError::HashMismatch { line: line_no });
+        }
+
+        seq = record.seq;
+        hash = record.hash;
+    }
+
+    Ok((seq, hash))
+}
+
+/// Opens the log at `AUDIT_LOG_PATH` for appending after verifying it; auditing is
+/// disabled if it is unset.
+pub async fn open() -> Result<(), String> {
+    let path = match dotenv::var("AUDIT_LOG_PATH") {
+        Ok(val) => val,
+        Err(_) => return Ok(()),
+    };
+
+    let (seq, hash) = match verify(&path) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("{}: {}", path, err)),
+    };
+
+    let file = match tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to open {}: {}", path, err)),
+    };
+
+    let len = match file.metadata().await {
+        Ok(val) => val.len(),
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+
+    let _ = AUDIT_LOG.set(Mutex::new(Chain {
+        file,
+        seq,
+        hash,
+        len,
+        failed: false,
+    }));
+    tracing::info!("Appending audit records to {} after record {}", path, seq);
+    Ok(())
+}
+
+/// Replaces the values of sensitive attributes, wherever a command names them as an
+/// `attr` field or as the first element of an `[attr, values]` pair.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if object
+                .get("attr")
+                .and_then(|attr| attr.as_str())
+                .is_some_and(is_sensitive)
+            {
+                for key in ["values", "value"] {
+                    if let Some(val) = object.get_mut(key) {
+                        *val = Value::from(REDACTED);
+                    }
+                }
+            }
+            object.values_mut().for_each(redact);
+        }
+        Value::Array(items) => {
+            if let [Value::String(attr), values] = items.as_mut_slice() {
+                if is_sensitive(attr) {
+                    *values = Value::from(REDACTED);
+                    return;
+                }
+            }
+            items.iter_mut().for_each(redact);
+        }
+        _ => {}
+    }
+}
+
+/// The command as submitted, with secrets redacted.
+pub fn command_value(command: &QueryCommand) -> Value {
+    let value = match command {
+        QueryCommand::Add(cmd) => serde_json::to_value(cmd),
+        QueryCommand::Modify(cmd) => serde_json::to_value(cmd),
+        QueryCommand::ModifyDn(cmd) => serde_json::to_value(cmd),
+        QueryCommand::Delete(cmd) => serde_json::to_value(cmd),
+        QueryCommand::Ensure(cmd) => serde_json::to_value(cmd),
+        QueryCommand::PasswordModify(cmd) => serde_json::to_value(cmd),
+        _ => Ok(Value::Null),
+    };
+
+    let mut value = match value {
+        Ok(Value::Object(object)) => object,
+        _ => serde_json::Map::new(),
+    };
+    value.insert("type".to_string(), Value::from(command.name()));
+
+    let mut value = Value::Object(value);
+    redact(&mut value);
+    value
+}
+
+/// Entry changed by a write command, and its DN afterwards if it is renamed.
+pub struct AuditTarget {
+    dn: Option<String>,
+    new_dn: Option<String>,
+}
+
+/// Error returned for a write that is not, or could not be, recorded.
+fn unaudited(message: impl Into<String>) -> LdapError {
+    LdapError::LdapResult {
+        result: LdapResult {
+            rc: 80,
+            matched: String::new(),
+            text: message.into(),
+            refs: Vec::new(),
+            ctrls: Vec::new(),
+        },
+    }
+}
+
+async fn check_chain(chain: &Mutex<Chain>) -> Result<(), LdapError> {
+    match chain.lock().await.failed {
+        true => Err(unaudited(
+            "Writes are refused since an audit record could not be written",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Refuses to execute writes once the audit log could not be written to, so that
+/// none goes unrecorded.
+pub async fn check() -> Result<(), LdapError> {
+    match AUDIT_LOG.get() {
+        Some(chain) => check_chain(chain).await,
+        None => Ok(()),
+    }
+}
+
+/// Returns what a command writes to if auditing is enabled and it is a write.
+pub fn target(command: &QueryCommand) -> Option<AuditTarget> {
+    AUDIT_LOG.get()?;
+
+    let (dn, new_dn) = match command {
+        QueryCommand::Add(cmd) => (Some(cmd.dn.clone()), None),
+        QueryCommand::Modify(cmd) => (Some(cmd.dn.clone()), None),
+        QueryCommand::Delete(cmd) => (Some(cmd.dn.clone()), None),
+        QueryCommand::Ensure(cmd) => (Some(cmd.dn.clone()), None),
+        QueryCommand::ModifyDn(cmd) => (Some(cmd.dn.clone()), Some(cmd.new_dn())),
+        QueryCommand::PasswordModify(cmd) => (cmd.user_id.map(|val| val.to_string()), None),
+        _ => return None,
+    };
+    Some(AuditTarget { dn, new_dn })
+}
+
+type Snapshot = BTreeMap<String, BTreeSet<String>>;
+
+/// Reads the user attributes of the target, keyed by lowercase name. Binary values
+/// are kept as their SHA-256 digest.
+pub async fn snapshot(ldap: &mut Ldap, target: &AuditTarget, after: bool) -> Snapshot {
+    let dn = match (after, &target.new_dn, &target.dn) {
+        (true, Some(dn), _) | (_, _, Some(dn)) => dn,
+        _ => return Snapshot::new(),
+    };
+
+    let entries = match ldap
+        .search(dn, Scope::Base, "(objectClass=*)", vec!["*"])
+        .await
+        .and_then(|res| res.success())
+    {
+        Ok((entries, _)) => entries,
+        Err(_) => return Snapshot::new(),
+    };
+
+    let entry = match entries.into_iter().next() {
+        Some(val) => SearchEntry::construct(val),
+        None => return Snapshot::new(),
+    };
+
+    let binary = entry.bin_attrs.into_iter().map(|(attr, values)| {
+        let values = values
+            .iter()
+            .map(|val| format!("sha256:{}", hex::encode(Sha256::digest(val))))
+            .collect();
+        (attr, values)
+    });
+    entry
+        .attrs
+        .into_iter()
+        .chain(binary)
+        .map(|(attr, values)| (attr.to_lowercase(), values.into_iter().collect()))
+        .collect()
+}
+
+fn diff(before: Snapshot, after: Snapshot) -> BTreeMap<String, AttributeChange> {
+    let empty = BTreeSet::new();
+    let attrs = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+    attrs
+        .into_iter()
+        .filter_map(|attr| {
+            let old = before.get(attr).unwrap_or(&empty);
+            let new = after.get(attr).unwrap_or(&empty);
+            match old == new {
+                true => None,
+                // Only whether a secret changed is recorded, never its value.
+                false if is_sensitive(attr) => Some((
+                    attr.clone(),
+                    AttributeChange {
+                        before: old.iter().take(1).map(|_| REDACTED.to_string()).collect(),
+                        after: new.iter().take(1).map(|_| REDACTED.to_string()).collect(),
+                    },
+                )),
+                false => Some((
+                    attr.clone(),
+                    AttributeChange {
+                        before: old.iter().cloned().collect(),
+                        after: new.iter().cloned().collect(),
+                    },
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Appends a record of an executed write, with the attribute diff between the
+/// snapshots taken before and after it. Fails if the record could not be written.
+pub async fn record(
+    command: &QueryCommand<'_>,
+    target: AuditTarget,
+    before: Snapshot,
+    after: Snapshot,
+    res: &Result<Option<QueryResult>, LdapError>,
+) -> Result<(), LdapError> {
+    let chain = match AUDIT_LOG.get() {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+
+    let result = match res {
+        Ok(Some(QueryResult::Ensure(ensure))) if ensure.result.is_none() => return Ok(()),
+        Ok(val) => match val.as_ref().and_then(|val| val.ldap_result()) {
+            Some(res) => AuditResult {
+                rc: Some(res.rc),
+                matched: Some(res.matched.clone()),
+                message: res.text.clone(),
+            },
+            None => AuditResult {
+                rc: None,
+                matched: None,
+                message: String::new(),
+            },
+        },
+        Err(LdapError::LdapResult { result }) => AuditResult {
+            rc: Some(result.rc),
+            matched: Some(result.matched.clone()),
+            message: result.text.clone(),
+        },
+        Err(err) => AuditResult {
+            rc: None,
+            matched: None,
+            message: err.to_string(),
+        },
+    };
+
+    let context = context::current();
+    append(
+        chain,
+        AuditRecord {
+            seq: 0,
+            timestamp: Utc::now(),
+            request_id: context.request_id,
+            key_fingerprint: context.key_fingerprint,
+            upstream: context.upstream,
+            bind_dn: context.bind_dn,
+            dn: target.dn,
+            command: command_value(command),
+            result,
+            changes: diff(before, after),
+            approval: context.approval,
+            prev_hash: String::new(),
+            hash: String::new(),
+        },
+    )
+    .await
+}
+
+/// Appends a record of a held batch being requested, approved, rejected or expired,
+/// attributed to the key of the current request.
+pub async fn record_approval(event: &str, request: &PendingRequest) {
+    let chain = match AUDIT_LOG.get() {
+        Some(val) => val,
+        None => return,
+    };
+
+    let context = context::current();
+    let command = serde_json::json!({
+        "type": "approval",
+        "event": event,
+        "id": request.id,
+        "requested_by": request.requester.key_fingerprint,
+        "commands": request.commands,
+        "expires_at": request.expires_at,
+    });
+    // A failure is logged, and refuses every later write.
+    let _ = append(
+        chain,
+        AuditRecord {
+            seq: 0,
+            timestamp: Utc::now(),
+            request_id: context.request_id,
+            key_fingerprint: context.key_fingerprint,
+            upstream: None,
+            bind_dn: None,
+            dn: None,
+            command,
+            result: AuditResult {
+                rc: None,
+                matched: None,
+                message: String::new(),
+            },
+            changes: BTreeMap::new(),
+            approval: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        },
+    )
+    .await;
+}
+
+/// Chains `record` to the previous one and writes it. A record that cannot be
+/// written is cut off again, so the log stays verifiable, and fails the chain.
+async fn append(chain: &Mutex<Chain>, mut record: AuditRecord) -> Result<(), LdapError> {
+    let mut chain = chain.lock().await;
+    if chain.failed {
+        return Err(unaudited(
+            "Writes are refused since an audit record could not be written",
+        ));
+    }
+    record.seq = chain.seq + 1;
+    record.prev_hash = chain.hash.clone();
+
+    let value = match serde_json::to_value(&record) {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Failed to serialize audit record: {:?}", err);
+            return Err(unaudited("Failed to serialize audit record"));
+        }
+    };
+    record.hash = record_hash(value);
+
+    let line = match serde_json::to_string(&record) {
+        Ok(val) => val + "\n",
+        Err(err) => {
+            tracing::error!("Failed to serialize audit record: {:?}", err);
+            return Err(unaudited("Failed to serialize audit record"));
+        }
+    };
+
+    // Errors of the write itself only surface once it is flushed.
+    let written = match chain.file.write_all(line.as_bytes()).await {
+        Ok(()) => match chain.file.flush().await {
+            Ok(()) => chain.file.sync_data().await,
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+    match written {
+        Ok(()) => {
+            chain.seq = record.seq;
+            chain.hash = record.hash;
+            chain.len += line.len() as u64;
+            Ok(())
+        }
+        Err(err) => {
+            tracing::error!("Failed to write audit record {}: {:?}", record.seq, err);
+            chain.failed = true;
+            // A partial line would keep the log from being verified on restart.
+            let len = chain.len;
+            if let Err(err) = chain.file.set_len(len).await {
+                tracing::error!("Failed to truncate audit log to {} bytes: {:?}", len, err);
+            }
+            Err(unaudited(format!(
+                "Failed to write audit record {}",
+                record.seq
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serialized records chained as `append` writes them.
+    fn chain(len: u64) -> Vec<String> {
+        let mut hash = GENESIS_HASH.to_string();
+        (1..=len)
+            .map(|seq| {
+                let mut record = AuditRecord {
+                    seq,
+                    timestamp: Utc::now(),
+                    request_id: Some(format!("request-{}", seq)),
+                    key_fingerprint: None,
+                    upstream: None,
+                    bind_dn: None,
+                    dn: Some(format!("cn=user{},dc=example,dc=com", seq)),
+                    command: serde_json::json!({ "type": "delete" }),
+                    result: AuditResult {
+                        rc: Some(0),
+                        matched: None,
+                        message: String::new(),
+                    },
+                    changes: BTreeMap::new(),
+                    approval: None,
+                    prev_hash: hash.clone(),
+                    hash: String::new(),
+                };
+                record.hash = record_hash(serde_json::to_value(&record).unwrap());
+                hash = record.hash.clone();
+                serde_json::to_string(&record).unwrap()
+            })
+            .collect()
+    }
+
+    fn verify_lines(name: &str, lines: &[String]) -> Result<(u64, String), AuditError> {
+        let path = std::env::temp_dir().join(format!(
+            "ldap-rest-audit-{}-{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let res = verify(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        res
+    }
+
+    fn last_hash(lines: &[String]) -> String {
+        let record: AuditRecord = serde_json::from_str(lines.last().unwrap()).unwrap();
+        record.hash
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "ldap-rest-audit-{}-{}.jsonl",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn record() -> AuditRecord {
+        serde_json::from_str(&chain(1)[0]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn appends_records_to_chain() {
+        let path = temp_path("append");
+        let lines = chain(2);
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let (seq, hash) = verify(path.to_str().unwrap()).unwrap();
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await
+            .unwrap();
+        let len = file.metadata().await.unwrap().len();
+        let chain = Mutex::new(Chain {
+            file,
+            seq,
+            hash,
+            len,
+            failed: false,
+        });
+
+        append(&chain, record()).await.unwrap();
+        assert!(check_chain(&chain).await.is_ok());
+        let res = verify(path.to_str().unwrap());
+        let written = std::fs::metadata(&path).unwrap().len();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(res.unwrap().0, 3);
+        assert_eq!(chain.lock().await.len, written);
+    }
+
+    #[tokio::test]
+    async fn refuses_writes_after_failed_record() {
+        let path = temp_path("failed");
+        let lines = chain(2);
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let (seq, hash) = verify(path.to_str().unwrap()).unwrap();
+        // Writing to a file opened only for reading fails.
+        let file = File::open(&path).await.unwrap();
+        let len = file.metadata().await.unwrap().len();
+        let chain = Mutex::new(Chain {
+            file,
+            seq,
+            hash,
+            len,
+            failed: false,
+        });
+
+        assert!(append(&chain, record()).await.is_err());
+        assert!(check_chain(&chain).await.is_err());
+        assert!(append(&chain, record()).await.is_err());
+        let res = verify(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(res.unwrap().0, 2);
+    }
+
+    #[test]
+    fn accepts_intact_chain() {
+        let lines = chain(3);
+        let (seq, hash) = verify_lines("intact", &lines).unwrap();
+        assert_eq!(seq, 3);
+        assert_eq!(hash, last_hash(&lines));
+    }
+
+    #[test]
+    fn accepts_missing_log() {
+        let (seq, hash) = verify("/nonexistent/ldap-rest-audit.jsonl").unwrap();
+        assert_eq!(seq, 0);
+        assert_eq!(hash, GENESIS_HASH);
+    }
+
+    #[test]
+    fn detects_edited_record() {
+        let mut lines = chain(3);
+        lines[1] = lines[1].replace("cn=user2", "cn=admin");
+        assert!(matches!(
+            verify_lines("edited", &lines),
+            Err(AuditError::HashMismatch { line: 2 })
+        ));
+    }
+
+    #[test]
+    fn detects_rehashed_record() {
+        let mut lines = chain(3);
+        let mut value = serde_json::from_str::<Value>(&lines[1]).unwrap();
+        value["dn"] = Value::from("cn=admin,dc=example,dc=com");
+        value["hash"] = Value::from(record_hash(value.clone()));
+        lines[1] = value.to_string();
+        assert!(matches!(
+            verify_lines("rehashed", &lines),
+            Err(AuditError::BrokenChain { line: 3 })
+        ));
+    }
+
+    #[test]
+    fn detects_removed_and_reordered_records() {
+        let mut lines = chain(3);
+        lines.remove(1);
+        assert!(matches!(
+            verify_lines("removed", &lines),
+            Err(AuditError::Gap {
+                line: 2,
+                expected: 2,
+                found: 3,
+            })
+        ));
+
+        let mut lines = chain(3);
+        lines.swap(1, 2);
+        assert!(matches!(
+            verify_lines("reordered", &lines),
+            Err(AuditError::Gap { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn detects_truncated_line() {
+        let mut lines = chain(2);
+        let len = lines[1].len();
+        lines[1].truncate(len / 2);
+        assert!(matches!(
+            verify_lines("truncated", &lines),
+            Err(AuditError::Malformed { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,718 @@
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{request::Parts, HeaderMap, Method, StatusCode, Uri},
+};
+use base64::Engine;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use ipnet::IpNet;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use ssh_key::{
+    authorized_keys::Entry, certificate::CertType, Algorithm, Certificate, HashAlg, PublicKey,
+    SshSig,
+};
+
+use crate::{
+    access::KeyAccess,
+    context::{self, Credential},
+    metrics::METRICS,
+    types::routes::Response,
+    AppState,
+};
+
+/// Version of the signed message. Version 1, `{"data", "timestamp", "public_key"}`, was
+/// signed by `/query` clients before the endpoint, audience and digest were covered.
+pub const SIGNATURE_VERSION: u8 = 2;
+
+/// Message signed by clients. Besides the key and time, it binds the signature to the
+/// endpoint (`method`, and `path` with the query string), the deployment receiving it
+/// (`audience`) and the signed content (`digest`), so it cannot be replayed elsewhere.
+#[derive(Serialize)]
+pub struct RequestSignatureData {
+    pub version: u8,
+    pub method: String,
+    pub path: String,
+    pub audience: String,
+    /// Digest of the signed content, as in a `Content-Digest` header: `sha-256=:<base64>:`.
+    pub digest: String,
+    pub timestamp: i64,
+    pub public_key: String,
+}
+
+/// Message signed by `/query` clients in version 1.
+#[derive(Serialize)]
+struct LegacySignatureData<'a> {
+    data: &'a str,
+    timestamp: i64,
+    public_key: &'a str,
+}
+
+/// Whether version 1 messages are still accepted, while `ACCEPT_V1_SIGNATURES` is
+/// `true`, so existing `/query` clients can move to version 2.
+fn accepts_legacy_signatures() -> bool {
+    dotenv::var("ACCEPT_V1_SIGNATURES").is_ok_and(|val| val == "true")
+}
+
+pub fn content_digest(content: &[u8]) -> String {
+    format!(
+        "sha-256=:{}:",
+        base64::engine::general_purpose::STANDARD.encode(Sha256::digest(content))
+    )
+}
+
+/// Audience signatures must name, read from `SIGNATURE_AUDIENCE` at startup.
+static AUDIENCE: OnceLock<String> = OnceLock::new();
+
+/// Reads `SIGNATURE_AUDIENCE`, which must be set. The audience is never taken from the
+/// request: its `Host` header is chosen by whoever sends it, so a signature made for
+/// another deployment could name this one.
+pub fn load_audience() -> Result<(), String> {
+    match dotenv::var("SIGNATURE_AUDIENCE") {
+        Ok(val) if !val.trim().is_empty() => {
+            let _ = AUDIENCE.set(val);
+            Ok(())
+        }
+        _ => Err("SIGNATURE_AUDIENCE is not set".to_string()),
+    }
+}
+
+/// Endpoint and deployment a request was sent to, which its signature must cover.
+pub struct SignatureTarget {
+    pub method: String,
+    pub path: String,
+    pub audience: String,
+}
+
+impl SignatureTarget {
+    fn new(method: &Method, uri: &Uri) -> Self {
+        SignatureTarget {
+            method: method.to_string(),
+            path: uri
+                .path_and_query()
+                .map(|val| val.as_str().to_string())
+                .unwrap_or_else(|| uri.path().to_string()),
+            audience: AUDIENCE.get().cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Serialized message `public_key` must have signed for `content` sent to this
+    /// target at `timestamp`, in the given `version` of the format.
+    pub fn message(
+        &self,
+        version: u8,
+        content: &[u8],
+        timestamp: i64,
+        public_key: &str,
+    ) -> Result<String, Response> {
+        let res = match version {
+            SIGNATURE_VERSION => serde_json::to_string(&RequestSignatureData {
+                version,
+                method: self.method.clone(),
+                path: self.path.clone(),
+                audience: self.audience.clone(),
+                digest: content_digest(content),
+                timestamp,
+                public_key: public_key.to_string(),
+            }),
+            1 if accepts_legacy_signatures() => {
+                tracing::warn!("Accepting a deprecated version 1 signature");
+                serde_json::to_string(&LegacySignatureData {
+                    data: &String::from_utf8_lossy(content),
+                    timestamp,
+                    public_key,
+                })
+            }
+            1 => {
+                return Err(reject(
+                    "unsupported_version",
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Version 1 signatures are no longer accepted, sign version {}",
+                        SIGNATURE_VERSION
+                    ),
+                ))
+            }
+            _ => {
+                return Err(reject(
+                    "unsupported_version",
+                    StatusCode::BAD_REQUEST,
+                    format!("Unsupported signature version {}", version),
+                ))
+            }
+        };
+        match res {
+            Ok(val) => Ok(val),
+            Err(err) => Err(Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to serialize payload: {:?}", err),
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SignatureTarget {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(SignatureTarget::new(&parts.method, &parts.uri))
+    }
+}
+
+/// Rejects a request, counting the failure under `reason`.
+pub fn reject(reason: &str, status: StatusCode, message: impl Into<String>) -> Response {
+    METRICS.auth_failures.with_label_values(&[reason]).inc();
+    Response::error(status, message)
+}
+
+/// Accepts timestamps up to five minutes old, and up to a minute ahead to allow for
+/// clock skew, so a signature cannot be made to stay valid for longer.
+pub fn check_timestamp(timestamp: i64) -> Result<(), Response> {
+    let time_request = match DateTime::from_timestamp(timestamp, 0) {
+        Some(val) => val,
+        None => {
+            return Err(reject(
+                "invalid_timestamp",
+                StatusCode::BAD_REQUEST,
+                "Invalid timestamp",
+            ))
+        }
+    };
+
+    let age = Utc::now() - time_request;
+    if age > chrono::Duration::minutes(5) {
+        return Err(reject(
+            "expired_timestamp",
+            StatusCode::BAD_REQUEST,
+            "Timestamp is too old",
+        ));
+    }
+    if age < -chrono::Duration::minutes(1) {
+        return Err(reject(
+            "future_timestamp",
+            StatusCode::BAD_REQUEST,
+            "Timestamp is in the future",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether an authorized_keys entry trusts a CA to sign certificates rather than a key.
+fn is_cert_authority(entry: &Entry) -> bool {
+    entry
+        .config_opts()
+        .iter()
+        .any(|option| option == "cert-authority")
+}
+
+/// Principals an authorized CA may issue certificates for, from its `principals`
+/// option; any if it has none.
+fn allowed_principals(entry: &Entry) -> Option<Vec<String>> {
+    entry.config_opts().iter().find_map(|option| {
+        option.strip_prefix("principals=").map(|value| {
+            value
+                .trim_matches('"')
+                .split(',')
+                .map(|principal| principal.trim().to_string())
+                .collect()
+        })
+    })
+}
+
+/// Parses the `expiry-time` of an authorized_keys entry, `YYYYMMDD[HHMM[SS]]` in local
+/// time or, with a `Z` suffix, in UTC.
+fn parse_expiry_time(value: &str) -> Option<DateTime<Utc>> {
+    let (digits, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(val) => (val, true),
+        None => (value, false),
+    };
+    let time = match digits.len() {
+        8 => NaiveDate::parse_from_str(digits, "%Y%m%d")
+            .ok()?
+            .and_hms_opt(0, 0, 0)?,
+        12 => NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M").ok()?,
+        14 => NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M%S").ok()?,
+        _ => return None,
+    };
+    match utc {
+        true => Some(time.and_utc()),
+        false => time
+            .and_local_timezone(Local)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+fn check_expiry(entry: &Entry) -> Result<(), Response> {
+    let value = match entry
+        .config_opts()
+        .iter()
+        .find_map(|option| option.strip_prefix("expiry-time="))
+    {
+        Some(val) => val.trim_matches('"'),
+        None => return Ok(()),
+    };
+
+    let expiry = match parse_expiry_time(value) {
+        Some(val) => val,
+        None => {
+            tracing::error!("Invalid expiry-time of authorized key: {}", value);
+            return Err(reject(
+                "invalid_key_options",
+                StatusCode::FORBIDDEN,
+                "Forbidden",
+            ));
+        }
+    };
+    if Utc::now() >= expiry {
+        return Err(reject(
+            "expired_key",
+            StatusCode::UNAUTHORIZED,
+            "Key has expired",
+        ));
+    }
+    Ok(())
+}
+
+/// Authorizes a request as the key or certificate of `entry`, recording who made it.
+fn set_identity(
+    entry: &Entry,
+    credential: Credential,
+    fingerprint: String,
+    comment: String,
+    principals: Vec<String>,
+) -> Result<(), Response> {
+    check_expiry(entry)?;
+    let access = match KeyAccess::from_options(entry.config_opts()) {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Invalid options of authorized key: {}", err);
+            return Err(reject(
+                "invalid_key_options",
+                StatusCode::FORBIDDEN,
+                "Forbidden",
+            ));
+        }
+    };
+    let span = tracing::Span::current();
+    span.record("key_fingerprint", &fingerprint);
+    span.record("key_comment", &comment);
+    context::set_key(credential, fingerprint, comment, access);
+    context::set_principals(principals);
+    Ok(())
+}
+
+/// Whether the client address is in the comma-separated `source-address` list of a
+/// certificate.
+fn check_source_address(addresses: &str) -> Result<(), Response> {
+    let client_addr = match context::current().client_addr {
+        Some(val) => val,
+        None => {
+            return Err(reject(
+                "source_address_mismatch",
+                StatusCode::UNAUTHORIZED,
+                "Certificate is not valid from this address",
+            ))
+        }
+    };
+
+    for address in addresses.split(',') {
+        let network = match address.trim().parse::<IpNet>() {
+            Ok(val) => val,
+            Err(_) => match address.trim().parse::<IpAddr>() {
+                Ok(val) => IpNet::from(val),
+                Err(_) => {
+                    return Err(reject(
+                        "invalid_certificate",
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid source-address {}", address),
+                    ))
+                }
+            },
+        };
+        if network.contains(&client_addr) {
+            return Ok(());
+        }
+    }
+
+    Err(reject(
+        "source_address_mismatch",
+        StatusCode::UNAUTHORIZED,
+        "Certificate is not valid from this address",
+    ))
+}
+
+/// Checks an OpenSSH user certificate against the `cert-authority` entries of the
+/// authorized keys, returning the key it certifies. Its principals are the identity
+/// of the request.
+fn authorize_certificate(state: &AppState, certificate: &str) -> Result<PublicKey, Response> {
+    let certificate = match Certificate::from_openssh(certificate) {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(reject(
+                "invalid_certificate",
+                StatusCode::BAD_REQUEST,
+                format!("Invalid certificate: {:?}", err),
+            ))
+        }
+    };
+
+    if state
+        .revoked_keys
+        .current()
+        .is_certificate_revoked(&certificate)
+    {
+        return Err(reject(
+            "revoked_key",
+            StatusCode::UNAUTHORIZED,
+            "Certificate is revoked",
+        ));
+    }
+
+    if certificate.cert_type() != CertType::User {
+        return Err(reject(
+            "invalid_certificate",
+            StatusCode::BAD_REQUEST,
+            "Certificate is not a user certificate",
+        ));
+    }
+
+    if certificate.public_key().algorithm() != Algorithm::Ed25519 {
+        return Err(reject(
+            "invalid_public_key",
+            StatusCode::BAD_REQUEST,
+            "Invalid public key algorithm",
+        ));
+    }
+
+    let ca_fingerprint = certificate.signature_key().fingerprint(HashAlg::Sha256);
+    let entry = match state.authorized_keys.lock() {
+        Ok(val) => val
+            .iter()
+            .find(|entry| {
+                is_cert_authority(entry)
+                    && entry.public_key().fingerprint(HashAlg::Sha256) == ca_fingerprint
+            })
+            .cloned(),
+        Err(_) => {
+            tracing::error!("Failed to acquire lock on authorized keys");
+            return Err(Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error",
+            ));
+        }
+    };
+    let entry = match entry {
+        Some(val) => val,
+        None => {
+            return Err(reject(
+                "unauthorized_key",
+                StatusCode::UNAUTHORIZED,
+                "Unauthorized",
+            ))
+        }
+    };
+
+    let now = Utc::now().timestamp().max(0) as u64;
+    if now < certificate.valid_after() || now >= certificate.valid_before() {
+        return Err(reject(
+            "expired_certificate",
+            StatusCode::UNAUTHORIZED,
+            "Certificate is not valid at this time",
+        ));
+    }
+    if let Err(err) = certificate.validate_at(now, [&ca_fingerprint]) {
+        return Err(reject(
+            "invalid_certificate",
+            StatusCode::UNAUTHORIZED,
+            format!("Failed to validate certificate: {}", err),
+        ));
+    }
+
+    let allowed = allowed_principals(&entry);
+    let principals = certificate
+        .valid_principals()
+        .iter()
+        .filter(|principal| {
+            allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(principal))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if principals.is_empty() {
+        return Err(reject(
+            "unauthorized_principal",
+            StatusCode::UNAUTHORIZED,
+            "Certificate has no authorized principal",
+        ));
+    }
+
+    for (name, value) in certificate.critical_options().iter() {
+        match name.as_str() {
+            "source-address" => check_source_address(value)?,
+            _ => {
+                return Err(reject(
+                    "invalid_certificate",
+                    StatusCode::UNAUTHORIZED,
+                    format!("Unsupported critical option {}", name),
+                ))
+            }
+        }
+    }
+
+    let public_key = PublicKey::from(certificate.public_key().clone());
+    let fingerprint = public_key.fingerprint(HashAlg::Sha256).to_string();
+    set_identity(
+        &entry,
+        Credential::SshCertificate,
+        fingerprint,
+        principals[0].clone(),
+        principals,
+    )?;
+    Ok(public_key)
+}
+
+/// Parses a base64 Ed25519 public key, or an OpenSSH certificate for one, and checks
+/// it against the authorized keys.
+pub fn authorize_key(state: &AppState, public_key: &str) -> Result<PublicKey, Response> {
+    context::set_public_key(public_key.to_string());
+    let is_certificate = public_key
+        .split_whitespace()
+        .next()
+        .is_some_and(|algorithm| algorithm.ends_with("-cert-v01@openssh.com"));
+    if is_certificate {
+        return authorize_certificate(state, public_key);
+    }
+
+    let padded_key = format!("ssh-ed25519 {} request", public_key).to_string();
+    let public_key = match PublicKey::from_openssh(&padded_key) {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(reject(
+                "invalid_public_key",
+                StatusCode::BAD_REQUEST,
+                format!("Invalid public key: {:?}", err),
+            ))
+        }
+    };
+
+    if public_key.algorithm() != Algorithm::Ed25519 {
+        return Err(reject(
+            "invalid_public_key",
+            StatusCode::BAD_REQUEST,
+            "Invalid public key algorithm",
+        ));
+    }
+
+    if state
+        .revoked_keys
+        .current()
+        .is_key_revoked(public_key.key_data())
+    {
+        return Err(reject(
+            "revoked_key",
+            StatusCode::UNAUTHORIZED,
+            "Key is revoked",
+        ));
+    }
+
+    let entry = match state.authorized_keys.lock() {
+        Ok(val) => val
+            .iter()
+            .find(|entry| {
+                !is_cert_authority(entry) && entry.public_key().key_data() == public_key.key_data()
+            })
+            .cloned(),
+        Err(_) => {
+            tracing::error!("Failed to acquire lock on authorized keys");
+            return Err(Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error",
+            ));
+        }
+    };
+    let entry = match entry {
+        Some(val) => val,
+        None => {
+            return Err(reject(
+                "unauthorized_key",
+                StatusCode::UNAUTHORIZED,
+                "Unauthorized",
+            ));
+        }
+    };
+
+    let fingerprint = public_key.fingerprint(HashAlg::Sha256).to_string();
+    let comment = entry.public_key().comment().to_string();
+    set_identity(&entry, Credential::Key, fingerprint, comment, Vec::new())?;
+
+    Ok(public_key)
+}
+
+/// Checks again that the key or certificate the current request was signed with is
+/// still authorized, and neither revoked nor expired, before a session, change stream
+/// or approved batch acts on it long after the request was authenticated. Client
+/// certificates were checked during the TLS handshake and are not checked again.
+pub fn reauthorize(state: &AppState) -> Result<(), Response> {
+    match context::current().public_key {
+        Some(public_key) => authorize_key(state, &public_key).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Verifies an armor-less SSH signature over `message` in the configured namespace.
+/// The signature may be on one line, as it is in headers.
+pub fn verify_signature(
+    public_key: &PublicKey,
+    signature: &str,
+    message: &[u8],
+) -> Result<(), Response> {
+    // The armored form wraps the base64 at 70 columns.
+    let encoded = signature.split_whitespace().collect::<String>();
+    let lines = encoded
+        .as_bytes()
+        .chunks(70)
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let padded_signature = format!(
+        "-----BEGIN SSH SIGNATURE-----\n{}\n-----END SSH SIGNATURE-----",
+        lines
+    )
+    .to_string();
+    let signature = match padded_signature.parse::<SshSig>() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(reject(
+                "invalid_signature",
+                StatusCode::BAD_REQUEST,
+                format!("Invalid signature: {:?}", err),
+            ))
+        }
+    };
+
+    let namespace = dotenv::var("NAMESPACE").unwrap_or_else(|_| "ldap-rest".to_string());
+    match public_key.verify(namespace.as_str(), message, &signature) {
+        Ok(()) => {
+            context::set_request_signature(content_digest(encoded.as_bytes()));
+            Ok(())
+        }
+        Err(err) => Err(reject(
+            "signature_mismatch",
+            StatusCode::BAD_REQUEST,
+            format!("Failed to verify signature: {:?}", err),
+        )),
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, Response> {
+    match headers.get(name).map(|val| val.to_str()) {
+        Some(Ok(val)) => Ok(val),
+        Some(Err(_)) => Err(reject(
+            "invalid_header",
+            StatusCode::BAD_REQUEST,
+            format!("Invalid {} header", name),
+        )),
+        None => Err(reject(
+            "missing_header",
+            StatusCode::UNAUTHORIZED,
+            format!("Missing {} header", name),
+        )),
+    }
+}
+
+/// Extracts a request whose method, path, audience and body are signed by an authorized key,
+/// or which came with a client certificate mapped to an identity.
+pub struct SignedRequest {
+    pub body: Bytes,
+}
+
+#[async_trait]
+impl FromRequest<Arc<AppState>> for SignedRequest {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if context::current().certificate.is_some() {
+            return match Bytes::from_request(req, state).await {
+                Ok(body) => Ok(SignedRequest { body }),
+                Err(err) => Err(Response::error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to read request body: {}", err),
+                )),
+            };
+        }
+
+        let target = SignatureTarget::new(req.method(), req.uri());
+        let headers = req.headers().clone();
+        let public_key_str = header(&headers, "X-Public-Key")?;
+        let signature = header(&headers, "X-Signature")?;
+        let timestamp = match header(&headers, "X-Timestamp")?.parse::<i64>() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(reject(
+                    "invalid_timestamp",
+                    StatusCode::BAD_REQUEST,
+                    "Invalid timestamp",
+                ))
+            }
+        };
+
+        check_timestamp(timestamp)?;
+        let public_key = authorize_key(state, public_key_str)?;
+
+        let body = match Bytes::from_request(req, state).await {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(Response::error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to read request body: {}", err),
+                ))
+            }
+        };
+        let message = target.message(SIGNATURE_VERSION, &body, timestamp, public_key_str)?;
+        verify_signature(&public_key, signature, message.as_bytes())?;
+
+        Ok(SignedRequest { body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_timestamp_in_both_directions() {
+        let now = Utc::now().timestamp();
+        assert!(check_timestamp(now).is_ok());
+        assert!(check_timestamp(now - 4 * 60).is_ok());
+        assert!(check_timestamp(now + 30).is_ok());
+        assert!(check_timestamp(now - 6 * 60).is_err());
+        assert!(check_timestamp(now + 5 * 60).is_err());
+        assert!(check_timestamp(i64::MAX).is_err());
+    }
+
+    #[test]
+    fn versions_the_signed_message() {
+        let target = SignatureTarget {
+            method: "POST".to_string(),
+            path: "/query".to_string(),
+            audience: "ldap.example.com".to_string(),
+        };
+        let message = match target.message(SIGNATURE_VERSION, b"{}", 1700000000, "key") {
+            Ok(val) => val,
+            Err(_) => panic!("failed to build the message"),
+        };
+        assert!(message.starts_with("{\"version\":2,\"method\":\"POST\""));
+        assert!(target.message(3, b"{}", 1700000000, "key").is_err());
+    }
+}
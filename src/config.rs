@@ -2,18 +2,27 @@ extern crate rcgen;
 use axum_server::tls_rustls::RustlsConfig;
 use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_ECDSA_P384_SHA384};
 
-use std::{fmt::Debug, io, path::PathBuf};
+use rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+
+use std::{fmt::Debug, io, sync::Arc};
 
 use time::{OffsetDateTime, UtcOffset};
 
 use crate::utils::print_error;
 
+#[allow(clippy::enum_variant_names)]
 pub enum LoadCertError {
     FileLoadError(io::Error),
     CreateKeyPairError(rcgen::Error),
     CreateCertificateParamError(rcgen::Error),
     CreateCertificateError(rcgen::Error),
     CreateRustlsConfigError(io::Error),
+    ClientCaLoadError(io::Error),
+    ParseCertificateError(String),
+    CreateServerConfigError(rustls::Error),
 }
 
 impl Debug for LoadCertError {
@@ -34,6 +43,15 @@ impl Debug for LoadCertError {
             LoadCertError::CreateRustlsConfigError(err) => {
                 print_error(f, "Failed to create RustlsConfig", err)
             }
+            LoadCertError::ClientCaLoadError(err) => {
+                print_error(f, "Failed to load client CA file", err)
+            }
+            LoadCertError::ParseCertificateError(err) => {
+                print_error(f, "Failed to parse certificate or key", err)
+            }
+            LoadCertError::CreateServerConfigError(err) => {
+                print_error(f, "Failed to create TLS server config", err)
+            }
         }
     }
 }
@@ -55,19 +73,20 @@ pub async fn load_or_create_cert() -> Result<RustlsConfig, LoadCertError> {
         }
     };
 
-    if cert_path.is_some() && key_path.is_some() {
-        let config = match RustlsConfig::from_pem_file(
-            PathBuf::from(cert_path.unwrap()),
-            PathBuf::from(key_path.unwrap()),
-        )
-        .await
-        {
+    if let (Some(cert_path), Some(key_path)) = (cert_path, key_path) {
+        let cert = match tokio::fs::read(cert_path).await {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(LoadCertError::FileLoadError(err));
+            }
+        };
+        let key = match tokio::fs::read(key_path).await {
             Ok(val) => val,
             Err(err) => {
                 return Err(LoadCertError::FileLoadError(err));
             }
         };
-        return Ok(config);
+        return rustls_config(cert, key).await;
     }
 
     let key_pair = match KeyPair::generate_for(&PKCS_ECDSA_P384_SHA384) {
@@ -107,17 +126,96 @@ pub async fn load_or_create_cert() -> Result<RustlsConfig, LoadCertError> {
 
     tracing::info!("Generated self-signed certificate\n{}", cert.pem());
 
-    let config = match RustlsConfig::from_pem(
+    rustls_config(
         cert.pem().into_bytes(),
         key_pair.serialize_pem().into_bytes(),
     )
     .await
+}
+
+/// Builds the TLS config for a PEM certificate chain and key. When `CLIENT_CA_PATH`
+/// is set, clients are asked for a certificate issued by one of the CAs in that
+/// file; handshakes without one are refused only if `CLIENT_CERT_REQUIRED` is `true`.
+async fn rustls_config(cert: Vec<u8>, key: Vec<u8>) -> Result<RustlsConfig, LoadCertError> {
+    let ca_path = match dotenv::var("CLIENT_CA_PATH") {
+        Ok(val) => val,
+        Err(_) => {
+            return match RustlsConfig::from_pem(cert, key).await {
+                Ok(val) => Ok(val),
+                Err(err) => Err(LoadCertError::CreateRustlsConfigError(err)),
+            };
+        }
+    };
+
+    let ca = match tokio::fs::read(&ca_path).await {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(LoadCertError::ClientCaLoadError(err));
+        }
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in parse_certs(&ca)? {
+        if let Err(err) = roots.add(&cert) {
+            return Err(LoadCertError::ParseCertificateError(format!(
+                "Invalid client CA in {}: {}",
+                ca_path, err
+            )));
+        }
+    }
+    if roots.is_empty() {
+        return Err(LoadCertError::ParseCertificateError(format!(
+            "No certificates in {}",
+            ca_path
+        )));
+    }
+
+    let required = dotenv::var("CLIENT_CERT_REQUIRED").is_ok_and(|val| val == "true");
+    let verifier = match required {
+        true => AllowAnyAuthenticatedClient::new(roots).boxed(),
+        false => AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+    };
+
+    let key = match rustls_pemfile::private_key(&mut key.as_slice()) {
+        Ok(Some(val)) => PrivateKey(val.secret_der().to_vec()),
+        Ok(None) => {
+            return Err(LoadCertError::ParseCertificateError(
+                "No private key found".to_string(),
+            ))
+        }
+        Err(err) => {
+            return Err(LoadCertError::ParseCertificateError(err.to_string()));
+        }
+    };
+
+    let mut config = match ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(parse_certs(&cert)?, key)
     {
         Ok(val) => val,
         Err(err) => {
-            return Err(LoadCertError::CreateRustlsConfigError(err));
+            return Err(LoadCertError::CreateServerConfigError(err));
         }
     };
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    tracing::info!(
+        "Client certificates issued by {} are {}",
+        ca_path,
+        match required {
+            true => "required",
+            false => "accepted",
+        }
+    );
+    Ok(RustlsConfig::from_config(Arc::new(config)))
+}
 
-    Ok(config)
+fn parse_certs(pem: &[u8]) -> Result<Vec<Certificate>, LoadCertError> {
+    match rustls_pemfile::certs(&mut &pem[..]).collect::<Result<Vec<_>, _>>() {
+        Ok(val) => Ok(val
+            .into_iter()
+            .map(|cert| Certificate(cert.to_vec()))
+            .collect()),
+        Err(err) => Err(LoadCertError::ParseCertificateError(err.to_string())),
+    }
 }
@@ -0,0 +1,144 @@
+use std::{cell::RefCell, future::Future, net::IpAddr, sync::Arc};
+
+use crate::{access::KeyAccess, approvals::Approval};
+
+/// How the caller of a request authenticated, which its names are tagged with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Credential {
+    /// A key listed in the authorized keys.
+    #[default]
+    Key,
+    /// An OpenSSH certificate, whose key comment is its first principal.
+    SshCertificate,
+    /// A TLS client certificate mapped to an identity.
+    ClientCertificate,
+    /// A SCIM bearer token.
+    Token,
+}
+
+/// Kinds an identity can be tagged with.
+const IDENTITY_TAGS: [&str; 4] = ["key:", "principal:", "cert:", "token:"];
+
+/// Tags an identity named in configuration, where untagged names stand for the
+/// fingerprint or comment of an authorized key.
+pub fn tag_identity(identity: &str) -> String {
+    match IDENTITY_TAGS.iter().any(|tag| identity.starts_with(tag)) {
+        true => identity.to_string(),
+        false => format!("key:{}", identity),
+    }
+}
+
+/// Who is acting on behalf of the current request, collected as it is handled.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub request_id: Option<String>,
+    pub credential: Credential,
+    pub key_fingerprint: Option<String>,
+    pub key_comment: Option<String>,
+    /// Principals of the certificate the request was signed with, if any.
+    pub principals: Vec<String>,
+    /// Key or certificate the request was signed with, as sent, so it can be checked
+    /// again while a session, stream or held batch outlives the request.
+    pub public_key: Option<String>,
+    /// Digest of the verified request signature, which the response signature covers.
+    pub request_signature: Option<String>,
+    /// Address of the connection the request came in on.
+    pub client_addr: Option<IpAddr>,
+    pub upstream: Option<String>,
+    pub bind_dn: Option<String>,
+    /// Restrictions of the signing key; unrestricted until a key is authorized.
+    pub access: Arc<KeyAccess>,
+    /// Approval of the held batch being executed, if any.
+    pub approval: Option<Approval>,
+    /// Subject of the client certificate that authenticated the request in place of
+    /// a signature.
+    pub certificate: Option<String>,
+}
+
+impl RequestContext {
+    /// Names the caller is known by, tagged with their kind so that names of
+    /// different kinds never match: `key:` for the fingerprint and comment of a key,
+    /// `principal:` for certificate principals, and `cert:` or `token:` for the
+    /// fingerprint and identity of a client certificate or bearer token.
+    pub fn identities(&self) -> Vec<String> {
+        let tag = match self.credential {
+            Credential::Key | Credential::SshCertificate => "key",
+            Credential::ClientCertificate => "cert",
+            Credential::Token => "token",
+        };
+        let comment = match self.credential {
+            Credential::SshCertificate => None,
+            _ => self.key_comment.as_ref(),
+        };
+        [self.key_fingerprint.as_ref(), comment]
+            .into_iter()
+            .flatten()
+            .map(|id| format!("{}:{}", tag, id))
+            .chain(
+                self.principals
+                    .iter()
+                    .map(|principal| format!("principal:{}", principal)),
+            )
+            .collect()
+    }
+}
+
+tokio::task_local! {
+    static CONTEXT: RefCell<RequestContext>;
+}
+
+/// Runs `future` with its own request context.
+pub fn scope<F: Future>(context: RequestContext, future: F) -> impl Future<Output = F::Output> {
+    CONTEXT.scope(RefCell::new(context), future)
+}
+
+/// Request context of the current task, to carry it over into spawned ones.
+pub fn current() -> RequestContext {
+    CONTEXT
+        .try_with(|context| context.borrow().clone())
+        .unwrap_or_default()
+}
+
+/// Restrictions of the key signing the current request.
+pub fn access() -> Arc<KeyAccess> {
+    CONTEXT
+        .try_with(|context| context.borrow().access.clone())
+        .unwrap_or_default()
+}
+
+fn update(f: impl FnOnce(&mut RequestContext)) {
+    let _ = CONTEXT.try_with(|context| f(&mut context.borrow_mut()));
+}
+
+pub fn set_key(credential: Credential, fingerprint: String, comment: String, access: KeyAccess) {
+    update(|context| {
+        context.credential = credential;
+        context.key_fingerprint = Some(fingerprint);
+        context.key_comment = Some(comment);
+        context.access = Arc::new(access);
+    });
+}
+
+pub fn set_principals(principals: Vec<String>) {
+    update(|context| context.principals = principals);
+}
+
+pub fn set_certificate(subject: String) {
+    update(|context| context.certificate = Some(subject));
+}
+
+pub fn set_public_key(public_key: String) {
+    update(|context| context.public_key = Some(public_key));
+}
+
+pub fn set_request_signature(digest: String) {
+    update(|context| context.request_signature = Some(digest));
+}
+
+pub fn set_upstream(upstream: String) {
+    update(|context| context.upstream = Some(upstream));
+}
+
+pub fn set_bind_dn(dn: String) {
+    update(|context| context.bind_dn = Some(dn));
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeSet;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ldap3_serde::SearchEntry;
+
+/// Renders search entries as RFC 4180 CSV, one row per entry.
+///
+/// The first column is always the entry DN. If `columns` is not given, every
+/// attribute returned for any entry becomes a column. Multi-valued attributes are
+/// joined with `separator`, and binary values are base64-encoded. Fields a
+/// spreadsheet would take for a formula are prefixed with `'`.
+pub fn write(entries: &[SearchEntry], columns: Option<&[String]>, separator: &str) -> String {
+    let columns = match columns {
+        Some(val) => val.to_vec(),
+        None => entries
+            .iter()
+            .flat_map(|entry| entry.attrs.keys().chain(entry.bin_attrs.keys()))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+    };
+
+    let mut out = String::new();
+
+    let header = std::iter::once("dn")
+        .chain(columns.iter().map(|column| column.as_str()))
+        .map(escape)
+        .collect::<Vec<_>>();
+    out.push_str(&header.join(","));
+    out.push_str("\r\n");
+
+    for entry in entries.iter() {
+        let mut row = Vec::with_capacity(columns.len() + 1);
+        row.push(escape(&entry.dn));
+
+        for column in columns.iter() {
+            let mut values = entry
+                .attrs
+                .iter()
+                .filter(|(attr, _)| attr.eq_ignore_ascii_case(column))
+                .flat_map(|(_, values)| values.iter().cloned())
+                .collect::<Vec<_>>();
+            values.extend(
+                entry
+                    .bin_attrs
+                    .iter()
+                    .filter(|(attr, _)| attr.eq_ignore_ascii_case(column))
+                    .flat_map(|(_, values)| values.iter().map(|value| STANDARD.encode(value))),
+            );
+            row.push(escape(&values.join(separator)));
+        }
+
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+
+    out
+}
+
+fn escape(field: &str) -> String {
+    // Spreadsheets evaluate fields starting with these as formulas.
+    let field = match field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", field),
+        false => field.to_string(),
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_special_characters() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a,b"), "\"a,b\"");
+        assert_eq!(escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn neutralizes_formulas() {
+        assert_eq!(escape("=1+1"), "'=1+1");
+        assert_eq!(escape("+1"), "'+1");
+        assert_eq!(escape("-1"), "'-1");
+        assert_eq!(escape("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(escape("\tx"), "'\tx");
+        assert_eq!(escape("\rx"), "\"'\rx\"");
+        assert_eq!(
+            escape("=HYPERLINK(\"x\",\"y\")"),
+            "\"'=HYPERLINK(\"\"x\"\",\"\"y\"\")\""
+        );
+        assert_eq!(escape("a=1"), "a=1");
+    }
+}
@@ -0,0 +1,281 @@
+use std::fmt::Display;
+
+use ldap3_serde::dn_escape;
+
+#[derive(Debug)]
+pub struct DnError(pub String);
+
+impl Display for DnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid DN: {}", self.0)
+    }
+}
+
+/// Single `attr=value` assertion of an RDN, with the value unescaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ava {
+    pub attr: String,
+    pub value: String,
+}
+
+/// Relative distinguished name; multi-valued RDNs hold more than one assertion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rdn(pub Vec<Ava>);
+
+/// Distinguished name, with the leftmost (most specific) RDN first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dn(pub Vec<Rdn>);
+
+impl Ava {
+    /// Compares assuming case-insensitive matching, the rule for most naming attributes.
+    pub fn matches(&self, other: &Ava) -> bool {
+        self.attr.eq_ignore_ascii_case(&other.attr) && self.value.eq_ignore_ascii_case(&other.value)
+    }
+}
+
+impl Rdn {
+    pub fn matches(&self, other: &Rdn) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .all(|ava| other.0.iter().any(|other| ava.matches(other)))
+    }
+}
+
+impl Dn {
+    /// Whether this DN is `base` itself or lies below it.
+    pub fn is_within(&self, base: &Dn) -> bool {
+        self.0.len() >= base.0.len()
+            && self
+                .0
+                .iter()
+                .rev()
+                .zip(base.0.iter().rev())
+                .all(|(rdn, base)| rdn.matches(base))
+    }
+
+    /// DN of the parent entry, or `None` for the root.
+    pub fn parent(&self) -> Option<Dn> {
+        match self.0.is_empty() {
+            true => None,
+            false => Some(Dn(self.0[1..].to_vec())),
+        }
+    }
+}
+
+impl Display for Dn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rdns = self
+            .0
+            .iter()
+            .map(|rdn| {
+                rdn.0
+                    .iter()
+                    .map(|ava| format!("{}={}", ava.attr, dn_escape(ava.value.as_str())))
+                    .collect::<Vec<_>>()
+                    .join("+")
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", rdns.join(","))
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parses an RFC 4514 string representation of a distinguished name.
+///
+/// Spaces around separators and quoted values (RFC 2253) are tolerated. Values
+/// in `#hex` form are kept verbatim.
+pub fn parse(input: &str) -> Result<Dn, DnError> {
+    let bytes = input.as_bytes();
+    let mut pos = 0;
+    let mut rdns = Vec::new();
+    let mut avas = Vec::new();
+
+    let skip_spaces = |pos: &mut usize| {
+        while *pos < bytes.len() && bytes[*pos] == b' ' {
+            *pos += 1;
+        }
+    };
+
+    skip_spaces(&mut pos);
+    if pos == bytes.len() {
+        return Ok(Dn(rdns));
+    }
+
+    loop {
+        skip_spaces(&mut pos);
+        let start = pos;
+        while pos < bytes.len() && bytes[pos] != b'=' {
+            pos += 1;
+        }
+        if pos == bytes.len() {
+            return Err(DnError(format!("missing \"=\" in \"{}\"", input)));
+        }
+        let attr = input[start..pos].trim();
+        if attr.is_empty()
+            || !attr
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.')
+        {
+            return Err(DnError(format!("invalid attribute type \"{}\"", attr)));
+        }
+        pos += 1;
+        skip_spaces(&mut pos);
+
+        let mut value = Vec::new();
+        if pos < bytes.len() && bytes[pos] == b'"' {
+            pos += 1;
+            loop {
+                match bytes.get(pos) {
+                    Some(b'"') => {
+                        pos += 1;
+                        break;
+                    }
+                    Some(b'\\') if pos + 1 < bytes.len() => {
+                        value.push(bytes[pos + 1]);
+                        pos += 2;
+                    }
+                    Some(c) => {
+                        value.push(*c);
+                        pos += 1;
+                    }
+                    None => return Err(DnError("unterminated quoted value".to_string())),
+                }
+            }
+            skip_spaces(&mut pos);
+        } else {
+            // Length of `value` up to and including the last character that must be kept,
+            // so unescaped trailing spaces can be trimmed.
+            let mut significant = 0;
+            while pos < bytes.len() && !matches!(bytes[pos], b',' | b';' | b'+') {
+                if bytes[pos] == b'\\' {
+                    let hex = bytes
+                        .get(pos + 1)
+                        .and_then(|c| hex_value(*c))
+                        .zip(bytes.get(pos + 2).and_then(|c| hex_value(*c)));
+                    match (hex, bytes.get(pos + 1)) {
+                        (Some((hi, lo)), _) => {
+                            value.push(hi << 4 | lo);
+                            pos += 3;
+                        }
+                        (None, Some(c)) => {
+                            value.push(*c);
+                            pos += 2;
+                        }
+                        (None, None) => {
+                            return Err(DnError("dangling escape at end of DN".to_string()))
+                        }
+                    }
+                    significant = value.len();
+                    continue;
+                }
+                value.push(bytes[pos]);
+                if bytes[pos] != b' ' {
+                    significant = value.len();
+                }
+                pos += 1;
+            }
+            value.truncate(significant);
+        }
+
+        let value = match String::from_utf8(value) {
+            Ok(val) => val,
+            Err(_) => return Err(DnError(format!("value of \"{}\" is not UTF-8", attr))),
+        };
+        avas.push(Ava {
+            attr: attr.to_string(),
+            value,
+        });
+
+        match bytes.get(pos) {
+            Some(b'+') => {
+                pos += 1;
+            }
+            Some(b',') | Some(b';') => {
+                rdns.push(Rdn(std::mem::take(&mut avas)));
+                pos += 1;
+            }
+            None => {
+                rdns.push(Rdn(avas));
+                return Ok(Dn(rdns));
+            }
+            Some(c) => {
+                return Err(DnError(format!(
+                    "unexpected \"{}\" after value of \"{}\"",
+                    *c as char, attr
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ava(attr: &str, value: &str) -> Ava {
+        Ava {
+            attr: attr.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_escapes_and_multi_valued_rdns() {
+        let dn = parse("cn=Smith\\, John+uid=js , ou=a\\2Cb;dc=example").unwrap();
+        assert_eq!(
+            dn,
+            Dn(vec![
+                Rdn(vec![ava("cn", "Smith, John"), ava("uid", "js")]),
+                Rdn(vec![ava("ou", "a,b")]),
+                Rdn(vec![ava("dc", "example")]),
+            ])
+        );
+
+        assert_eq!(parse("cn=\"a, b\"").unwrap().0[0].0[0].value, "a, b");
+        assert_eq!(
+            parse("cn=trailing\\ ").unwrap().0[0].0[0].value,
+            "trailing "
+        );
+        assert_eq!(parse("cn=\\C3\\A9").unwrap().0[0].0[0].value, "é");
+        assert_eq!(parse("").unwrap(), Dn(vec![]));
+    }
+
+    #[test]
+    fn rejects_invalid_dns() {
+        for dn in ["cn", "c n=a", "cn=a\\", "cn=\"a", "cn=\"a\"b", "cn=\\FF"] {
+            assert!(parse(dn).is_err(), "{}", dn);
+        }
+    }
+
+    #[test]
+    fn escapes_on_display() {
+        let dn = parse("cn=Smith\\, John,dc=example").unwrap();
+        assert_eq!(dn.to_string(), "cn=Smith\\2c John,dc=example");
+        assert_eq!(parse(&dn.to_string()).unwrap(), dn);
+    }
+
+    #[test]
+    fn compares_subtrees_ignoring_case() {
+        let base = parse("ou=People,dc=Example").unwrap();
+        assert!(parse("uid=a,ou=people,dc=example")
+            .unwrap()
+            .is_within(&base));
+        assert!(base.is_within(&base));
+        assert!(!parse("ou=groups,dc=example").unwrap().is_within(&base));
+        assert!(!parse("dc=example").unwrap().is_within(&base));
+        assert_eq!(
+            parse("uid=a,ou=people").unwrap().parent(),
+            Some(parse("ou=people").unwrap())
+        );
+    }
+}
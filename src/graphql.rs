@@ -0,0 +1,506 @@
+use std::collections::{HashMap, HashSet};
+
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    Context, EmptySubscription, Enum, ErrorExtensions, InputObject, Object, Result, Schema,
+    SimpleObject,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+use ldap3_serde::{ldap_escape, Ldap, LdapError, LdapResult, Scope, SearchEntry, SearchResult};
+
+use crate::{
+    dn::{self, Dn, Rdn},
+    types::query::{
+        AddCommand, AddMod, Command, DeleteCommand, DeleteMod, Mod, ModifyCommand, QueryCommand,
+        QueryResult, ReplaceMod, SearchCommand,
+    },
+};
+
+pub type DirectorySchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Builds the schema, rejecting queries nested deeper than `GRAPHQL_MAX_DEPTH` (default
+/// 10) or more complex than `GRAPHQL_MAX_COMPLEXITY` (default 1000) before they run.
+pub fn schema() -> DirectorySchema {
+    let depth = dotenv::var("GRAPHQL_MAX_DEPTH")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(10);
+    let complexity = dotenv::var("GRAPHQL_MAX_COMPLEXITY")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(1000);
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(depth)
+        .limit_complexity(complexity)
+        .finish()
+}
+
+/// Searches a loader batch runs at once.
+const CONCURRENT_LOADS: usize = 8;
+
+/// Entries read by one search of a loader batch, which bounds the size of its filter.
+const ENTRIES_PER_SEARCH: usize = 50;
+
+/// Attributes read for every entry; `memberOf` is operational on most servers.
+fn entry_attrs() -> Vec<String> {
+    vec!["*".to_string(), "memberOf".to_string()]
+}
+
+fn ldap_error(err: LdapError) -> async_graphql::Error {
+    async_graphql::Error::new(format!("Failed to execute command: {:?}", err))
+}
+
+fn result_error(res: &LdapResult) -> async_graphql::Error {
+    async_graphql::Error::new(res.to_string()).extend_with(|_, e| e.set("rc", res.rc))
+}
+
+async fn execute(ldap: &Ldap, command: QueryCommand<'_>) -> Result<QueryResult> {
+    let mut ldap = ldap.clone();
+    match command.execute(&mut ldap).await {
+        Ok(Some(val)) => Ok(val),
+        Ok(None) => Err(async_graphql::Error::new("Unexpected result")),
+        Err(err) => Err(ldap_error(err)),
+    }
+}
+
+async fn search(ldap: &Ldap, base: String, scope: Scope, filter: String) -> Result<Vec<Entry>> {
+    let command = QueryCommand::Search(SearchCommand {
+        base,
+        scope,
+        filter,
+        attrs: entry_attrs(),
+        format: None,
+        page: None,
+    });
+
+    match execute(ldap, command).await? {
+        QueryResult::Search(SearchResult(entries, res)) => {
+            if res.rc != 0 {
+                return Err(result_error(&res));
+            }
+            Ok(entries
+                .into_iter()
+                .filter(|entry| !entry.is_ref())
+                .map(|entry| Entry(SearchEntry::construct(entry)))
+                .collect())
+        }
+        _ => Err(async_graphql::Error::new("Unexpected result")),
+    }
+}
+
+/// Checks the result of a write operation.
+async fn write(ldap: &Ldap, command: QueryCommand<'_>) -> Result<()> {
+    match execute(ldap, command).await? {
+        QueryResult::Common(res) if res.rc == 0 => Ok(()),
+        QueryResult::Common(res) => Err(result_error(&res)),
+        _ => Err(async_graphql::Error::new("Unexpected result")),
+    }
+}
+
+/// Whether a search failed because its base does not exist.
+fn is_no_such_object(err: &async_graphql::Error) -> bool {
+    err.extensions.as_ref().and_then(|e| e.get("rc")) == Some(&async_graphql::Value::from(32))
+}
+
+/// Filter matching the entries named by `rdns`, e.g. `(|(uid=a)(&(cn=b)(sn=c)))`.
+fn rdn_filter(rdns: &[&Rdn]) -> String {
+    let terms = rdns
+        .iter()
+        .map(|rdn| {
+            let avas = rdn
+                .0
+                .iter()
+                .map(|ava| format!("({}={})", ava.attr, ldap_escape(ava.value.as_str())))
+                .collect::<String>();
+            match rdn.0.len() {
+                1 => avas,
+                _ => format!("(&{})", avas),
+            }
+        })
+        .collect::<String>();
+    format!("(|{})", terms)
+}
+
+/// Reads entries by DN. Keys requested while resolving one level of a query are
+/// collected into one batch and deduplicated. DNs below the same parent are then read
+/// together, with a one-level search of the parent for their RDNs, up to
+/// `ENTRIES_PER_SEARCH` of them per search and `CONCURRENT_LOADS` searches at a time
+/// on the shared connection.
+pub struct EntryLoader {
+    pub ldap: Ldap,
+}
+
+impl EntryLoader {
+    /// Reads `dn` with a base search. A missing entry only means a dangling reference.
+    async fn read_one(ldap: Ldap, dn: String) -> Result<Vec<(String, Entry)>> {
+        match search(
+            &ldap,
+            dn.clone(),
+            Scope::Base,
+            "(objectClass=*)".to_string(),
+        )
+        .await
+        {
+            Ok(val) => Ok(val
+                .into_iter()
+                .take(1)
+                .map(|entry| (dn.clone(), entry))
+                .collect()),
+            Err(err) if is_no_such_object(&err) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads the children of `parent` named by `keys`, each given with its RDN.
+    ///
+    /// Keys restricted to subtrees, or denied the naming attributes, can be refused a
+    /// search of the parent while still allowed to read the entries, so any other
+    /// failure than a missing parent falls back to reading them one by one.
+    async fn read_children(
+        ldap: Ldap,
+        parent: String,
+        keys: Vec<(String, Rdn)>,
+    ) -> Result<Vec<(String, Entry)>> {
+        let rdns = keys.iter().map(|(_, rdn)| rdn).collect::<Vec<_>>();
+        let entries = match search(&ldap, parent, Scope::OneLevel, rdn_filter(&rdns)).await {
+            Ok(val) => val,
+            Err(err) if is_no_such_object(&err) => return Ok(Vec::new()),
+            Err(_) => {
+                let mut entries = Vec::with_capacity(keys.len());
+                for (dn, _) in keys {
+                    entries.extend(Self::read_one(ldap.clone(), dn).await?);
+                }
+                return Ok(entries);
+            }
+        };
+
+        let mut found = Vec::with_capacity(keys.len());
+        for entry in entries {
+            let rdn = match dn::parse(&entry.0.dn).map(|val| val.0.into_iter().next()) {
+                Ok(Some(val)) => val,
+                _ => continue,
+            };
+            for (dn, _) in keys.iter().filter(|(_, key)| key.matches(&rdn)) {
+                found.push((dn.clone(), entry.clone()));
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl Loader<String> for EntryLoader {
+    type Value = Entry;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Entry>> {
+        // Parents, keyed by their DN in lowercase, with the children requested.
+        let mut parents = HashMap::<String, (String, Vec<(String, Rdn)>)>::new();
+        let mut searches = Vec::<BoxFuture<'static, Result<Vec<(String, Entry)>>>>::new();
+
+        for key in keys {
+            let (rdn, parent) = match dn::parse(key) {
+                Ok(Dn(mut rdns)) if rdns.len() > 1 => (rdns.remove(0), Dn(rdns).to_string()),
+                // Entries at the root, and DNs the server may still make sense of.
+                _ => {
+                    searches.push(Self::read_one(self.ldap.clone(), key.clone()).boxed());
+                    continue;
+                }
+            };
+            parents
+                .entry(parent.to_lowercase())
+                .or_insert_with(|| (parent, Vec::new()))
+                .1
+                .push((key.clone(), rdn));
+        }
+
+        for (parent, children) in parents.into_values() {
+            for chunk in children.chunks(ENTRIES_PER_SEARCH) {
+                let ldap = self.ldap.clone();
+                searches.push(match chunk {
+                    [(dn, _)] => Self::read_one(ldap, dn.clone()).boxed(),
+                    _ => Self::read_children(ldap, parent.clone(), chunk.to_vec()).boxed(),
+                });
+            }
+        }
+
+        let mut entries = HashMap::with_capacity(keys.len());
+        let mut results = stream::iter(searches).buffer_unordered(CONCURRENT_LOADS);
+        while let Some(res) = results.next().await {
+            entries.extend(res?);
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Attribute {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Entry(SearchEntry);
+
+impl Entry {
+    /// Values of `name`, matched case-insensitively. Binary values are base64-encoded.
+    fn values(&self, name: &str) -> Vec<String> {
+        let text = self
+            .0
+            .attrs
+            .iter()
+            .filter(|(attr, _)| attr.eq_ignore_ascii_case(name))
+            .flat_map(|(_, values)| values.iter().cloned());
+        let binary = self
+            .0
+            .bin_attrs
+            .iter()
+            .filter(|(attr, _)| attr.eq_ignore_ascii_case(name))
+            .flat_map(|(_, values)| values.iter().map(|value| STANDARD.encode(value)));
+        text.chain(binary).collect()
+    }
+
+    async fn load(&self, ctx: &Context<'_>, attr: &str) -> Result<Vec<Entry>> {
+        let loader = ctx.data::<DataLoader<EntryLoader>>()?;
+        let dns = self.values(attr);
+        let mut entries = loader.load_many(dns.iter().cloned()).await?;
+        Ok(dns.iter().filter_map(|dn| entries.remove(dn)).collect())
+    }
+}
+
+/// A directory entry. DN-valued attributes can be traversed with `references`.
+#[Object]
+impl Entry {
+    async fn dn(&self) -> &str {
+        &self.0.dn
+    }
+
+    async fn attribute(&self, name: String) -> Vec<String> {
+        self.values(&name)
+    }
+
+    async fn attributes(&self) -> Vec<Attribute> {
+        let mut names = self
+            .0
+            .attrs
+            .keys()
+            .chain(self.0.bin_attrs.keys())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| Attribute {
+                name: name.clone(),
+                values: self.values(name),
+            })
+            .collect()
+    }
+
+    /// Entries named by the DN-valued attribute `attr`. Dangling references are skipped.
+    async fn references(&self, ctx: &Context<'_>, attr: String) -> Result<Vec<Entry>> {
+        self.load(ctx, &attr).await
+    }
+
+    async fn member(&self, ctx: &Context<'_>) -> Result<Vec<Entry>> {
+        self.load(ctx, "member").await
+    }
+
+    async fn member_of(&self, ctx: &Context<'_>) -> Result<Vec<Entry>> {
+        self.load(ctx, "memberOf").await
+    }
+
+    async fn owner(&self, ctx: &Context<'_>) -> Result<Vec<Entry>> {
+        self.load(ctx, "owner").await
+    }
+
+    async fn manager(&self, ctx: &Context<'_>) -> Result<Option<Entry>> {
+        Ok(self.load(ctx, "manager").await?.into_iter().next())
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+pub enum SearchScope {
+    Base,
+    OneLevel,
+    Subtree,
+}
+
+impl From<SearchScope> for Scope {
+    fn from(value: SearchScope) -> Self {
+        match value {
+            SearchScope::Base => Scope::Base,
+            SearchScope::OneLevel => Scope::OneLevel,
+            SearchScope::Subtree => Scope::Subtree,
+        }
+    }
+}
+
+/// Equality assertion, escaped before being added to the search filter.
+#[derive(InputObject)]
+pub struct Match {
+    pub name: String,
+    pub value: String,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn entry(&self, ctx: &Context<'_>, dn: String) -> Result<Option<Entry>> {
+        ctx.data::<DataLoader<EntryLoader>>()?.load_one(dn).await
+    }
+
+    /// Searches below `base`. `filter` is an RFC 4515 filter; `where` adds equality
+    /// assertions, all of which must hold.
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        base: String,
+        #[graphql(default_with = "SearchScope::Subtree")] scope: SearchScope,
+        filter: Option<String>,
+        #[graphql(name = "where", default)] matches: Vec<Match>,
+    ) -> Result<Vec<Entry>> {
+        let mut filters = Vec::with_capacity(matches.len() + 1);
+        for m in matches.iter() {
+            if m.name.is_empty()
+                || !m
+                    .name
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.' || c == b';')
+            {
+                return Err(async_graphql::Error::new(format!(
+                    "Invalid attribute name \"{}\"",
+                    m.name
+                )));
+            }
+            filters.push(format!("({}={})", m.name, ldap_escape(m.value.as_str())));
+        }
+        if let Some(filter) = filter {
+            filters.push(filter);
+        }
+
+        let filter = match filters.len() {
+            0 => "(objectClass=*)".to_string(),
+            1 => filters.remove(0),
+            _ => format!("(&{})", filters.concat()),
+        };
+
+        search(ctx.data::<Ldap>()?, base, scope.into(), filter).await
+    }
+}
+
+#[derive(InputObject)]
+pub struct AttributeInput {
+    pub name: String,
+    #[graphql(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Add,
+    Delete,
+    Replace,
+}
+
+#[derive(InputObject)]
+pub struct ChangeInput {
+    pub op: ChangeOp,
+    pub name: String,
+    /// Values to add, delete or replace with; deleting no values removes the attribute.
+    #[graphql(default)]
+    pub values: Vec<String>,
+}
+
+impl From<ChangeInput> for Mod {
+    fn from(value: ChangeInput) -> Self {
+        let attr = value.name;
+        let values = value.values.into_iter().collect::<HashSet<_>>();
+        match value.op {
+            ChangeOp::Add => Mod::Add(AddMod { attr, values }),
+            ChangeOp::Delete => Mod::Delete(DeleteMod { attr, values }),
+            ChangeOp::Replace => Mod::Replace(ReplaceMod { attr, values }),
+        }
+    }
+}
+
+pub struct MutationRoot;
+
+impl MutationRoot {
+    async fn read(ldap: &Ldap, dn: String) -> Result<Entry> {
+        match search(ldap, dn, Scope::Base, "(objectClass=*)".to_string())
+            .await?
+            .into_iter()
+            .next()
+        {
+            Some(val) => Ok(val),
+            None => Err(async_graphql::Error::new("Entry not found")),
+        }
+    }
+}
+
+#[Object]
+impl MutationRoot {
+    async fn add(
+        &self,
+        ctx: &Context<'_>,
+        dn: String,
+        attributes: Vec<AttributeInput>,
+    ) -> Result<Entry> {
+        let ldap = ctx.data::<Ldap>()?;
+        let command = QueryCommand::Add(AddCommand {
+            dn: dn.clone(),
+            attrs: attributes
+                .into_iter()
+                .map(|attr| (attr.name, attr.values.into_iter().collect()))
+                .collect(),
+        });
+        write(ldap, command).await?;
+        MutationRoot::read(ldap, dn).await
+    }
+
+    async fn modify(
+        &self,
+        ctx: &Context<'_>,
+        dn: String,
+        changes: Vec<ChangeInput>,
+    ) -> Result<Entry> {
+        let ldap = ctx.data::<Ldap>()?;
+        let command = QueryCommand::Modify(ModifyCommand {
+            dn: dn.clone(),
+            changes: changes.into_iter().map(Mod::from).collect(),
+        });
+        write(ldap, command).await?;
+        MutationRoot::read(ldap, dn).await
+    }
+
+    async fn delete(&self, ctx: &Context<'_>, dn: String) -> Result<bool> {
+        write(
+            ctx.data::<Ldap>()?,
+            QueryCommand::Delete(DeleteCommand { dn }),
+        )
+        .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_children_by_rdn() {
+        let rdns = [
+            "uid=alice,ou=people",
+            "cn=a\\2Cb+sn=c,ou=people",
+            "cn=(x*),ou=people",
+        ]
+        .iter()
+        .map(|val| dn::parse(val).unwrap().0.remove(0))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            rdn_filter(&rdns.iter().collect::<Vec<_>>()),
+            "(|(uid=alice)(&(cn=a,b)(sn=c))(cn=\\28x\\2a\\29))"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use ldap3_serde::Scope;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::{upstream, AppState};
+
+/// Status of one dependency of the service.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct CheckStatus {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl CheckStatus {
+    fn ok() -> Self {
+        CheckStatus {
+            ok: true,
+            message: None,
+        }
+    }
+
+    fn failed(message: impl Into<String>) -> Self {
+        CheckStatus {
+            ok: false,
+            message: Some(message.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ReadinessResponse {
+    pub result: bool,
+    pub authorized_keys: CheckStatus,
+    pub tls: CheckStatus,
+    /// Keyed by `host:port`.
+    pub upstreams: BTreeMap<String, CheckStatus>,
+}
+
+/// Upstream results of the last readiness probe, reused for `HEALTH_CACHE_SECONDS`.
+#[derive(Default)]
+pub struct HealthCache(Mutex<Option<(Instant, BTreeMap<String, CheckStatus>)>>);
+
+/// Upstreams probed for readiness: `HEALTH_UPSTREAMS` (comma-separated `host:port`),
+/// or `LDAP_HOST` and `LDAP_PORT`.
+pub fn upstreams() -> Vec<(String, u16)> {
+    let list = match dotenv::var("HEALTH_UPSTREAMS") {
+        Ok(val) => val,
+        Err(_) => {
+            let host = dotenv::var("LDAP_HOST").unwrap_or_else(|_| "localhost".to_string());
+            let port = dotenv::var("LDAP_PORT")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .unwrap_or(389);
+            return vec![(host, port)];
+        }
+    };
+
+    list.split(',')
+        .map(|val| val.trim())
+        .filter(|val| !val.is_empty())
+        .map(|val| match val.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host.to_string(), port),
+                Err(_) => (val.to_string(), 389),
+            },
+            None => (val.to_string(), 389),
+        })
+        .collect()
+}
+
+/// Reads the root DSE of an upstream over a fresh anonymous connection.
+async fn check_upstream(host: String, port: u16) -> CheckStatus {
+    let mut ldap = match upstream::connect(Some(host), Some(port)).await {
+        Ok(val) => val,
+        Err(_) => return CheckStatus::failed("Failed to connect"),
+    };
+
+    let res = ldap
+        .search("", Scope::Base, "(objectClass=*)", vec!["namingContexts"])
+        .await;
+    let _ = ldap.unbind().await;
+
+    match res {
+        Ok(res) if res.1.rc == 0 => CheckStatus::ok(),
+        Ok(res) => CheckStatus::failed(format!("Failed to read root DSE: {}", res.1)),
+        Err(err) => CheckStatus::failed(format!("Failed to read root DSE: {}", err)),
+    }
+}
+
+async fn check_upstreams(cache: &HealthCache) -> BTreeMap<String, CheckStatus> {
+    let ttl = dotenv::var("HEALTH_CACHE_SECONDS")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(10);
+    let timeout = dotenv::var("HEALTH_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(3);
+
+    // Held while probing, so concurrent requests wait for one probe instead of
+    // each opening their own connections.
+    let mut cached = cache.0.lock().await;
+    if let Some((checked, statuses)) = cached.as_ref() {
+        if checked.elapsed() < Duration::from_secs(ttl) {
+            return statuses.clone();
+        }
+    }
+
+    let checks = upstreams().into_iter().map(|(host, port)| async move {
+        let name = format!("{}:{}", host, port);
+        let status =
+            match tokio::time::timeout(Duration::from_secs(timeout), check_upstream(host, port))
+                .await
+            {
+                Ok(val) => val,
+                Err(_) => CheckStatus::failed("Timed out"),
+            };
+        (name, status)
+    });
+    let statuses = futures::future::join_all(checks)
+        .await
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+    *cached = Some((Instant::now(), statuses.clone()));
+    statuses
+}
+
+fn check_authorized_keys(state: &AppState) -> CheckStatus {
+    match state.authorized_keys.lock() {
+        Ok(val) if val.is_empty() => CheckStatus::failed("No authorized keys loaded"),
+        Ok(_) => CheckStatus::ok(),
+        Err(_) => CheckStatus::failed("Authorized keys are unavailable"),
+    }
+}
+
+fn check_tls(state: &AppState) -> CheckStatus {
+    match state.tls_error.lock() {
+        Ok(val) => match val.as_ref() {
+            Some(err) => CheckStatus::failed(format!("Last reload failed: {}", err)),
+            None => CheckStatus::ok(),
+        },
+        Err(_) => CheckStatus::failed("TLS status is unavailable"),
+    }
+}
+
+pub async fn readiness(state: &AppState) -> ReadinessResponse {
+    let authorized_keys = check_authorized_keys(state);
+    let tls = check_tls(state);
+    let upstreams = check_upstreams(&state.health).await;
+
+    ReadinessResponse {
+        result: authorized_keys.ok && tls.ok && upstreams.values().all(|status| status.ok),
+        authorized_keys,
+        tls,
+        upstreams,
+    }
+}
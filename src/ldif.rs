@@ -0,0 +1,537 @@
+use std::{collections::HashSet, fmt::Display};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ldap3_serde::SearchEntry;
+
+use crate::types::query::{
+    AddCommand, AddMod, DeleteCommand, DeleteMod, IncrementMod, Mod, ModifyCommand,
+    ModifyDnCommand, QueryCommand, ReplaceMod,
+};
+
+#[derive(Debug)]
+pub struct LdifError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl LdifError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        LdifError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for LdifError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LDIF error on line {}: {}", self.line, self.message)
+    }
+}
+
+/// A logical (unfolded) LDIF line, with the number of the physical line it started on.
+struct Line {
+    number: usize,
+    text: String,
+}
+
+/// Parses an RFC 2849 LDIF document into the commands it describes.
+///
+/// Content records become `add` commands; change records become `add`, `delete`,
+/// `modify` or `modifydn` commands. Values must be valid UTF-8, and URL values
+/// (`attr:< url`) and controls are rejected.
+pub fn parse(input: &str) -> Result<Vec<QueryCommand<'static>>, LdifError> {
+    let mut records = split_records(input)?;
+
+    if let Some(first) = records.first_mut() {
+        if first
+            .first()
+            .is_some_and(|line| line.text.to_ascii_lowercase().starts_with("version:"))
+        {
+            let version = first.remove(0);
+            match split_line(&version) {
+                Ok((_, value)) if value == "1" => {}
+                Ok((_, value)) => {
+                    return Err(LdifError::new(
+                        version.number,
+                        format!("Unsupported LDIF version {}", value),
+                    ))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    let mut commands = Vec::with_capacity(records.len());
+    for record in records.iter().filter(|record| !record.is_empty()) {
+        match parse_record(record) {
+            Ok(val) => commands.push(val),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Unfolds continuation lines, drops comments and groups lines into records.
+fn split_records(input: &str) -> Result<Vec<Vec<Line>>, LdifError> {
+    let mut records = Vec::<Vec<Line>>::new();
+    let mut record = Vec::<Line>::new();
+    let mut comment = false;
+
+    for (idx, raw) in input.split('\n').enumerate() {
+        let number = idx + 1;
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+
+        if let Some(rest) = raw.strip_prefix(' ') {
+            if comment {
+                continue;
+            }
+            match record.last_mut() {
+                Some(line) => line.text.push_str(rest),
+                None => return Err(LdifError::new(number, "Unexpected continuation line")),
+            }
+            continue;
+        }
+
+        if raw.is_empty() {
+            comment = false;
+            if !record.is_empty() {
+                records.push(std::mem::take(&mut record));
+            }
+            continue;
+        }
+
+        comment = raw.starts_with('#');
+        if !comment {
+            record.push(Line {
+                number,
+                text: raw.to_string(),
+            });
+        }
+    }
+
+    if !record.is_empty() {
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Splits an `attr: value` or `attr:: base64` line into its attribute and decoded value.
+fn split_line(line: &Line) -> Result<(String, String), LdifError> {
+    let (attr, rest) = match line.text.split_once(':') {
+        Some(val) => val,
+        None => return Err(LdifError::new(line.number, "Expected \"attribute: value\"")),
+    };
+
+    if attr.is_empty() {
+        return Err(LdifError::new(line.number, "Missing attribute name"));
+    }
+
+    if let Some(encoded) = rest.strip_prefix(':') {
+        let bytes = match STANDARD.decode(encoded.trim()) {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(LdifError::new(
+                    line.number,
+                    format!("Invalid base64 value: {}", err),
+                ))
+            }
+        };
+        return match String::from_utf8(bytes) {
+            Ok(val) => Ok((attr.to_string(), val)),
+            Err(_) => Err(LdifError::new(
+                line.number,
+                "Binary values are not supported",
+            )),
+        };
+    }
+
+    if rest.starts_with('<') {
+        return Err(LdifError::new(line.number, "URL values are not supported"));
+    }
+
+    Ok((attr.to_string(), rest.trim_start_matches(' ').to_string()))
+}
+
+fn parse_record(lines: &[Line]) -> Result<QueryCommand<'static>, LdifError> {
+    let (attr, dn) = split_line(&lines[0])?;
+    if !attr.eq_ignore_ascii_case("dn") {
+        return Err(LdifError::new(
+            lines[0].number,
+            "Record must start with \"dn\"",
+        ));
+    }
+
+    let rest = &lines[1..];
+    let first = match rest.first() {
+        Some(line) => match split_line(line) {
+            Ok(val) => Some((line.number, val)),
+            Err(err) => return Err(err),
+        },
+        None => None,
+    };
+
+    let (number, changetype) = match first {
+        Some((number, (attr, _))) if attr.eq_ignore_ascii_case("control") => {
+            return Err(LdifError::new(number, "Controls are not supported"));
+        }
+        Some((number, (attr, value))) if attr.eq_ignore_ascii_case("changetype") => {
+            (number, value.to_ascii_lowercase())
+        }
+        _ => {
+            return match parse_attrs(rest) {
+                Ok(attrs) => Ok(QueryCommand::Add(AddCommand { dn, attrs })),
+                Err(err) => Err(err),
+            }
+        }
+    };
+
+    let rest = &rest[1..];
+    match changetype.as_str() {
+        "add" => match parse_attrs(rest) {
+            Ok(attrs) => Ok(QueryCommand::Add(AddCommand { dn, attrs })),
+            Err(err) => Err(err),
+        },
+        "delete" => match rest.first() {
+            Some(line) => Err(LdifError::new(
+                line.number,
+                "Unexpected line in delete record",
+            )),
+            None => Ok(QueryCommand::Delete(DeleteCommand { dn })),
+        },
+        "modify" => match parse_mods(rest) {
+            Ok(changes) => Ok(QueryCommand::Modify(ModifyCommand { dn, changes })),
+            Err(err) => Err(err),
+        },
+        "modrdn" | "moddn" => parse_modrdn(dn, number, rest),
+        _ => Err(LdifError::new(
+            number,
+            format!("Unknown changetype \"{}\"", changetype),
+        )),
+    }
+}
+
+fn parse_attrs(lines: &[Line]) -> Result<Vec<(String, HashSet<String>)>, LdifError> {
+    let mut attrs = Vec::<(String, HashSet<String>)>::new();
+
+    for line in lines.iter() {
+        let (attr, value) = split_line(line)?;
+
+        match attrs
+            .iter_mut()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&attr))
+        {
+            Some((_, values)) => {
+                values.insert(value);
+            }
+            None => attrs.push((attr, HashSet::from([value]))),
+        }
+    }
+
+    if attrs.is_empty() {
+        return Err(LdifError::new(
+            lines.first().map_or(0, |line| line.number),
+            "Record has no attributes",
+        ));
+    }
+
+    Ok(attrs)
+}
+
+fn parse_mods(lines: &[Line]) -> Result<Vec<Mod>, LdifError> {
+    let mut changes = Vec::new();
+    let mut iter = lines.iter();
+
+    while let Some(line) = iter.next() {
+        let (op, attr) = split_line(line)?;
+
+        let mut values = HashSet::new();
+        let mut terminated = false;
+        for line in iter.by_ref() {
+            if line.text == "-" {
+                terminated = true;
+                break;
+            }
+            match split_line(line) {
+                Ok((name, value)) if name.eq_ignore_ascii_case(&attr) => {
+                    values.insert(value);
+                }
+                Ok((name, _)) => {
+                    return Err(LdifError::new(
+                        line.number,
+                        format!("Expected value for \"{}\", found \"{}\"", attr, name),
+                    ))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if !terminated {
+            return Err(LdifError::new(
+                line.number,
+                "Modification must be terminated by \"-\"",
+            ));
+        }
+
+        changes.push(match op.to_ascii_lowercase().as_str() {
+            "add" => Mod::Add(AddMod { attr, values }),
+            "delete" => Mod::Delete(DeleteMod { attr, values }),
+            "replace" => Mod::Replace(ReplaceMod { attr, values }),
+            "increment" => {
+                if values.len() != 1 {
+                    return Err(LdifError::new(
+                        line.number,
+                        "Increment requires exactly one value",
+                    ));
+                }
+                let value = values.into_iter().next().unwrap();
+                if value.parse::<i64>().is_err() {
+                    return Err(LdifError::new(
+                        line.number,
+                        "Increment value must be an integer",
+                    ));
+                }
+                Mod::Increment(IncrementMod { attr, value })
+            }
+            _ => {
+                return Err(LdifError::new(
+                    line.number,
+                    format!("Unknown modification \"{}\"", op),
+                ))
+            }
+        });
+    }
+
+    Ok(changes)
+}
+
+fn parse_modrdn(
+    dn: String,
+    number: usize,
+    lines: &[Line],
+) -> Result<QueryCommand<'static>, LdifError> {
+    let mut rdn = None;
+    let mut delete_old = None;
+    let mut new_superior = None;
+
+    for line in lines.iter() {
+        let (attr, value) = split_line(line)?;
+
+        match attr.to_ascii_lowercase().as_str() {
+            "newrdn" => rdn = Some(value),
+            "deleteoldrdn" => {
+                delete_old = match value.as_str() {
+                    "0" => Some(false),
+                    "1" => Some(true),
+                    _ => return Err(LdifError::new(line.number, "deleteoldrdn must be 0 or 1")),
+                }
+            }
+            "newsuperior" => new_superior = Some(value),
+            _ => {
+                return Err(LdifError::new(
+                    line.number,
+                    format!("Unexpected \"{}\" in modrdn record", attr),
+                ))
+            }
+        }
+    }
+
+    match (rdn, delete_old) {
+        (Some(rdn), Some(delete_old)) => Ok(QueryCommand::ModifyDn(ModifyDnCommand {
+            dn,
+            rdn,
+            delete_old,
+            new_superior,
+        })),
+        _ => Err(LdifError::new(
+            number,
+            "modrdn record requires newrdn and deleteoldrdn",
+        )),
+    }
+}
+
+/// Renders search entries as an RFC 2849 LDIF content document.
+///
+/// Values that are not SAFE-STRINGs, and all binary values, are base64-encoded.
+/// Lines are folded at 76 columns.
+pub fn write(entries: &[SearchEntry]) -> String {
+    let mut out = String::from("version: 1\n");
+
+    for entry in entries.iter() {
+        out.push('\n');
+        write_line(&mut out, "dn", entry.dn.as_bytes());
+
+        let mut attrs = entry.attrs.iter().collect::<Vec<_>>();
+        attrs.sort_by(|a, b| a.0.cmp(b.0));
+        for (attr, values) in attrs {
+            for value in values.iter() {
+                write_line(&mut out, attr, value.as_bytes());
+            }
+        }
+
+        let mut bin_attrs = entry.bin_attrs.iter().collect::<Vec<_>>();
+        bin_attrs.sort_by(|a, b| a.0.cmp(b.0));
+        for (attr, values) in bin_attrs {
+            for value in values.iter() {
+                write_line(&mut out, attr, value);
+            }
+        }
+    }
+
+    out
+}
+
+fn is_safe_string(value: &[u8]) -> bool {
+    match (value.first(), value.last()) {
+        (None, _) => true,
+        (Some(first), Some(last)) => {
+            !matches!(first, b' ' | b':' | b'<')
+                && *last != b' '
+                && value
+                    .iter()
+                    .all(|c| c.is_ascii() && !matches!(c, b'\0' | b'\n' | b'\r'))
+        }
+        _ => false,
+    }
+}
+
+fn write_line(out: &mut String, attr: &str, value: &[u8]) {
+    let line = match std::str::from_utf8(value) {
+        Ok(val) if is_safe_string(value) => format!("{}: {}", attr, val),
+        _ => format!("{}:: {}", attr, STANDARD.encode(value)),
+    };
+
+    // Output is pure ASCII at this point, so slicing on byte offsets is safe.
+    let mut rest = line.as_str();
+    let mut width = 76;
+    while rest.len() > width {
+        let (head, tail) = rest.split_at(width);
+        out.push_str(head);
+        out.push_str("\n ");
+        rest = tail;
+        width = 75;
+    }
+    out.push_str(rest);
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn entry() -> SearchEntry {
+        SearchEntry {
+            dn: "cn=Émile Zola,ou=people,dc=example,dc=com".to_string(),
+            attrs: HashMap::from([
+                (
+                    "objectClass".to_string(),
+                    vec!["top".to_string(), "person".to_string()],
+                ),
+                ("cn".to_string(), vec!["Émile Zola".to_string()]),
+                (
+                    "description".to_string(),
+                    vec![" leading space".to_string()],
+                ),
+                ("sn".to_string(), vec!["Z".repeat(100)]),
+            ]),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    fn attrs(command: &QueryCommand) -> (String, HashMap<String, HashSet<String>>) {
+        match command {
+            QueryCommand::Add(add) => (
+                add.dn.clone(),
+                add.attrs
+                    .iter()
+                    .map(|(attr, values)| (attr.clone(), values.clone()))
+                    .collect(),
+            ),
+            _ => panic!("Expected an add command"),
+        }
+    }
+
+    #[test]
+    fn round_trips_entries() {
+        let entry = entry();
+        let out = write(std::slice::from_ref(&entry));
+        assert!(out.lines().all(|line| line.len() <= 76));
+        assert!(out.contains("\ndn:: "));
+
+        let commands = parse(&out).unwrap();
+        assert_eq!(commands.len(), 1);
+        let (dn, parsed) = attrs(&commands[0]);
+        assert_eq!(dn, entry.dn);
+        let expected = entry
+            .attrs
+            .into_iter()
+            .map(|(attr, values)| (attr, values.into_iter().collect()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_change_records() {
+        let input = "version: 1\n\
+            # comment\n \
+             continued comment\n\
+            \n\
+            dn: cn=a,dc=example,dc=com\n\
+            changetype: modify\n\
+            replace: mail\n\
+            mail: a@exam\n \
+             ple.com\n\
+            -\n\
+            increment: uidNumber\n\
+            uidNumber: 1\n\
+            -\n\
+            \n\
+            dn: cn=a,dc=example,dc=com\n\
+            changetype: modrdn\n\
+            newrdn: cn=b\n\
+            deleteoldrdn: 1\n\
+            \n\
+            dn: cn=b,dc=example,dc=com\n\
+            changetype: delete\n";
+        let commands = parse(input).unwrap();
+        assert_eq!(commands.len(), 3);
+
+        match &commands[0] {
+            QueryCommand::Modify(modify) => {
+                assert_eq!(modify.changes.len(), 2);
+                match &modify.changes[0] {
+                    Mod::Replace(replace) => {
+                        assert_eq!(replace.values, HashSet::from(["a@example.com".to_string()]))
+                    }
+                    _ => panic!("Expected a replace"),
+                }
+                assert!(matches!(&modify.changes[1], Mod::Increment(_)));
+            }
+            _ => panic!("Expected a modify command"),
+        }
+        match &commands[1] {
+            QueryCommand::ModifyDn(modify_dn) => {
+                assert_eq!(modify_dn.rdn, "cn=b");
+                assert!(modify_dn.delete_old);
+            }
+            _ => panic!("Expected a modifydn command"),
+        }
+        assert!(matches!(&commands[2], QueryCommand::Delete(_)));
+    }
+
+    #[test]
+    fn reports_error_lines() {
+        let err = parse("dn: cn=a\nchangetype: modify\nadd: mail\nmail: x\n").unwrap_err();
+        assert_eq!(err.line, 3);
+
+        let err = parse("dn: cn=a\nchangetype: rename\n").unwrap_err();
+        assert_eq!(err.line, 2);
+
+        let err = parse("version: 2\n").unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{net::SocketAddr, time::Instant};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use tracing::{field::Empty, Instrument};
+
+use crate::context::{self, RequestContext};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Installs the global subscriber, writing JSON lines when `LOG_FORMAT` is `json`.
+pub fn init() {
+    let json = dotenv::var("LOG_FORMAT").is_ok_and(|val| val.eq_ignore_ascii_case("json"));
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "ldap_rest=info".into()),
+        )
+        .with(json.then(|| tracing_subscriber::fmt::layer().json()))
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .init();
+}
+
+/// Request IDs supplied by clients are kept only if they are short and printable.
+fn valid_request_id(value: &HeaderValue) -> Option<String> {
+    let value = value.to_str().ok()?;
+    match !value.is_empty() && value.len() <= 128 && value.bytes().all(|c| c.is_ascii_graphic()) {
+        true => Some(value.to_string()),
+        false => None,
+    }
+}
+
+/// Runs each request in a span carrying its ID, taken from `X-Request-Id` or
+/// generated, which is echoed in the response.
+///
+/// The span also declares the fields filled in while handling the request: the
+/// fingerprint and comment of the signing key, the upstream and the command types.
+pub async fn request_id(req: Request, next: Next) -> Response {
+    let request_id = match req.headers().get("X-Request-Id").and_then(valid_request_id) {
+        Some(val) => val,
+        None => uuid::Uuid::new_v4().to_string(),
+    };
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        route = %route,
+        key_fingerprint = Empty,
+        key_comment = Empty,
+        upstream = Empty,
+        commands = Empty,
+    );
+
+    let started = Instant::now();
+    let context = RequestContext {
+        request_id: Some(request_id.clone()),
+        client_addr: req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip()),
+        ..Default::default()
+    };
+    let mut res = context::scope(context, next.run(req))
+        .instrument(span.clone())
+        .await;
+    span.in_scope(|| {
+        tracing::info!(
+            status = res.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Request completed"
+        )
+    });
+
+    if let Ok(val) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert("X-Request-Id", val);
+    }
+    res
+}
@@ -1,7 +1,29 @@
+mod access;
+mod approvals;
+mod audit;
+mod auth;
 mod config;
+mod context;
+mod csv;
+mod dn;
+mod graphql;
+mod health;
+mod ldif;
+mod logging;
+mod metrics;
+mod mtls;
+mod openapi;
+mod policy;
+mod revocation;
 mod routes;
+mod schema;
+mod scim;
+mod signing;
+mod sync;
 mod types;
+mod upstream;
 mod utils;
+mod webhooks;
 
 extern crate dotenv;
 
@@ -13,6 +35,7 @@ use std::{io, net::SocketAddr};
 
 use axum::error_handling::HandleErrorLayer;
 use axum::http::StatusCode;
+use axum::middleware;
 use axum::BoxError;
 use axum_server::tls_rustls::RustlsConfig;
 use axum_server::Handle;
@@ -25,12 +48,8 @@ use signal_hook::{
 use ssh_key::authorized_keys::Entry;
 use ssh_key::AuthorizedKeys;
 use tower::ServiceBuilder;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use axum::{
-    routing::{get, post},
-    Router,
-};
+use axum::Router;
 use types::routes::Response;
 
 enum Error {
@@ -45,11 +64,21 @@ impl std::fmt::Debug for Error {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 enum StartError {
     CreateSignalHandlerError(io::Error),
     AddressParseError(AddrParseError),
+    SignatureAudienceError(String),
     CertificateError(config::LoadCertError),
     AuthorizedKeysError(ssh_key::Error),
+    ClientIdentitiesError(String),
+    RevokedKeysError(String),
+    ServerKeyError(String),
+    ScimMappingError(String),
+    ScimTokensError(String),
+    WebhooksError(String),
+    AuditLogError(String),
+    PolicyError(String),
     ServerError(io::Error),
 }
 
@@ -65,9 +94,36 @@ impl std::fmt::Debug for StartError {
             StartError::AuthorizedKeysError(err) => {
                 utils::print_error(f, "Failed to load authorized keys, exiting.", err)
             }
+            StartError::ClientIdentitiesError(err) => {
+                utils::print_error(f, "Failed to load client identities, exiting.", err)
+            }
+            StartError::RevokedKeysError(err) => {
+                utils::print_error(f, "Failed to load revoked keys, exiting.", err)
+            }
+            StartError::ServerKeyError(err) => {
+                utils::print_error(f, "Failed to load server key, exiting.", err)
+            }
+            StartError::ScimMappingError(err) => {
+                utils::print_error(f, "Failed to load SCIM mapping, exiting.", err)
+            }
+            StartError::ScimTokensError(err) => {
+                utils::print_error(f, "Failed to load SCIM tokens, exiting.", err)
+            }
+            StartError::WebhooksError(err) => {
+                utils::print_error(f, "Failed to load webhooks, exiting.", err)
+            }
+            StartError::AuditLogError(err) => {
+                utils::print_error(f, "Failed to open audit log, exiting.", err)
+            }
+            StartError::PolicyError(err) => {
+                utils::print_error(f, "Failed to load policy, exiting.", err)
+            }
             StartError::AddressParseError(err) => {
                 utils::print_error(f, "Failed to parse address, exiting.", err)
             }
+            StartError::SignatureAudienceError(err) => {
+                utils::print_error(f, "Failed to load signature audience, exiting.", err)
+            }
             StartError::ServerError(err) => {
                 utils::print_error(f, "Error occurred while running server, exiting", err)
             }
@@ -79,13 +135,12 @@ impl std::fmt::Debug for StartError {
 async fn main() -> Result<(), ()> {
     dotenv::dotenv().ok();
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "ldap_rest=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    logging::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(|val| val.as_str()) == Some("verify-audit-log") {
+        return verify_audit_log(args.get(2).cloned());
+    }
 
     let res = start().await;
     match res {
@@ -97,8 +152,41 @@ async fn main() -> Result<(), ()> {
     }
 }
 
+/// Checks the hash chain of the audit log at `path` or `AUDIT_LOG_PATH`.
+fn verify_audit_log(path: Option<String>) -> Result<(), ()> {
+    let path = match path.or_else(|| dotenv::var("AUDIT_LOG_PATH").ok()) {
+        Some(val) => val,
+        None => {
+            tracing::error!("No audit log given and AUDIT_LOG_PATH not set");
+            return Err(());
+        }
+    };
+
+    match audit::verify(&path) {
+        Ok((records, hash)) => {
+            tracing::info!("Verified {} audit records, last hash {}", records, hash);
+            Ok(())
+        }
+        Err(err) => {
+            tracing::error!("Audit log verification failed: {}", err);
+            Err(())
+        }
+    }
+}
+
 struct AppState {
     authorized_keys: Mutex<Vec<Entry>>,
+    client_identities: Mutex<Vec<mtls::ClientIdentity>>,
+    scim_tokens: Mutex<Vec<scim::token::ScimToken>>,
+    revoked_keys: revocation::RevocationList,
+    /// Key responses are signed with, if any.
+    server_key: Option<ssh_key::PrivateKey>,
+    approvals: approvals::ApprovalStore,
+    scim: scim::mapping::ScimConfig,
+    graphql: graphql::DirectorySchema,
+    health: health::HealthCache,
+    /// Error of the last failed certificate reload, cleared by a successful one.
+    tls_error: Mutex<Option<String>>,
 }
 
 async fn start() -> Result<(), Error> {
@@ -111,6 +199,10 @@ async fn start() -> Result<(), Error> {
         }
     };
 
+    if let Err(err) = auth::load_audience() {
+        return Err(Error::Start(StartError::SignatureAudienceError(err)));
+    }
+
     let conf = match config::load_or_create_cert().await {
         Ok(val) => val,
         Err(err) => {
@@ -127,23 +219,78 @@ async fn start() -> Result<(), Error> {
         }
     };
 
+    let client_identities = match mtls::load() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Error::Start(StartError::ClientIdentitiesError(err)));
+        }
+    };
+
+    let revoked_keys = match revocation::RevocationList::load() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Error::Start(StartError::RevokedKeysError(err)));
+        }
+    };
+
+    let server_key = match signing::load() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Error::Start(StartError::ServerKeyError(err)));
+        }
+    };
+
+    let scim = match scim::mapping::ScimConfig::load() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Error::Start(StartError::ScimMappingError(err)));
+        }
+    };
+
+    let scim_tokens = match scim::token::load() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Error::Start(StartError::ScimTokensError(err)));
+        }
+    };
+
+    match policy::load() {
+        Ok(val) => policy::set(val),
+        Err(err) => {
+            return Err(Error::Start(StartError::PolicyError(err)));
+        }
+    };
+
+    if let Err(err) = audit::open().await {
+        return Err(Error::Start(StartError::AuditLogError(err)));
+    }
+
+    let targets = match webhooks::load() {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Error::Start(StartError::WebhooksError(err)));
+        }
+    };
+    webhooks::start(targets);
+
     let state = Arc::new(AppState {
         authorized_keys: Mutex::new(keys),
+        client_identities: Mutex::new(client_identities),
+        scim_tokens: Mutex::new(scim_tokens),
+        revoked_keys,
+        server_key,
+        approvals: approvals::ApprovalStore::default(),
+        scim,
+        graphql: graphql::schema(),
+        health: health::HealthCache::default(),
+        tls_error: Mutex::new(None),
     });
 
-    let app = Router::new()
-        .route("/", get(routes::index::get))
-        .route("/query", post(routes::query::post))
-        .with_state(state.clone())
-        .layer(
-            ServiceBuilder::new()
-                .layer(HandleErrorLayer::new(handle_error))
-                .timeout(Duration::from_secs(10)),
-        );
+    let app = app(state.clone());
 
     let handle = Handle::new();
 
-    let signals_reload = match Signals::new(&[SIGHUP, SIGUSR2]) {
+    let signals_reload = match Signals::new([SIGHUP, SIGUSR2]) {
         Ok(val) => val,
         Err(err) => {
             return Err(Error::Start(StartError::CreateSignalHandlerError(err)));
@@ -151,7 +298,7 @@ async fn start() -> Result<(), Error> {
     };
 
     let signals_shutdown =
-        match Signals::new(&[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM]) {
+        match Signals::new([signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM]) {
             Ok(val) => val,
             Err(err) => {
                 return Err(Error::Start(StartError::CreateSignalHandlerError(err)));
@@ -161,15 +308,41 @@ async fn start() -> Result<(), Error> {
     tokio::spawn(signal_reload(signals_reload, conf.clone(), state));
     tokio::spawn(signal_shutdown(signals_shutdown, handle.clone()));
 
-    let server = axum_server::bind_rustls(addr, conf);
+    let server = axum_server::bind(addr).acceptor(mtls::ClientCertAcceptor::new(conf));
     tracing::info!("Listening on {}", addr);
 
-    match server.handle(handle).serve(app.into_make_service()).await {
+    match server
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+    {
         Ok(_) => Ok(()),
         Err(err) => Err(Error::Start(StartError::ServerError(err))),
     }
 }
 
+/// Serves every route of `routes::table` behind the middleware of the API.
+fn app(state: Arc<AppState>) -> Router {
+    routes::table()
+        .into_iter()
+        .fold(Router::new(), |router, (path, route)| {
+            router.route(path, route)
+        })
+        .with_state(state.clone())
+        .layer(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(logging::request_id))
+                .layer(middleware::from_fn(metrics::track))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    mtls::authenticate,
+                ))
+                .layer(middleware::from_fn_with_state(state.clone(), signing::sign))
+                .layer(HandleErrorLayer::new(handle_error))
+                .timeout(Duration::from_secs(10)),
+        )
+}
+
 async fn signal_reload(mut signals: SignalsInfo, config: RustlsConfig, state: Arc<AppState>) {
     for sig in signals.forever() {
         tracing::info!("Received signal {:?}", sig);
@@ -184,7 +357,7 @@ async fn signal_reload(mut signals: SignalsInfo, config: RustlsConfig, state: Ar
                 None
             }
         };
-        if keys.is_some() {
+        if let Some(keys) = keys {
             let mut val = match state.authorized_keys.try_lock() {
                 Ok(val) => val,
                 Err(_) => {
@@ -194,25 +367,70 @@ async fn signal_reload(mut signals: SignalsInfo, config: RustlsConfig, state: Ar
             };
 
             val.clear();
-            val.extend(keys.unwrap());
+            val.extend(keys);
             tracing::info!("Authorized keys reloaded");
         }
 
+        tracing::info!("Reloading revoked keys");
+        match state.revoked_keys.reload() {
+            Ok(()) => tracing::info!("Revoked keys reloaded"),
+            Err(err) => tracing::error!("Failed to reload revoked keys: {}", err),
+        };
+
+        tracing::info!("Reloading client identities");
+        match mtls::load() {
+            Ok(identities) => match state.client_identities.lock() {
+                Ok(mut val) => {
+                    *val = identities;
+                    tracing::info!("Client identities reloaded");
+                }
+                Err(_) => tracing::error!("Failed to acquire lock on client identities"),
+            },
+            Err(err) => tracing::error!("Failed to reload client identities: {}", err),
+        };
+
+        tracing::info!("Reloading SCIM tokens");
+        match scim::token::load() {
+            Ok(tokens) => match state.scim_tokens.lock() {
+                Ok(mut val) => {
+                    *val = tokens;
+                    tracing::info!("SCIM tokens reloaded");
+                }
+                Err(_) => tracing::error!("Failed to acquire lock on SCIM tokens"),
+            },
+            Err(err) => tracing::error!("Failed to reload SCIM tokens: {}", err),
+        };
+
+        tracing::info!("Reloading policy");
+        match policy::load() {
+            Ok(val) => {
+                policy::set(val);
+                tracing::info!("Policy reloaded");
+            }
+            Err(err) => tracing::error!("Failed to reload policy: {}", err),
+        };
+
         tracing::info!("Reloading certificates");
         match config::load_or_create_cert().await {
             Ok(val) => {
                 tracing::info!("Certificates reloaded");
                 config.reload_from_config(val.get_inner());
+                if let Ok(mut tls_error) = state.tls_error.lock() {
+                    *tls_error = None;
+                }
             }
             Err(err) => {
                 tracing::error!("Failed to reload certificates: {:?}", err);
+                if let Ok(mut tls_error) = state.tls_error.lock() {
+                    *tls_error = Some(format!("{:?}", err));
+                }
             }
         };
     }
 }
 
 async fn signal_shutdown(mut signals: SignalsInfo, handle: Handle) {
-    for sig in signals.forever() {
+    if let Some(sig) = signals.forever().next() {
         tracing::info!("Received signal {:?}", sig);
         tracing::info!("Shutting down server");
         handle.shutdown();
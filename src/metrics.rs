@@ -0,0 +1,193 @@
+use std::{sync::LazyLock, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use ldap3_serde::LdapError;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::types::query::QueryResult;
+
+pub struct Metrics {
+    registry: Registry,
+    pub requests: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub requests_in_flight: IntGauge,
+    pub commands: IntCounterVec,
+    pub command_duration: HistogramVec,
+    pub ldap_results: IntCounterVec,
+    pub auth_failures: IntCounterVec,
+    pub upstream_connect_duration: HistogramVec,
+    pub upstream_connect_errors: IntCounterVec,
+}
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let metric = IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric");
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("unique metric");
+    metric
+}
+
+fn histogram(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let metric = HistogramVec::new(HistogramOpts::new(name, help), labels).expect("valid metric");
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("unique metric");
+    metric
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let registry = Registry::new_custom(Some("ldap_rest".to_string()), None).expect("valid prefix");
+
+    let requests_in_flight = IntGauge::new(
+        "http_requests_in_flight",
+        "Requests currently being handled",
+    )
+    .expect("valid metric");
+    registry
+        .register(Box::new(requests_in_flight.clone()))
+        .expect("unique metric");
+
+    Metrics {
+        requests: counter(
+            &registry,
+            "http_requests_total",
+            "Handled requests by route, method and status",
+            &["route", "method", "status"],
+        ),
+        request_duration: histogram(
+            &registry,
+            "http_request_duration_seconds",
+            "Request latency by route and method",
+            &["route", "method"],
+        ),
+        requests_in_flight,
+        commands: counter(
+            &registry,
+            "commands_total",
+            "Executed query commands by type and outcome",
+            &["command", "outcome"],
+        ),
+        command_duration: histogram(
+            &registry,
+            "command_duration_seconds",
+            "Query command latency by type",
+            &["command"],
+        ),
+        ldap_results: counter(
+            &registry,
+            "ldap_results_total",
+            "LDAP result codes returned to query commands",
+            &["command", "code"],
+        ),
+        auth_failures: counter(
+            &registry,
+            "auth_failures_total",
+            "Rejected request signatures by reason",
+            &["reason"],
+        ),
+        upstream_connect_duration: histogram(
+            &registry,
+            "upstream_connect_duration_seconds",
+            "Latency of connecting to an upstream directory by configured upstream",
+            &["host"],
+        ),
+        upstream_connect_errors: counter(
+            &registry,
+            "upstream_connect_errors_total",
+            "Failed connections to an upstream directory by configured upstream",
+            &["host"],
+        ),
+        registry,
+    }
+});
+
+impl Metrics {
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+
+    /// Records the outcome of a query command, and its LDAP result code if it has one.
+    /// Returns the outcome label: `success`, `failure` or `error`.
+    pub fn record_command(
+        &self,
+        command: &str,
+        started: Instant,
+        res: &Result<Option<QueryResult>, LdapError>,
+    ) -> &'static str {
+        self.command_duration
+            .with_label_values(&[command])
+            .observe(started.elapsed().as_secs_f64());
+
+        let rc = match res {
+            Ok(Some(res)) => res.ldap_result().map(|res| res.rc),
+            Ok(None) => None,
+            Err(LdapError::LdapResult { result }) => Some(result.rc),
+            Err(_) => None,
+        };
+        if let Some(rc) = rc {
+            self.ldap_results
+                .with_label_values(&[command, &rc.to_string()])
+                .inc();
+        }
+
+        let outcome = match (res, rc) {
+            (Err(LdapError::LdapResult { .. }), _) => "failure",
+            (Err(_), _) => "error",
+            (Ok(_), Some(0)) | (Ok(_), None) => "success",
+            // Compare reports its answer as compareFalse (5) or compareTrue (6).
+            (Ok(Some(QueryResult::Compare(_))), Some(5 | 6)) => "success",
+            (Ok(_), Some(_)) => "failure",
+        };
+        self.commands.with_label_values(&[command, outcome]).inc();
+        outcome
+    }
+}
+
+/// Keeps the in-flight gauge right when a request is dropped before completing.
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        METRICS.requests_in_flight.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        METRICS.requests_in_flight.dec();
+    }
+}
+
+/// Counts requests and their latency by matched route.
+pub async fn track(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+
+    let in_flight = InFlight::start();
+    let started = Instant::now();
+    let res = next.run(req).await;
+    drop(in_flight);
+
+    METRICS
+        .request_duration
+        .with_label_values(&[&route, &method])
+        .observe(started.elapsed().as_secs_f64());
+    METRICS
+        .requests
+        .with_label_values(&[&route, &method, res.status().as_str()])
+        .inc();
+
+    res
+}
@@ -0,0 +1,269 @@
+use std::{future::Future, io, pin::Pin, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use ssh_key::authorized_keys::ConfigOpts;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::Layer;
+use x509_cert::{
+    der::Decode,
+    ext::pkix::{name::GeneralName, SubjectAltName},
+    Certificate,
+};
+
+use crate::{
+    access::KeyAccess,
+    context::{self, Credential},
+    dn::{self, Dn},
+    AppState,
+};
+
+/// Leaf certificate the client presented in the TLS handshake, in DER.
+#[derive(Clone, Default)]
+pub struct PeerCertificate(pub Option<Arc<[u8]>>);
+
+/// Accepts TLS connections and hands the client certificate of each to its requests.
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        ClientCertAcceptor {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = <RustlsAcceptor as Accept<I, S>>::Stream;
+    type Service = <Extension<PeerCertificate> as Layer<S>>::Service;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accept.await?;
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| Arc::from(cert.0.as_slice()));
+            Ok((
+                stream,
+                Extension(PeerCertificate(certificate)).layer(service),
+            ))
+        })
+    }
+}
+
+/// Entry of `CLIENT_IDENTITIES_PATH`, naming the callers authenticated by their
+/// client certificate.
+#[derive(Deserialize)]
+struct ClientIdentityConfig {
+    /// Subject DN of the certificate.
+    subject: Option<String>,
+    /// Subject alternative name, as `DNS:`, `URI:`, `email:` or `IP:` followed by
+    /// the name.
+    san: Option<String>,
+    /// Name the caller is known by, in place of a key comment; defaults to the
+    /// subject or SAN.
+    identity: Option<String>,
+    /// Restrictions, in the syntax of authorized_keys options.
+    #[serde(default)]
+    options: String,
+}
+
+enum CertificateName {
+    Subject(Dn),
+    San(String, String),
+}
+
+pub struct ClientIdentity {
+    name: CertificateName,
+    identity: String,
+    access: KeyAccess,
+}
+
+fn san_kind(kind: &str) -> Option<&'static str> {
+    match kind.to_ascii_lowercase().as_str() {
+        "dns" => Some("DNS"),
+        "uri" => Some("URI"),
+        "email" => Some("email"),
+        "ip" => Some("IP"),
+        _ => None,
+    }
+}
+
+impl ClientIdentity {
+    fn from_config(config: ClientIdentityConfig) -> Result<Self, String> {
+        let (name, default_identity) = match (config.subject, config.san) {
+            (Some(subject), None) => match dn::parse(&subject) {
+                Ok(val) => (CertificateName::Subject(val), subject),
+                Err(err) => return Err(format!("Invalid subject {}: {}", subject, err)),
+            },
+            (None, Some(san)) => {
+                let kind = san.split_once(':').and_then(|(kind, value)| {
+                    san_kind(kind).map(|kind| (kind.to_string(), value.to_string()))
+                });
+                match kind {
+                    Some((kind, value)) => (CertificateName::San(kind, value), san),
+                    None => return Err(format!("Invalid SAN {}", san)),
+                }
+            }
+            _ => return Err("Exactly one of subject and san must be given".to_string()),
+        };
+
+        let options = match ConfigOpts::new(config.options) {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Invalid options of {}: {}", default_identity, err)),
+        };
+        let access = match KeyAccess::from_options(&options) {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Invalid options of {}: {}", default_identity, err)),
+        };
+
+        Ok(ClientIdentity {
+            name,
+            identity: config.identity.unwrap_or(default_identity),
+            access,
+        })
+    }
+
+    fn matches(&self, subject: &Option<Dn>, sans: &[(&'static str, String)]) -> bool {
+        match &self.name {
+            CertificateName::Subject(dn) => subject
+                .as_ref()
+                .is_some_and(|subject| subject.0.len() == dn.0.len() && subject.is_within(dn)),
+            CertificateName::San(kind, value) => sans.iter().any(|(san_kind, san)| {
+                san_kind == kind
+                    && match *san_kind {
+                        "DNS" | "email" => san.eq_ignore_ascii_case(value),
+                        _ => san == value,
+                    }
+            }),
+        }
+    }
+}
+
+/// Loads the identities of client certificates from `CLIENT_IDENTITIES_PATH`, a JSON
+/// list; none are known when it is not set.
+pub fn load() -> Result<Vec<ClientIdentity>, String> {
+    let path = match dotenv::var("CLIENT_IDENTITIES_PATH") {
+        Ok(val) => val,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+
+    let configs = match serde_json::from_str::<Vec<ClientIdentityConfig>>(&content) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to parse {}: {}", path, err)),
+    };
+
+    configs
+        .into_iter()
+        .map(ClientIdentity::from_config)
+        .collect()
+}
+
+fn subject_alt_names(cert: &Certificate) -> Vec<(&'static str, String)> {
+    let names = match cert.tbs_certificate.get::<SubjectAltName>() {
+        Ok(Some((_, val))) => val.0,
+        _ => return Vec::new(),
+    };
+    names
+        .into_iter()
+        .filter_map(|name| match name {
+            GeneralName::DnsName(val) => Some(("DNS", val.to_string())),
+            GeneralName::UniformResourceIdentifier(val) => Some(("URI", val.to_string())),
+            GeneralName::Rfc822Name(val) => Some(("email", val.to_string())),
+            GeneralName::IpAddress(val) => match val.as_bytes().len() {
+                4 => <[u8; 4]>::try_from(val.as_bytes())
+                    .ok()
+                    .map(|ip| ("IP", std::net::IpAddr::from(ip).to_string())),
+                16 => <[u8; 16]>::try_from(val.as_bytes())
+                    .ok()
+                    .map(|ip| ("IP", std::net::IpAddr::from(ip).to_string())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Authenticates requests whose connection presented a client certificate mapped to
+/// an identity, which then act like a request signed by an authorized key. Requests
+/// with an unknown certificate still have to be signed.
+pub async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let der = match req.extensions().get::<PeerCertificate>() {
+        Some(PeerCertificate(Some(val))) => val.clone(),
+        _ => return next.run(req).await,
+    };
+
+    let cert = match Certificate::from_der(&der) {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::warn!("Failed to parse client certificate: {}", err);
+            return next.run(req).await;
+        }
+    };
+    let subject_str = cert.tbs_certificate.subject.to_string();
+    let subject = dn::parse(&subject_str).ok();
+    let sans = subject_alt_names(&cert);
+
+    let identity = match state.client_identities.lock() {
+        Ok(val) => val
+            .iter()
+            .find(|identity| identity.matches(&subject, &sans))
+            .map(|identity| (identity.identity.clone(), identity.access.clone())),
+        Err(_) => {
+            tracing::error!("Failed to acquire lock on client identities");
+            None
+        }
+    };
+
+    match identity {
+        Some((identity, access)) => {
+            let fingerprint = format!(
+                "SHA256:{}",
+                base64::engine::general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(&der))
+            );
+            let span = tracing::Span::current();
+            span.record("key_fingerprint", &fingerprint);
+            span.record("key_comment", &identity);
+            context::set_key(Credential::ClientCertificate, fingerprint, identity, access);
+            context::set_certificate(subject_str);
+        }
+        None => tracing::info!(
+            subject = %subject_str,
+            "Client certificate is not mapped to an identity"
+        ),
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,543 @@
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{InstanceType, Schema, SchemaObject},
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    approvals::PendingResponse,
+    health::ReadinessResponse,
+    policy::Decision,
+    routes::{
+        entries::{EntryParams, PatchBody, PutBody},
+        index::StatusResponse,
+        query::{QueryRequest, SuccessResponse},
+        well_known::ServerKeyResponse,
+    },
+    types::{
+        entry::Entry,
+        patch::PatchOperation,
+        query::{remote::LdapResultDef, EnsureResult},
+        routes::{DataResponse, ErrorResponse},
+    },
+};
+
+/// Schema of a string holding the JSON serialization of `T`.
+pub fn json_string<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        ..Default::default()
+    };
+    schema
+        .extensions
+        .insert("contentMediaType".to_string(), json!("application/json"));
+    schema
+        .extensions
+        .insert("contentSchema".to_string(), json!(gen.subschema_for::<T>()));
+    Schema::Object(schema)
+}
+
+fn content<T: JsonSchema>(gen: &mut SchemaGenerator, media_type: &str) -> Value {
+    json!({ media_type: { "schema": gen.subschema_for::<T>() } })
+}
+
+fn response<T: JsonSchema>(gen: &mut SchemaGenerator, description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": content::<T>(gen, "application/json"),
+    })
+}
+
+/// Describes each property of `T` as an optional query parameter.
+fn query_parameters<T: JsonSchema>(gen: &mut SchemaGenerator) -> Vec<Value> {
+    let schema = gen.root_schema_for::<T>().schema;
+    let properties = match schema.object {
+        Some(val) => val.properties,
+        None => return vec![],
+    };
+
+    properties
+        .into_iter()
+        .map(|(name, schema)| {
+            let description = match &schema {
+                Schema::Object(val) => val
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.description.clone()),
+                _ => None,
+            };
+            let mut parameter = json!({
+                "name": name,
+                "in": "query",
+                "required": false,
+                "schema": schema,
+            });
+            if let Some(description) = description {
+                parameter["description"] = json!(description);
+            }
+            parameter
+        })
+        .collect()
+}
+
+/// Builds the OpenAPI 3.1 description of the API from the request and response types.
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::draft2019_09()
+        .with(|settings| settings.definitions_path = "#/components/schemas/".to_string())
+        .into_generator();
+
+    let error = response::<ErrorResponse>(&mut gen, "Error");
+    let path_parameter = |name: &str| {
+        json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        })
+    };
+    let entry_parameters = query_parameters::<EntryParams>(&mut gen);
+    let signed = json!([
+        { "publicKey": [], "timestamp": [], "signature": [] },
+        { "clientCertificate": [] },
+    ]);
+    let scim_security = json!([
+        { "bearer": [] },
+        { "publicKey": [], "timestamp": [], "signature": [] },
+        { "clientCertificate": [] },
+    ]);
+    let scim_resource = json!({
+        "description": "SCIM resource",
+        "content": { "application/scim+json": { "schema": { "type": "object" } } },
+    });
+    let scim_body = json!({
+        "required": true,
+        "content": { "application/scim+json": { "schema": { "type": "object" } } },
+    });
+    let scim_resource_parameter = json!({
+        "name": "resource",
+        "in": "path",
+        "required": true,
+        "schema": { "enum": ["Users", "Groups"] },
+    });
+
+    let mut patch_content = content::<PatchBody>(&mut gen, "application/json");
+    if let Value::Object(patch_content) = &mut patch_content {
+        patch_content.extend(
+            content::<Vec<PatchOperation>>(&mut gen, "application/json-patch+json")
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+        );
+        patch_content.insert(
+            "application/merge-patch+json".to_string(),
+            json!({ "schema": { "type": "object" } }),
+        );
+    }
+
+    let paths = json!({
+        "/": {
+            "get": {
+                "summary": "Health check",
+                "responses": { "200": response::<StatusResponse>(&mut gen, "Healthy") },
+            },
+        },
+        "/.well-known/ldap-rest-key": {
+            "get": {
+                "summary": "Public key responses are signed with",
+                "responses": {
+                    "200": response::<DataResponse<ServerKeyResponse>>(&mut gen, "Server key"),
+                    "404": error,
+                },
+            },
+        },
+        "/health/live": {
+            "get": {
+                "summary": "Liveness check",
+                "responses": { "200": response::<StatusResponse>(&mut gen, "Running") },
+            },
+        },
+        "/health/ready": {
+            "get": {
+                "summary": "Readiness check of authorized keys, TLS and upstream directories",
+                "responses": {
+                    "200": response::<ReadinessResponse>(&mut gen, "Ready"),
+                    "503": response::<ReadinessResponse>(&mut gen, "Not ready"),
+                },
+            },
+        },
+        "/query": {
+            "post": {
+                "summary": "Execute signed LDAP commands",
+                "requestBody": {
+                    "required": true,
+                    "content": content::<QueryRequest>(&mut gen, "application/json"),
+                },
+                "responses": {
+                    "200": response::<SuccessResponse>(&mut gen, "Results of every command"),
+                    "202": response::<DataResponse<PendingResponse>>(&mut gen, "Held for approval"),
+                    "206": error,
+                    "400": error,
+                    "401": error,
+                },
+            },
+        },
+        "/approvals/{id}": {
+            "parameters": [path_parameter("id")],
+            "get": {
+                "summary": "Read a batch held for approval",
+                "security": signed,
+                "responses": {
+                    "200": response::<DataResponse<PendingResponse>>(&mut gen, "Held batch"),
+                    "404": error,
+                },
+            },
+            "post": {
+                "summary": "Approve a held batch and execute it",
+                "security": signed,
+                "responses": {
+                    "200": response::<SuccessResponse>(&mut gen, "Results of every command"),
+                    "206": error,
+                    "403": error,
+                    "404": error,
+                },
+            },
+            "delete": {
+                "summary": "Reject or withdraw a held batch",
+                "security": signed,
+                "responses": {
+                    "200": response::<DataResponse<PendingResponse>>(&mut gen, "Rejected batch"),
+                    "403": error,
+                    "404": error,
+                },
+            },
+        },
+        "/policy/explain": {
+            "post": {
+                "summary": "Explain which policy rules allow or deny signed LDAP commands",
+                "requestBody": {
+                    "required": true,
+                    "content": content::<QueryRequest>(&mut gen, "application/json"),
+                },
+                "responses": {
+                    "200": response::<DataResponse<Vec<Decision>>>(&mut gen, "Decision for every command"),
+                    "400": error,
+                    "401": error,
+                },
+            },
+        },
+        "/entries/{dn}": {
+            "parameters": [path_parameter("dn")],
+            "get": {
+                "summary": "Read an entry",
+                "parameters": entry_parameters,
+                "security": signed,
+                "responses": {
+                    "200": response::<DataResponse<Entry>>(&mut gen, "Entry"),
+                    "404": error,
+                },
+            },
+            "put": {
+                "summary": "Create an entry or replace the given attributes",
+                "security": signed,
+                "requestBody": {
+                    "required": true,
+                    "content": content::<PutBody>(&mut gen, "application/json"),
+                },
+                "responses": {
+                    "200": response::<DataResponse<EnsureResult>>(&mut gen, "Entry updated"),
+                    "201": response::<DataResponse<EnsureResult>>(&mut gen, "Entry created"),
+                    "422": error,
+                },
+            },
+            "patch": {
+                "summary": "Modify an entry",
+                "security": signed,
+                "requestBody": { "required": true, "content": patch_content },
+                "responses": {
+                    "200": response::<DataResponse<LdapResultDef>>(&mut gen, "LDAP result"),
+                    "404": error,
+                    "422": error,
+                },
+            },
+            "delete": {
+                "summary": "Delete an entry",
+                "security": signed,
+                "responses": {
+                    "200": response::<DataResponse<LdapResultDef>>(&mut gen, "LDAP result"),
+                    "404": error,
+                },
+            },
+        },
+        "/entries/{dn}/children": {
+            "parameters": [path_parameter("dn")],
+            "get": {
+                "summary": "List the immediate children of an entry",
+                "parameters": entry_parameters,
+                "security": signed,
+                "responses": {
+                    "200": response::<DataResponse<Vec<Entry>>>(&mut gen, "Entries"),
+                    "404": error,
+                },
+            },
+        },
+        "/entries/{dn}/changes": {
+            "parameters": [path_parameter("dn")],
+            "get": {
+                "summary": "Stream changes below an entry as server-sent events",
+                "security": signed,
+                "parameters": [{
+                    "name": "filter",
+                    "in": "query",
+                    "required": false,
+                    "schema": { "type": "string" },
+                }, {
+                    "name": "Last-Event-ID",
+                    "in": "header",
+                    "required": false,
+                    "description": "Synchronization cookie to resume from",
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "200": {
+                        "description": "Event stream",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                    },
+                    "400": error,
+                },
+            },
+        },
+        "/graphql": {
+            "post": {
+                "summary": "Execute a GraphQL query or mutation",
+                "security": signed,
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "required": ["query"],
+                        "properties": {
+                            "query": { "type": "string" },
+                            "operationName": { "type": "string" },
+                            "variables": { "type": "object" },
+                        },
+                    } } },
+                },
+                "responses": {
+                    "200": {
+                        "description": "GraphQL response",
+                        "content": { "application/json": { "schema": { "type": "object" } } },
+                    },
+                    "400": error,
+                },
+            },
+        },
+        "/session": {
+            "get": {
+                "summary": "WebSocket session on one upstream connection",
+                "responses": {
+                    "101": { "description": "Switching to the WebSocket protocol" },
+                    "400": error,
+                },
+            },
+        },
+        "/metrics": {
+            "get": {
+                "summary": "Prometheus metrics, when METRICS_ENABLED is true",
+                "responses": {
+                    "200": {
+                        "description": "Metrics in the Prometheus text format",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                    "404": error,
+                },
+            },
+        },
+        "/openapi.json": {
+            "get": {
+                "summary": "This document",
+                "responses": {
+                    "200": {
+                        "description": "OpenAPI document",
+                        "content": { "application/json": { "schema": { "type": "object" } } },
+                    },
+                },
+            },
+        },
+        "/scim/v2/ServiceProviderConfig": {
+            "get": {
+                "summary": "SCIM service provider configuration",
+                "responses": { "200": scim_resource },
+            },
+        },
+        "/scim/v2/Schemas": {
+            "get": {
+                "summary": "SCIM schemas of the mapped resources",
+                "responses": { "200": scim_resource },
+            },
+        },
+        "/scim/v2/ResourceTypes": {
+            "get": {
+                "summary": "SCIM resource types",
+                "responses": { "200": scim_resource },
+            },
+        },
+        "/scim/v2/{resource}": {
+            "parameters": [scim_resource_parameter],
+            "get": {
+                "summary": "List SCIM resources",
+                "security": scim_security,
+                "parameters": [
+                    { "name": "filter", "in": "query", "required": false, "schema": { "type": "string" } },
+                    { "name": "startIndex", "in": "query", "required": false, "schema": { "type": "integer", "minimum": 1 } },
+                    { "name": "count", "in": "query", "required": false, "schema": { "type": "integer", "minimum": 0 } },
+                ],
+                "responses": { "200": scim_resource, "400": scim_resource },
+            },
+            "post": {
+                "summary": "Create a SCIM resource",
+                "security": scim_security,
+                "requestBody": scim_body,
+                "responses": { "201": scim_resource, "400": scim_resource, "409": scim_resource },
+            },
+        },
+        "/scim/v2/{resource}/{id}": {
+            "parameters": [scim_resource_parameter, path_parameter("id")],
+            "get": {
+                "summary": "Read a SCIM resource",
+                "security": scim_security,
+                "responses": { "200": scim_resource, "404": scim_resource },
+            },
+            "put": {
+                "summary": "Replace the attributes of a SCIM resource given in the body",
+                "security": scim_security,
+                "requestBody": scim_body,
+                "responses": { "200": scim_resource, "404": scim_resource },
+            },
+            "patch": {
+                "summary": "Modify a SCIM resource",
+                "security": scim_security,
+                "requestBody": scim_body,
+                "responses": { "200": scim_resource, "404": scim_resource },
+            },
+            "delete": {
+                "summary": "Delete a SCIM resource",
+                "security": scim_security,
+                "responses": { "204": { "description": "Deleted" }, "404": scim_resource },
+            },
+        },
+    });
+
+    let header = |name: &str| json!({ "type": "apiKey", "in": "header", "name": name });
+    let schemas = gen
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, json!(schema)))
+        .collect::<Map<_, _>>();
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "jsonSchemaDialect": "https://json-schema.org/draft/2019-09/schema",
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "publicKey": header("X-Public-Key"),
+                "timestamp": header("X-Timestamp"),
+                "signature": header("X-Signature"),
+                "clientCertificate": { "type": "mutualTLS" },
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        approvals::ApprovalStore, graphql, health::HealthCache, revocation::RevocationList, routes,
+        scim::mapping::ScimConfig, AppState,
+    };
+
+    /// Paths of the route table, with `:param` segments written as `{param}`, and the
+    /// methods the router built by `app` answers on each, as listed in the `Allow`
+    /// header of its answer to a method no route has.
+    async fn router_routes() -> BTreeSet<(String, String)> {
+        let state = Arc::new(AppState {
+            authorized_keys: Mutex::new(Vec::new()),
+            client_identities: Mutex::new(Vec::new()),
+            scim_tokens: Mutex::new(Vec::new()),
+            revoked_keys: RevocationList::default(),
+            server_key: None,
+            approvals: ApprovalStore::default(),
+            scim: ScimConfig::default(),
+            graphql: graphql::schema(),
+            health: HealthCache::default(),
+            tls_error: Mutex::new(None),
+        });
+        let app = crate::app(state);
+
+        let mut routes = BTreeSet::new();
+        for (path, _) in routes::table() {
+            let (mut uri, mut documented) = (Vec::new(), Vec::new());
+            for segment in path.split('/') {
+                match segment.strip_prefix(':') {
+                    Some(param) => {
+                        uri.push("x".to_string());
+                        documented.push(format!("{{{}}}", param));
+                    }
+                    None => {
+                        uri.push(segment.to_string());
+                        documented.push(segment.to_string());
+                    }
+                }
+            }
+            let documented = documented.join("/");
+
+            let req = Request::builder()
+                .method(Method::TRACE)
+                .uri(uri.join("/"))
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+            let allow = res.headers()[header::ALLOW].to_str().unwrap();
+            for method in allow.split(',').map(|method| method.trim()) {
+                if method != "HEAD" {
+                    routes.insert((documented.clone(), method.to_lowercase()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[tokio::test]
+    async fn documents_every_route() {
+        let document = document();
+        let mut documented = BTreeSet::new();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                if method != "parameters" {
+                    documented.insert((path.clone(), method.clone()));
+                }
+            }
+        }
+        assert_eq!(documented, router_routes().await);
+    }
+}
@@ -0,0 +1,625 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Offset, Utc, Weekday};
+use ldap3_serde::Ldap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    context::{self, RequestContext},
+    dn::{self, Dn, Rdn},
+    types::query::{Mod, QueryCommand},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Allow,
+    Deny,
+    /// Held until another key approves it.
+    Approve,
+}
+
+/// One RDN of a DN pattern.
+#[derive(Debug, Clone)]
+enum RdnPattern {
+    /// `*`, any single RDN.
+    Any,
+    /// `**`, any number of RDNs, including none.
+    AnyDepth,
+    /// `uid=*`, any value of the attribute.
+    AnyValue(String),
+    Exact(Rdn),
+}
+
+/// DN with wildcards, e.g. `uid=*,ou=people,dc=example,dc=com` or
+/// `**,ou=staff,dc=example,dc=com`.
+#[derive(Debug, Clone)]
+pub struct DnPattern(Vec<RdnPattern>);
+
+impl DnPattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut rdns = Vec::new();
+        let mut start = 0;
+        let mut escaped = false;
+        let mut parts = Vec::new();
+        for (idx, c) in pattern.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                ',' if !escaped => {
+                    parts.push(&pattern[start..idx]);
+                    start = idx + 1;
+                }
+                _ => escaped = false,
+            }
+        }
+        parts.push(&pattern[start..]);
+
+        for part in parts.into_iter().map(str::trim) {
+            let rdn = match part {
+                "*" => RdnPattern::Any,
+                "**" => RdnPattern::AnyDepth,
+                _ => match part.strip_suffix("=*") {
+                    Some(attr) => RdnPattern::AnyValue(attr.trim().to_string()),
+                    None => match dn::parse(part) {
+                        Ok(Dn(mut val)) if val.len() == 1 => RdnPattern::Exact(val.remove(0)),
+                        Ok(_) => return Err(format!("Invalid DN pattern \"{}\"", pattern)),
+                        Err(err) => return Err(format!("Invalid DN pattern: {}", err)),
+                    },
+                },
+            };
+            rdns.push(rdn);
+        }
+
+        Ok(DnPattern(rdns))
+    }
+
+    pub fn matches(&self, dn: &Dn) -> bool {
+        fn matches(patterns: &[RdnPattern], rdns: &[Rdn]) -> bool {
+            match patterns.first() {
+                None => rdns.is_empty(),
+                Some(RdnPattern::AnyDepth) => {
+                    (0..=rdns.len()).any(|skip| matches(&patterns[1..], &rdns[skip..]))
+                }
+                Some(pattern) => match rdns.first() {
+                    None => false,
+                    Some(rdn) => {
+                        let matched = match pattern {
+                            RdnPattern::Any | RdnPattern::AnyDepth => true,
+                            RdnPattern::AnyValue(attr) => {
+                                rdn.0.len() == 1 && rdn.0[0].attr.eq_ignore_ascii_case(attr)
+                            }
+                            RdnPattern::Exact(val) => rdn.matches(val),
+                        };
+                        matched && matches(&patterns[1..], &rdns[1..])
+                    }
+                },
+            }
+        }
+        matches(&self.0, &dn.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DnPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        DnPattern::parse(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+fn utc() -> FixedOffset {
+    Utc.fix()
+}
+
+fn deserialize_offset<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FixedOffset, D::Error> {
+    let offset = String::deserialize(deserializer)?;
+    offset.parse().map_err(serde::de::Error::custom)
+}
+
+/// Days and hours a rule applies in. A window whose `to` is before its `from` spans
+/// midnight.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeWindow {
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub from: Option<NaiveTime>,
+    pub to: Option<NaiveTime>,
+    /// Offset the days and hours are given in, e.g. `+09:00`.
+    #[serde(default = "utc", deserialize_with = "deserialize_offset")]
+    pub utc_offset: FixedOffset,
+}
+
+impl TimeWindow {
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let now = now.with_timezone(&self.utc_offset);
+        if !self.days.is_empty() && !self.days.contains(&now.weekday()) {
+            return false;
+        }
+        let time = now.time();
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if from <= to => from <= time && time < to,
+            (Some(from), Some(to)) => from <= time || time < to,
+            (Some(from), None) => from <= time,
+            (None, Some(to)) => time < to,
+            (None, None) => true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    pub effect: Effect,
+    /// Identities the rule applies to, tagged as in [`RequestContext::identities`];
+    /// untagged ones stand for the fingerprint or comment of a key.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Command types, as in the `type` field of a command.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    #[serde(default)]
+    pub dn: Vec<DnPattern>,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    /// Groups the target entries must be a `member` of.
+    #[serde(default)]
+    pub member_of: Vec<String>,
+    /// Groups the target entries must not be a `member` of.
+    #[serde(default)]
+    pub not_member_of: Vec<String>,
+    pub time: Option<TimeWindow>,
+    /// Groups whose keys may approve commands held by an `approve` rule.
+    #[serde(default)]
+    pub approvers: Vec<String>,
+    /// Lets any other key approve commands held by an `approve` rule without
+    /// `approvers`, which otherwise must name at least one group.
+    #[serde(default)]
+    pub any_approver: bool,
+}
+
+fn default_effect() -> Effect {
+    Effect::Deny
+}
+
+/// Rules read from `POLICY_PATH`, evaluated in order before each command; the first
+/// matching rule decides.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Effect when no rule matches.
+    #[serde(default = "default_effect")]
+    pub default: Effect,
+    /// Named groups of tagged identities, as in `keys` of a rule.
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    pub rules: Vec<Rule>,
+    /// SHA-256 digest of the policy file, which approvals are tied to.
+    #[serde(skip)]
+    pub digest: String,
+}
+
+/// Why a rule did not apply to a command.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Mismatch {
+    pub rule: String,
+    pub reason: String,
+}
+
+/// Outcome of evaluating the policy for one command.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Decision {
+    pub command: String,
+    pub effect: Effect,
+    /// Rule that decided, or `None` if the default applied.
+    pub rule: Option<String>,
+    pub reason: String,
+    /// Groups that may approve the command, if it is held for approval.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub approvers: Vec<String>,
+    /// Whether any other key may approve the command, if it is held for approval.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub any_approver: bool,
+    /// Rules evaluated before the deciding one.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<Mismatch>,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let effect = match self.effect {
+            Effect::Allow => "Allowed",
+            Effect::Deny => "Denied",
+            Effect::Approve => "Held for approval",
+        };
+        match &self.rule {
+            Some(rule) => write!(f, "{} by policy rule \"{}\"", effect, rule),
+            None => write!(f, "{} by default policy", effect),
+        }
+    }
+}
+
+/// What a command acts on, as far as rules are concerned.
+struct Target {
+    command: &'static str,
+    dns: Vec<String>,
+    attributes: Vec<String>,
+    /// Whether the command reads every attribute, as a search for none, `*` or `+`
+    /// does.
+    every_attribute: bool,
+}
+
+impl Target {
+    fn of(command: &QueryCommand) -> Self {
+        let (dns, attributes) = match command {
+            QueryCommand::Search(cmd) => (vec![cmd.base.clone()], cmd.attrs.clone()),
+            QueryCommand::Add(cmd) => (
+                vec![cmd.dn.clone()],
+                cmd.attrs.iter().map(|(attr, _)| attr.clone()).collect(),
+            ),
+            QueryCommand::Compare(cmd) => (vec![cmd.dn.clone()], vec![cmd.attribute.clone()]),
+            QueryCommand::Delete(cmd) => (vec![cmd.dn.clone()], vec![]),
+            QueryCommand::Modify(cmd) => (
+                vec![cmd.dn.clone()],
+                cmd.changes
+                    .iter()
+                    .map(Mod::attr)
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            QueryCommand::ModifyDn(cmd) => (vec![cmd.dn.clone(), cmd.new_dn()], vec![]),
+            QueryCommand::Ensure(cmd) => (
+                vec![cmd.dn.clone()],
+                cmd.attrs.iter().map(|attr| attr.attr.clone()).collect(),
+            ),
+            QueryCommand::PasswordModify(cmd) => (
+                cmd.user_id
+                    .map(|user_id| user_id.strip_prefix("dn:").unwrap_or(user_id).to_string())
+                    .into_iter()
+                    .collect(),
+                vec!["userPassword".to_string()],
+            ),
+            QueryCommand::Bind(_)
+            | QueryCommand::Unbind(_)
+            | QueryCommand::Ldif(_)
+            | QueryCommand::WhoAmI(_)
+            | QueryCommand::ExtendedOperation(_) => (vec![], vec![]),
+        };
+        let every_attribute = match command {
+            QueryCommand::Search(cmd) => {
+                cmd.attrs.is_empty() || cmd.attrs.iter().any(|attr| attr == "*" || attr == "+")
+            }
+            _ => false,
+        };
+        Target {
+            command: command.name(),
+            dns,
+            attributes,
+            every_attribute,
+        }
+    }
+}
+
+/// An allow rule needs every target to satisfy a condition, deny and approve rules
+/// any of them.
+fn quantify<T>(effect: Effect, targets: &[T], predicate: impl Fn(&T) -> bool) -> bool {
+    match effect {
+        Effect::Allow => !targets.is_empty() && targets.iter().all(predicate),
+        Effect::Deny | Effect::Approve => targets.iter().any(predicate),
+    }
+}
+
+async fn is_member(ldap: &mut Ldap, group: &str, dn: &str) -> bool {
+    match ldap.compare(group, "member", dn).await {
+        Ok(res) => res.0.rc == 6,
+        Err(err) => {
+            tracing::warn!(
+                "Failed to check membership of {} in {}: {:?}",
+                dn,
+                group,
+                err
+            );
+            false
+        }
+    }
+}
+
+impl Rule {
+    fn applies_to_key(&self, policy: &Policy, context: &RequestContext) -> bool {
+        let identities = context.identities();
+        if identities.is_empty() {
+            return false;
+        }
+
+        (self.keys.is_empty() && self.groups.is_empty())
+            || identities.iter().any(|id| self.keys.contains(id))
+            || policy.in_groups(context, &self.groups)
+    }
+
+    fn matches_attributes(&self, target: &Target) -> bool {
+        if self.attributes.is_empty() {
+            return true;
+        }
+        // Reading every attribute reads the listed ones, and others besides.
+        match (target.every_attribute, self.effect) {
+            (true, Effect::Allow) => false,
+            (true, Effect::Deny | Effect::Approve) => true,
+            (false, effect) => quantify(effect, &target.attributes, |attr| {
+                self.attributes
+                    .iter()
+                    .any(|listed| listed.eq_ignore_ascii_case(attr))
+            }),
+        }
+    }
+
+    /// Checks every condition of the rule, returning the first one that fails.
+    async fn check(
+        &self,
+        policy: &Policy,
+        context: &RequestContext,
+        target: &Target,
+        now: DateTime<Utc>,
+        ldap: &mut Ldap,
+    ) -> Result<(), String> {
+        if !self.applies_to_key(policy, context) {
+            return Err("key is not listed".to_string());
+        }
+        if !self.commands.is_empty()
+            && !self
+                .commands
+                .iter()
+                .any(|command| command.eq_ignore_ascii_case(target.command))
+        {
+            return Err(format!("command \"{}\" is not listed", target.command));
+        }
+        if let Some(time) = &self.time {
+            if !time.contains(now) {
+                return Err("outside of the time window".to_string());
+            }
+        }
+
+        if !self.dn.is_empty() {
+            let dns = match target
+                .dns
+                .iter()
+                .map(|val| dn::parse(val))
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(val) => val,
+                Err(err) => return Err(err.to_string()),
+            };
+            if !quantify(self.effect, &dns, |dn| {
+                self.dn.iter().any(|pattern| pattern.matches(dn))
+            }) {
+                return Err("DN does not match".to_string());
+            }
+        }
+        if !self.matches_attributes(target) {
+            return Err("attributes do not match".to_string());
+        }
+
+        if !self.member_of.is_empty() || !self.not_member_of.is_empty() {
+            let mut satisfied = Vec::with_capacity(target.dns.len());
+            for dn in target.dns.iter() {
+                let mut member = true;
+                for group in self.member_of.iter() {
+                    member = is_member(ldap, group, dn).await;
+                    if member {
+                        break;
+                    }
+                }
+                let mut excluded = false;
+                for group in self.not_member_of.iter() {
+                    excluded = is_member(ldap, group, dn).await;
+                    if excluded {
+                        break;
+                    }
+                }
+                satisfied.push(member && !excluded);
+            }
+            if !quantify(self.effect, &satisfied, |val| *val) {
+                return Err("group membership does not match".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Policy {
+    /// Decides whether the key of the current request may run `command`. Group
+    /// memberships are looked up over `ldap`.
+    pub async fn evaluate(&self, ldap: &mut Ldap, command: &QueryCommand<'_>) -> Decision {
+        if let QueryCommand::Ldif(cmd) = command {
+            let mut held = None;
+            for record in cmd.records.iter() {
+                let decision = Box::pin(self.evaluate(ldap, record)).await;
+                let decision = Decision {
+                    command: command.name().to_string(),
+                    reason: format!("{} record: {}", decision.command, decision.reason),
+                    ..decision
+                };
+                match decision.effect {
+                    Effect::Deny => return decision,
+                    Effect::Approve if held.is_none() => held = Some(decision),
+                    _ => {}
+                }
+            }
+            return held.unwrap_or_else(|| Decision {
+                command: command.name().to_string(),
+                effect: Effect::Allow,
+                rule: None,
+                reason: "Every record is allowed".to_string(),
+                approvers: Vec::new(),
+                any_approver: false,
+                skipped: Vec::new(),
+            });
+        }
+
+        let context = context::current();
+        let target = Target::of(command);
+        let now = Utc::now();
+        let mut skipped = Vec::new();
+        for rule in self.rules.iter() {
+            match rule.check(self, &context, &target, now, ldap).await {
+                Ok(()) => {
+                    return Decision {
+                        command: target.command.to_string(),
+                        effect: rule.effect,
+                        rule: Some(rule.name.clone()),
+                        reason: "Every condition matched".to_string(),
+                        approvers: rule.approvers.clone(),
+                        any_approver: rule.any_approver,
+                        skipped,
+                    }
+                }
+                Err(reason) => skipped.push(Mismatch {
+                    rule: rule.name.clone(),
+                    reason,
+                }),
+            }
+        }
+
+        Decision {
+            command: target.command.to_string(),
+            effect: self.default,
+            rule: None,
+            reason: "No rule matched".to_string(),
+            approvers: Vec::new(),
+            any_approver: false,
+            skipped,
+        }
+    }
+
+    /// Evaluates the policy for a batch without running any of it. Group memberships
+    /// are looked up with the identity `ldap` is bound as, even after a bind in the
+    /// batch; every command is evaluated again as the batch runs.
+    pub async fn review(&self, ldap: &mut Ldap, commands: &[QueryCommand<'_>]) -> Vec<Decision> {
+        let mut decisions = Vec::with_capacity(commands.len());
+        for command in commands.iter() {
+            decisions.push(self.evaluate(ldap, command).await);
+        }
+        decisions
+    }
+
+    /// Whether the key in `context` belongs to one of `groups`.
+    pub fn in_groups(&self, context: &RequestContext, groups: &[String]) -> bool {
+        let identities = context.identities();
+        groups.iter().any(|group| {
+            self.groups
+                .get(group)
+                .is_some_and(|keys| identities.iter().any(|id| keys.contains(id)))
+        })
+    }
+}
+
+static POLICY: RwLock<Option<Arc<Policy>>> = RwLock::new(None);
+
+/// Reads the policy from `POLICY_PATH`, or `None` if it is not set.
+pub fn load() -> Result<Option<Policy>, String> {
+    let path = match dotenv::var("POLICY_PATH") {
+        Ok(val) => val,
+        Err(_) => return Ok(None),
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+
+    let mut policy: Policy = match serde_json::from_str(&content) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to parse {}: {}", path, err)),
+    };
+    policy.digest = hex::encode(Sha256::digest(content.as_bytes()));
+    for rule in policy.rules.iter_mut() {
+        rule.keys = rule
+            .keys
+            .iter()
+            .map(|id| context::tag_identity(id))
+            .collect();
+    }
+    for keys in policy.groups.values_mut() {
+        *keys = keys.iter().map(|id| context::tag_identity(id)).collect();
+    }
+
+    // Who may approve has to be spelled out, rather than falling back to any key.
+    if policy.default == Effect::Approve {
+        return Err(format!(
+            "Invalid default of {}: approve names no approvers, end the rules with an approve rule instead",
+            path
+        ));
+    }
+    for rule in policy.rules.iter() {
+        if rule.effect == Effect::Approve && rule.approvers.is_empty() && !rule.any_approver {
+            return Err(format!(
+                "Invalid rule {} of {}: an approve rule needs approvers or any_approver",
+                rule.name, path
+            ));
+        }
+    }
+
+    Ok(Some(policy))
+}
+
+pub fn set(policy: Option<Policy>) {
+    *POLICY.write().unwrap_or_else(|err| err.into_inner()) = policy.map(Arc::new);
+}
+
+/// Policy in effect, if one is configured.
+pub fn current() -> Option<Arc<Policy>> {
+    POLICY.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(effect: &str) -> Rule {
+        serde_json::from_value(serde_json::json!({
+            "name": "passwords",
+            "effect": effect,
+            "attributes": ["userPassword"],
+            "any_approver": true,
+        }))
+        .unwrap()
+    }
+
+    fn search(attrs: &[&str]) -> Target {
+        let command = serde_json::json!({
+            "type": "search",
+            "base": "dc=example,dc=org",
+            "scope": "Subtree",
+            "filter": "(objectClass=*)",
+            "attrs": attrs,
+        })
+        .to_string();
+        Target::of(&serde_json::from_str::<QueryCommand>(&command).unwrap())
+    }
+
+    #[test]
+    fn matches_listed_attributes() {
+        assert!(rule("deny").matches_attributes(&search(&["cn", "userPassword"])));
+        assert!(!rule("deny").matches_attributes(&search(&["cn"])));
+        assert!(rule("allow").matches_attributes(&search(&["userPassword"])));
+        assert!(!rule("allow").matches_attributes(&search(&["cn", "userPassword"])));
+    }
+
+    #[test]
+    fn wildcard_searches_read_every_listed_attribute() {
+        for attrs in [&[][..], &["*"], &["+"], &["cn", "*"]] {
+            let target = search(attrs);
+            assert!(rule("deny").matches_attributes(&target), "{:?}", attrs);
+            assert!(rule("approve").matches_attributes(&target), "{:?}", attrs);
+            assert!(!rule("allow").matches_attributes(&target), "{:?}", attrs);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use base64::Engine;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use ssh_key::{public::KeyData, Certificate, PublicKey};
+
+const KRL_MAGIC: &[u8] = b"SSHKRL\n\0";
+
+/// Certificates revoked by a KRL, issued by `ca` or, without one, by any CA.
+struct RevokedCertificates {
+    ca: Option<Vec<u8>>,
+    /// Inclusive ranges of serial numbers.
+    serials: Vec<(u64, u64)>,
+    key_ids: HashSet<String>,
+}
+
+/// Keys and certificates that may no longer sign requests.
+#[derive(Default)]
+pub struct RevokedKeys {
+    /// Public key blobs.
+    keys: HashSet<Vec<u8>>,
+    sha1: HashSet<Vec<u8>>,
+    sha256: HashSet<Vec<u8>>,
+    certificates: Vec<RevokedCertificates>,
+}
+
+fn key_blob(key: &KeyData) -> Vec<u8> {
+    PublicKey::from(key.clone()).to_bytes().unwrap_or_default()
+}
+
+impl RevokedKeys {
+    pub fn is_key_revoked(&self, key: &KeyData) -> bool {
+        let blob = key_blob(key);
+        self.keys.contains(&blob)
+            || self.sha1.contains(Sha1::digest(&blob).as_slice())
+            || self.sha256.contains(Sha256::digest(&blob).as_slice())
+    }
+
+    /// Whether the certificate, the key it certifies or the CA that signed it is revoked.
+    pub fn is_certificate_revoked(&self, certificate: &Certificate) -> bool {
+        if self.is_key_revoked(certificate.public_key())
+            || self.is_key_revoked(certificate.signature_key())
+        {
+            return true;
+        }
+
+        let ca = key_blob(certificate.signature_key());
+        self.certificates.iter().any(|revoked| {
+            revoked.ca.as_ref().is_none_or(|val| *val == ca)
+                && (revoked
+                    .serials
+                    .iter()
+                    .any(|(min, max)| (*min..=*max).contains(&certificate.serial()))
+                    || revoked.key_ids.contains(certificate.key_id()))
+        })
+    }
+}
+
+/// Reads the fields of an OpenSSH KRL.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("Truncated KRL".to_string());
+        }
+        let (val, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(val)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+fn parse_certificates(mut section: Reader) -> Result<RevokedCertificates, String> {
+    let ca = section.string()?;
+    section.string()?;
+    let mut revoked = RevokedCertificates {
+        ca: (!ca.is_empty()).then(|| ca.to_vec()),
+        serials: Vec::new(),
+        key_ids: HashSet::new(),
+    };
+
+    while !section.is_empty() {
+        let kind = section.u8()?;
+        let mut data = Reader(section.string()?);
+        match kind {
+            0x20 => {
+                while !data.is_empty() {
+                    let serial = data.u64()?;
+                    revoked.serials.push((serial, serial));
+                }
+            }
+            0x21 => {
+                let min = data.u64()?;
+                let max = data.u64()?;
+                revoked.serials.push((min, max));
+            }
+            0x22 => {
+                let offset = data.u64()?;
+                let bitmap = data.string()?;
+                for (index, byte) in bitmap.iter().rev().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (1 << bit) != 0 {
+                            let serial = offset + (index * 8 + bit) as u64;
+                            revoked.serials.push((serial, serial));
+                        }
+                    }
+                }
+            }
+            0x23 => {
+                while !data.is_empty() {
+                    let key_id = String::from_utf8_lossy(data.string()?).to_string();
+                    revoked.key_ids.insert(key_id);
+                }
+            }
+            _ => return Err(format!("Unknown KRL certificate section {}", kind)),
+        }
+    }
+    Ok(revoked)
+}
+
+/// Parses a binary OpenSSH KRL, as written by `ssh-keygen -k`. Its signature is not
+/// checked, the file being trusted like `authorized_keys`.
+fn parse_krl(data: &[u8]) -> Result<RevokedKeys, String> {
+    let mut reader = Reader(&data[KRL_MAGIC.len()..]);
+    let version = reader.u32()?;
+    if version != 1 {
+        return Err(format!("Unsupported KRL format version {}", version));
+    }
+    // KRL version, generation date, flags, reserved and comment
+    reader.u64()?;
+    reader.u64()?;
+    reader.u64()?;
+    reader.string()?;
+    reader.string()?;
+
+    let mut revoked = RevokedKeys::default();
+    while !reader.is_empty() {
+        let kind = reader.u8()?;
+        let mut section = Reader(reader.string()?);
+        match kind {
+            1 => revoked.certificates.push(parse_certificates(section)?),
+            2 | 3 | 5 => {
+                let set = match kind {
+                    2 => &mut revoked.keys,
+                    3 => &mut revoked.sha1,
+                    _ => &mut revoked.sha256,
+                };
+                while !section.is_empty() {
+                    set.insert(section.string()?.to_vec());
+                }
+            }
+            // Signatures come last.
+            4 => break,
+            _ => return Err(format!("Unknown KRL section {}", kind)),
+        }
+    }
+    Ok(revoked)
+}
+
+/// Parses SHA-256 fingerprints, one per line as printed by `ssh-keygen -l`, with
+/// blank lines and `#` comments ignored.
+fn parse_fingerprints(content: &str) -> Result<RevokedKeys, String> {
+    let mut revoked = RevokedKeys::default();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // `ssh-keygen -l` puts the key size before the fingerprint.
+        let mut fields = line.split_whitespace().peekable();
+        fields.next_if(|val| val.bytes().all(|b| b.is_ascii_digit()));
+        let fingerprint = match fields.next() {
+            Some(val) => val,
+            None => continue,
+        };
+        let hash = match fingerprint.strip_prefix("SHA256:") {
+            Some(val) => base64::engine::general_purpose::STANDARD_NO_PAD
+                .decode(val.trim_end_matches('='))
+                .ok(),
+            None => None,
+        };
+        match hash {
+            Some(val) if val.len() == 32 => revoked.sha256.insert(val),
+            _ => return Err(format!("Invalid fingerprint {}", fingerprint)),
+        };
+    }
+    Ok(revoked)
+}
+
+fn read(path: &str) -> Result<RevokedKeys, String> {
+    let data = match std::fs::read(path) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+
+    let revoked = match data.starts_with(KRL_MAGIC) {
+        true => parse_krl(&data),
+        false => match std::str::from_utf8(&data) {
+            Ok(val) => parse_fingerprints(val),
+            Err(_) => Err("Not a KRL or a list of fingerprints".to_string()),
+        },
+    };
+    match revoked {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to parse {}: {}", path, err)),
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|val| val.modified()).ok()
+}
+
+/// Revoked keys read from `REVOKED_KEYS_PATH`, re-read whenever the file changes so
+/// a revocation applies to the next request.
+#[derive(Default)]
+pub struct RevocationList {
+    path: Option<String>,
+    state: Mutex<(Option<SystemTime>, Arc<RevokedKeys>)>,
+}
+
+impl RevocationList {
+    pub fn load() -> Result<Self, String> {
+        let path = match dotenv::var("REVOKED_KEYS_PATH") {
+            Ok(val) => val,
+            Err(_) => return Ok(RevocationList::default()),
+        };
+
+        let modified = modified(&path);
+        let revoked = read(&path)?;
+        Ok(RevocationList {
+            path: Some(path),
+            state: Mutex::new((modified, Arc::new(revoked))),
+        })
+    }
+
+    /// Re-reads the file, keeping the previous list if it cannot be read.
+    pub fn reload(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.0 = modified(path);
+        state.1 = Arc::new(read(path)?);
+        Ok(())
+    }
+
+    pub fn current(&self) -> Arc<RevokedKeys> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(path) = &self.path {
+            let modified = modified(path);
+            if modified.is_some() && modified != state.0 {
+                state.0 = modified;
+                match read(path) {
+                    Ok(val) => {
+                        state.1 = Arc::new(val);
+                        tracing::info!("Revoked keys reloaded");
+                    }
+                    Err(err) => tracing::error!(
+                        "Failed to reload revoked keys, keeping the previous list: {}",
+                        err
+                    ),
+                }
+            }
+        }
+        state.1.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by `ssh-keygen -k -s ca.pub` for `serial: 5-10` and `id: nightly`, then
+    /// updated with `-u` to revoke `REVOKED` and the SHA-256 fingerprint of `HASHED`.
+    const KRL: &str = "5353484b524c0a00000000010000000000000000000000006ad53ad50000000000000000000000000000000001000000\
+        5d000000330000000b7373682d6564323535313900000020e3ff6766716d2b1ead3a18ba90ebfbcf2e55fc451a63c54e9d3474a634581a79\
+        00000000220000000d0000000000000005000000013f230000000b000000076e696768746c790200000037000000330000000b7373682d65\
+        643235353139000000209801a2187b688dd19da86ac9334e829f014c6b2d07988a40302093c7d8fb2701050000002400000020e9c068e9c0\
+        c51533135fa8ccfeb63d0af5165705825c47a633c9b58090307761";
+
+    const REVOKED: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJgBohh7aI3RnahqyTNOgp8BTGstB5iKQDAgk8fY+ycB revoked";
+    const HASHED: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIARKjjJxHgfpe6nJSzg2iorjb7OIy1F/Og+vruhbMKpy hashed";
+    const GOOD: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIEwFapj22P0USgCqQOVIIVT6nhWpw3TEcxZ0eQkcP+G1 good";
+
+    /// Serial 7, key id `deploy-1`.
+    const CERT_SERIAL: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIMsmIUeSway7Esjm52Tlk5i1wxG4NjU7pXnP6IVBVlJ/AAAAIAbV07+J/YYkSnys6fBpSJJ1sEuAybrqEuGA2284b3YVAAAAAAAAAAcAAAABAAAACGRlcGxveS0xAAAACQAAAAVhbGljZQAAAABeC+EAAAAAAPKlI4AAAAAAAAAAggAAABVwZXJtaXQtWDExLWZvcndhcmRpbmcAAAAAAAAAF3Blcm1pdC1hZ2VudC1mb3J3YXJkaW5nAAAAAAAAABZwZXJtaXQtcG9ydC1mb3J3YXJkaW5nAAAAAAAAAApwZXJtaXQtcHR5AAAAAAAAAA5wZXJtaXQtdXNlci1yYwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACDj/2dmcW0rHq06GLqQ6/vPLlX8RRpjxU6dNHSmNFgaeQAAAFMAAAALc3NoLWVkMjU1MTkAAABAc/S7SN7bDM1GZhuSCHxo4rUe/qvET77fnccZE9vJOanBfbeMu1XPXT6xZ4SY7CYG/J/19+wzHES93WMrJqA1Aw== cert1";
+    /// Serial 100, key id `build-2`.
+    const CERT_VALID: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIJCVQWgGuYoGId6yWpLmsRu9fcsNNejwEDPZIAWCJcTFAAAAIAYwIJbxT+7VsJ/Ul625aGrkWBNgIPekZPHoLYVmfgMQAAAAAAAAAGQAAAABAAAAB2J1aWxkLTIAAAAJAAAABWFsaWNlAAAAAF4L4QAAAAAA8qUjgAAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIOP/Z2ZxbSserToYupDr+88uVfxFGmPFTp00dKY0WBp5AAAAUwAAAAtzc2gtZWQyNTUxOQAAAECUs1u0grCh/hhkxoL0ET18QtT6xISzaYczQTj98ucuqWYDNZUT6zkIH1il1rvZxQfiNxJmwwtiIe5BROU3I9YI cert2";
+    /// Serial 42, key id `nightly`.
+    const CERT_KEY_ID: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIKED/hP+87MeGKCZ/8jHgNnxSfLUj6wOi2ZILGsO7lgmAAAAIOaWEuGlCVZB42kl3LxQRZz1OScq7KHjJFlo6kGRrXpnAAAAAAAAACoAAAABAAAAB25pZ2h0bHkAAAAJAAAABWFsaWNlAAAAAF4L4QAAAAAA8qUjgAAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIOP/Z2ZxbSserToYupDr+88uVfxFGmPFTp00dKY0WBp5AAAAUwAAAAtzc2gtZWQyNTUxOQAAAEBmzsORQafeH4mcvlWw/1Odi+Bkcpth1hFhr8qgGUVM3+zdt2L8ufHW8hPqVqkY1PFAqiBw7Cp3zKlJh83h9nIB cert3";
+
+    fn key(line: &str) -> KeyData {
+        PublicKey::from_openssh(line).unwrap().key_data().clone()
+    }
+
+    fn certificate(line: &str) -> Certificate {
+        Certificate::from_openssh(line).unwrap()
+    }
+
+    fn krl() -> RevokedKeys {
+        parse_krl(&hex::decode(KRL.replace(' ', "")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn revokes_keys_by_blob_and_hash() {
+        let revoked = krl();
+        assert!(revoked.is_key_revoked(&key(REVOKED)));
+        assert!(revoked.is_key_revoked(&key(HASHED)));
+        assert!(!revoked.is_key_revoked(&key(GOOD)));
+    }
+
+    #[test]
+    fn revokes_certificates_by_serial_and_key_id() {
+        let revoked = krl();
+        assert!(revoked.is_certificate_revoked(&certificate(CERT_SERIAL)));
+        assert!(revoked.is_certificate_revoked(&certificate(CERT_KEY_ID)));
+        assert!(!revoked.is_certificate_revoked(&certificate(CERT_VALID)));
+    }
+
+    /// KRL of a single certificate section for any CA.
+    fn krl_with(kind: u8, data: &[u8]) -> Vec<u8> {
+        let string = |val: &[u8]| [&(val.len() as u32).to_be_bytes()[..], val].concat();
+        let mut section = [string(b""), string(b"")].concat();
+        section.push(kind);
+        section.extend(string(data));
+
+        let mut krl = KRL_MAGIC.to_vec();
+        krl.extend(1u32.to_be_bytes());
+        krl.extend([0; 24]);
+        krl.extend(string(b""));
+        krl.extend(string(b""));
+        krl.push(1);
+        krl.extend(string(&section));
+        krl
+    }
+
+    #[test]
+    fn parses_serial_lists_and_ranges() {
+        let list = [7u64.to_be_bytes(), 9u64.to_be_bytes()].concat();
+        let revoked = parse_krl(&krl_with(0x20, &list)).unwrap();
+        assert_eq!(revoked.certificates[0].serials, vec![(7, 7), (9, 9)]);
+        assert!(revoked.certificates[0].ca.is_none());
+        assert!(revoked.is_certificate_revoked(&certificate(CERT_SERIAL)));
+
+        let range = [90u64.to_be_bytes(), 110u64.to_be_bytes()].concat();
+        let revoked = parse_krl(&krl_with(0x21, &range)).unwrap();
+        assert!(revoked.is_certificate_revoked(&certificate(CERT_VALID)));
+        assert!(!revoked.is_certificate_revoked(&certificate(CERT_SERIAL)));
+    }
+
+    #[test]
+    fn rejects_malformed_krl() {
+        let mut krl = krl_with(0x21, &[0; 16]);
+        krl.truncate(krl.len() - 4);
+        assert_eq!(parse_krl(&krl).err().as_deref(), Some("Truncated KRL"));
+
+        let mut krl = krl_with(0x21, &[0; 16]);
+        krl[KRL_MAGIC.len() + 3] = 2;
+        assert!(parse_krl(&krl).is_err());
+
+        assert!(parse_krl(&krl_with(0x30, &[])).is_err());
+    }
+
+    #[test]
+    fn parses_fingerprint_lists() {
+        // As printed by `ssh-keygen -l`, and as the bare fingerprint.
+        for line in [
+            "256 SHA256:6cBo6cDFFTMTX6jM/rY9CvUWVwWCXEemM8m1gJAwd2E hashed (ED25519)",
+            "SHA256:6cBo6cDFFTMTX6jM/rY9CvUWVwWCXEemM8m1gJAwd2E",
+        ] {
+            let content = format!("# revoked 2024-01-01\n\n{}\n", line);
+            let revoked = parse_fingerprints(&content).unwrap();
+            assert!(revoked.is_key_revoked(&key(HASHED)));
+            assert!(!revoked.is_key_revoked(&key(GOOD)));
+        }
+
+        assert!(parse_fingerprints("SHA256:tooshort").is_err());
+        assert!(parse_fingerprints("MD5:00:11:22").is_err());
+    }
+}
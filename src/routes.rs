@@ -1,2 +1,64 @@
+use std::sync::Arc;
+
+use axum::routing::{get, post, MethodRouter};
+
+use crate::AppState;
+
+pub mod approvals;
+pub mod entries;
+pub mod graphql;
+pub mod health;
 pub mod index;
+pub mod metrics;
+pub mod openapi;
+pub mod policy;
 pub mod query;
+pub mod scim;
+pub mod session;
+pub mod well_known;
+
+/// Every path the API serves, with its handlers. `openapi::document` describes each of
+/// them.
+pub fn table() -> Vec<(&'static str, MethodRouter<Arc<AppState>>)> {
+    vec![
+        ("/", get(index::get)),
+        ("/.well-known/ldap-rest-key", get(well_known::server_key)),
+        (
+            "/approvals/:id",
+            get(approvals::get)
+                .post(approvals::post)
+                .delete(approvals::delete),
+        ),
+        ("/graphql", post(graphql::post)),
+        ("/health/live", get(health::live)),
+        ("/health/ready", get(health::ready)),
+        ("/metrics", get(metrics::get)),
+        ("/openapi.json", get(openapi::get)),
+        ("/policy/explain", post(policy::explain)),
+        ("/query", post(query::post)),
+        ("/session", get(session::get)),
+        (
+            "/entries/:dn",
+            get(entries::get)
+                .put(entries::put)
+                .patch(entries::patch)
+                .delete(entries::delete),
+        ),
+        ("/entries/:dn/children", get(entries::children)),
+        ("/entries/:dn/changes", get(entries::changes)),
+        (
+            "/scim/v2/ServiceProviderConfig",
+            get(scim::service_provider_config),
+        ),
+        ("/scim/v2/Schemas", get(scim::schemas)),
+        ("/scim/v2/ResourceTypes", get(scim::resource_types)),
+        ("/scim/v2/:resource", get(scim::list).post(scim::post)),
+        (
+            "/scim/v2/:resource/:id",
+            get(scim::get)
+                .put(scim::put)
+                .patch(scim::patch)
+                .delete(scim::delete),
+        ),
+    ]
+}
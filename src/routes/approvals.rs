@@ -0,0 +1,152 @@
+mod delete;
+mod get;
+mod post;
+
+pub use self::delete::delete;
+pub use self::get::get;
+pub use self::post::post;
+
+use axum::http::StatusCode;
+
+use crate::{approvals::PendingRequest, context::RequestContext, policy, types::routes::Response};
+
+/// Whether two callers share a tagged identity, or the subject of their client
+/// certificate. The same person signing with a key and presenting a certificate has
+/// two fingerprints but one identity.
+fn same_caller(a: &RequestContext, b: &RequestContext) -> bool {
+    let identities = b.identities();
+    a.identities().iter().any(|id| identities.contains(id))
+        || a.certificate.is_some() && a.certificate == b.certificate
+}
+
+/// Checks that `approver` may approve a held batch: it must be another caller, in the
+/// approver groups of every rule that held the batch unless the rule lets any other
+/// key approve.
+fn check_approver(request: &PendingRequest, approver: &RequestContext) -> Result<(), Response> {
+    if same_caller(approver, &request.requester) {
+        return Err(Response::error(
+            StatusCode::FORBIDDEN,
+            "A batch cannot be approved by the identity that submitted it",
+        ));
+    }
+
+    let policy = policy::current();
+    let allowed = request.held().all(|decision| {
+        decision.any_approver
+            || !decision.approvers.is_empty()
+                && policy
+                    .as_ref()
+                    .is_some_and(|policy| policy.in_groups(approver, &decision.approvers))
+    });
+    match allowed {
+        true => Ok(()),
+        false => Err(Response::error(
+            StatusCode::FORBIDDEN,
+            "Key is not allowed to approve this batch",
+        )),
+    }
+}
+
+fn not_found() -> Response {
+    Response::error(StatusCode::NOT_FOUND, "Approval request not found")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        context::Credential,
+        policy::{Decision, Effect, Policy},
+    };
+
+    fn caller(fingerprint: &str, comment: &str) -> RequestContext {
+        RequestContext {
+            key_fingerprint: Some(fingerprint.to_string()),
+            key_comment: Some(comment.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn request(any_approver: bool) -> PendingRequest {
+        PendingRequest {
+            id: "1".to_string(),
+            requester: caller("SHA256:key", "alice"),
+            data: String::new(),
+            commands: Vec::new(),
+            policy: "digest".to_string(),
+            decisions: vec![Decision {
+                command: "delete".to_string(),
+                effect: Effect::Approve,
+                rule: Some("ou-delete".to_string()),
+                reason: String::new(),
+                approvers: Vec::new(),
+                any_approver,
+                skipped: Vec::new(),
+            }],
+            created_at: Utc::now(),
+            expires_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn rejects_same_identity_with_another_fingerprint() {
+        let mut approver = caller("SHA256:certificate", "alice");
+        approver.certificate = Some("CN=alice".to_string());
+        assert!(check_approver(&request(true), &approver).is_err());
+
+        let mut requester = caller("SHA256:other", "bob");
+        requester.certificate = Some("CN=alice".to_string());
+        assert!(same_caller(&approver, &requester));
+    }
+
+    #[test]
+    fn tells_identities_of_different_kinds_apart() {
+        let requester = caller("SHA256:key", "alice");
+        let approver = RequestContext {
+            credential: Credential::SshCertificate,
+            key_fingerprint: Some("SHA256:certificate".to_string()),
+            key_comment: Some("alice".to_string()),
+            principals: vec!["alice".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            approver.identities(),
+            vec!["key:SHA256:certificate", "principal:alice"]
+        );
+        assert!(!same_caller(&approver, &requester));
+
+        let token = RequestContext {
+            credential: Credential::Token,
+            ..caller("SHA256:token", "alice")
+        };
+        assert!(!same_caller(&token, &requester));
+    }
+
+    #[test]
+    fn approval_covers_only_the_holding_rules_of_its_policy() {
+        let request = request(true);
+        let approval = request.approve(Some("SHA256:other".to_string()));
+        let policy = |digest: &str| Policy {
+            default: Effect::Deny,
+            groups: Default::default(),
+            rules: Vec::new(),
+            digest: digest.to_string(),
+        };
+        let decision = |rule: &str| Decision {
+            rule: Some(rule.to_string()),
+            ..request.decisions[0].clone()
+        };
+        assert!(approval.covers(&policy("digest"), &decision("ou-delete")));
+        assert!(!approval.covers(&policy("reloaded"), &decision("ou-delete")));
+        assert!(!approval.covers(&policy("digest"), &decision("other")));
+    }
+
+    #[test]
+    fn needs_approvers_unless_any_approver() {
+        let approver = caller("SHA256:other", "bob");
+        assert!(check_approver(&request(false), &approver).is_err());
+        assert!(check_approver(&request(true), &approver).is_ok());
+    }
+}
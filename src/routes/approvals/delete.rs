@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    approvals::PendingResponse,
+    audit,
+    auth::SignedRequest,
+    context,
+    types::routes::{DataResponse, Response},
+    AppState,
+};
+
+use super::{check_approver, not_found};
+
+/// Rejects a held batch, or withdraws it when called by the key that submitted it.
+pub async fn delete(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _: SignedRequest,
+) -> Response {
+    let request = match state.approvals.get(&id).await {
+        Some(val) => val,
+        None => return not_found(),
+    };
+
+    let caller = context::current();
+    let event = match caller.key_fingerprint == request.requester.key_fingerprint {
+        true => "withdrawn",
+        false => match check_approver(&request, &caller) {
+            Ok(()) => "rejected",
+            Err(err) => return err,
+        },
+    };
+
+    let request = match state.approvals.take(&id).await {
+        Some(val) => val,
+        None => return not_found(),
+    };
+    tracing::info!(approval = %request.id, "Approval request {}", event);
+    audit::record_approval(event, &request).await;
+
+    Response {
+        status: StatusCode::OK,
+        body: Box::new(DataResponse {
+            result: true,
+            data: PendingResponse::from(&request),
+        }),
+    }
+}
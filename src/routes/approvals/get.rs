@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    approvals::PendingResponse,
+    auth::SignedRequest,
+    types::routes::{DataResponse, Response},
+    AppState,
+};
+
+use super::not_found;
+
+pub async fn get(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _: SignedRequest,
+) -> Response {
+    match state.approvals.get(&id).await {
+        Some(request) => Response {
+            status: StatusCode::OK,
+            body: Box::new(DataResponse {
+                result: true,
+                data: PendingResponse::from(&request),
+            }),
+        },
+        None => not_found(),
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use tracing::Instrument;
+
+use crate::{
+    audit,
+    auth::{self, SignedRequest},
+    context::{self, RequestContext},
+    routes::query::{execute, QueryData},
+    types::routes::Response,
+    upstream, AppState,
+};
+
+use super::{check_approver, not_found};
+
+/// Approves a held batch and executes it with the identity and restrictions of the
+/// key that submitted it, answering like `/query`.
+pub async fn post(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    _: SignedRequest,
+) -> Response {
+    let request = match state.approvals.get(&id).await {
+        Some(val) => val,
+        None => return not_found(),
+    };
+
+    let approver = context::current();
+    if let Err(err) = check_approver(&request, &approver) {
+        return err;
+    }
+
+    // The batch runs as its submitter, whose key may have been revoked, removed or
+    // expired while it was held, and whose restrictions may have changed. Its own span
+    // keeps the fingerprint of the approver on the span of the request.
+    let requester = context::scope(request.requester.clone(), async {
+        auth::reauthorize(&state).is_ok().then(context::current)
+    })
+    .instrument(tracing::info_span!("reauthorize"))
+    .await;
+    let requester = match requester {
+        Some(val) => val,
+        None => {
+            return Response::error(
+                StatusCode::FORBIDDEN,
+                "The key that submitted the batch is no longer authorized",
+            )
+        }
+    };
+
+    let request = match state.approvals.take(&id).await {
+        Some(val) => val,
+        None => return not_found(),
+    };
+    tracing::info!(approval = %request.id, "Approval request approved");
+    audit::record_approval("approved", &request).await;
+
+    let context = RequestContext {
+        request_id: approver.request_id.clone(),
+        approval: Some(request.approve(approver.key_fingerprint.clone())),
+        ..requester
+    };
+    let data = request.data;
+    context::scope(context, async move {
+        let query = match serde_json::from_str::<QueryData>(&data) {
+            Ok(val) => val,
+            Err(err) => {
+                return Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to parse held request: {:?}", err),
+                )
+            }
+        };
+
+        let mut ldap = match upstream::connect_named(query.host.clone(), query.port).await {
+            Ok(val) => val,
+            Err(err) => return err,
+        };
+        execute(&mut ldap, &query.commands).await
+    })
+    .await
+}
@@ -0,0 +1,102 @@
+mod changes;
+mod children;
+mod delete;
+mod get;
+mod patch;
+mod put;
+
+pub use self::changes::changes;
+pub use self::children::children;
+pub use self::delete::delete;
+pub use self::get::get;
+pub use self::patch::{patch, PatchBody};
+pub use self::put::{put, PutBody};
+
+use axum::http::StatusCode;
+use ldap3_serde::{Ldap, LdapError, LdapResult};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    types::{
+        query::{Command, QueryCommand, QueryResult},
+        routes::{DataResponse, Response},
+    },
+    upstream,
+};
+
+/// Query parameters accepted by every entry route.
+#[derive(Deserialize, JsonSchema)]
+pub struct EntryParams {
+    /// Comma-separated list of attributes to return.
+    pub attrs: Option<String>,
+    pub filter: Option<String>,
+}
+
+impl EntryParams {
+    fn attrs(&self) -> Vec<String> {
+        match &self.attrs {
+            Some(val) => val
+                .split(',')
+                .map(|attr| attr.trim().to_string())
+                .filter(|attr| !attr.is_empty())
+                .collect(),
+            None => vec!["*".to_string()],
+        }
+    }
+}
+
+/// Maps an LDAP result code to the closest HTTP status.
+fn status_for(rc: u32) -> StatusCode {
+    match rc {
+        0 => StatusCode::OK,
+        32 => StatusCode::NOT_FOUND,
+        48 | 49 => StatusCode::UNAUTHORIZED,
+        50 => StatusCode::FORBIDDEN,
+        20 | 68 => StatusCode::CONFLICT,
+        16 | 17 | 19 | 21 | 34 | 64 | 65 | 66 | 67 | 69 => StatusCode::UNPROCESSABLE_ENTITY,
+        3 | 51 | 52 => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn ldap_error(res: &LdapResult) -> Response {
+    Response::error(status_for(res.rc), res.to_string())
+}
+
+/// Turns the result of a write operation into a response.
+fn write_response(res: LdapResult) -> Response {
+    if res.rc != 0 {
+        return ldap_error(&res);
+    }
+
+    Response {
+        status: StatusCode::OK,
+        body: Box::new(DataResponse {
+            result: true,
+            data: res,
+        }),
+    }
+}
+
+/// Connects to the configured upstream and binds the service account.
+async fn open() -> Result<Ldap, Response> {
+    upstream::connect_service().await
+}
+
+async fn execute(ldap: &mut Ldap, command: QueryCommand<'_>) -> Result<QueryResult, Response> {
+    match command.execute(ldap).await {
+        Ok(Some(val)) => Ok(val),
+        Ok(None) => Err(Response::error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Command returned no result",
+        )),
+        // Also raised for commands outside the subtrees of the key or held back by
+        // the policy, which are forbidden rather than failures of the directory.
+        Err(LdapError::LdapResult { result }) => Err(ldap_error(&result)),
+        Err(err) => Err(Response::error(
+            StatusCode::BAD_GATEWAY,
+            format!("Failed to execute command: {:?}", err),
+        )),
+    }
+}
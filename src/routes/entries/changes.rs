@@ -0,0 +1,209 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive},
+        IntoResponse, Sse,
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ldap3_serde::{Ldap, Scope};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    auth::{self, SignedRequest},
+    context,
+    sync::{watch, SyncMessage},
+    types::{
+        change::ChangeEvent,
+        query::{QueryCommand, SearchCommand},
+        routes::ErrorResponse,
+    },
+    AppState,
+};
+
+use super::{open, EntryParams};
+
+fn event(name: &str, data: &impl Serialize, cookie: Option<&[u8]>) -> Event {
+    let event = match Event::default().event(name).json_data(data) {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Failed to serialize change event: {:?}", err);
+            Event::default().event("error")
+        }
+    };
+    match cookie {
+        Some(cookie) => event.id(STANDARD.encode(cookie)),
+        None => event,
+    }
+}
+
+impl From<SyncMessage> for Event {
+    fn from(value: SyncMessage) -> Self {
+        match value {
+            SyncMessage::Change(change, cookie) => {
+                event(change.kind.name(), &change, cookie.as_deref())
+            }
+            SyncMessage::Sync(sync, cookie) => event("sync", &sync, cookie.as_deref()),
+            SyncMessage::Error(message) => event(
+                "error",
+                &ErrorResponse {
+                    result: false,
+                    message,
+                },
+                None,
+            ),
+        }
+    }
+}
+
+/// Whether the key of the current request may read the entry a change is about, as
+/// a base search of its DN and, for renames, of its old DN. Changes without a DN are
+/// checked against the base of the stream.
+async fn permitted(ldap: &mut Ldap, change: &ChangeEvent, base: &str, attrs: &[String]) -> bool {
+    let dns = match &change.dn {
+        Some(dn) => std::iter::once(dn.as_str())
+            .chain(change.old_dn.as_deref())
+            .collect(),
+        None => vec![base],
+    };
+    for dn in dns {
+        let command = QueryCommand::Search(SearchCommand {
+            base: dn.to_string(),
+            scope: Scope::Base,
+            filter: "(objectClass=*)".to_string(),
+            attrs: attrs.to_vec(),
+            format: None,
+            page: None,
+        });
+        if command.authorize(ldap).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Forwards the messages of a change stream, leaving out changes the key of the
+/// current request may not see, until either side goes away or the key is no longer
+/// authorized.
+async fn forward(
+    state: Arc<AppState>,
+    mut ldap: Ldap,
+    base: String,
+    attrs: Vec<String>,
+    mut rx: mpsc::Receiver<SyncMessage>,
+    tx: mpsc::Sender<SyncMessage>,
+) {
+    loop {
+        let message = tokio::select! {
+            _ = tx.closed() => break,
+            res = rx.recv() => match res {
+                Some(val) => val,
+                None => break,
+            },
+        };
+        // The key may have been revoked, removed or expired since the stream opened.
+        if auth::reauthorize(&state).is_err() {
+            let message = "Key is no longer authorized".to_string();
+            let _ = tx.send(SyncMessage::Error(message)).await;
+            break;
+        }
+        if let SyncMessage::Change(change, _) = &message {
+            if !permitted(&mut ldap, change, &base, &attrs).await {
+                continue;
+            }
+        }
+        if tx.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Streams changes below an entry as server-sent events. Each event carries the
+/// latest synchronization cookie as its id, so a reconnecting client resumes
+/// where it left off by sending it back in `Last-Event-ID`.
+pub async fn changes(
+    State(state): State<Arc<AppState>>,
+    Path(dn): Path<String>,
+    Query(params): Query<EntryParams>,
+    headers: HeaderMap,
+    _: SignedRequest,
+) -> axum::response::Response {
+    let cookie = match headers
+        .get("Last-Event-ID")
+        .and_then(|val| val.to_str().ok())
+        .filter(|val| !val.is_empty())
+    {
+        Some(val) => match STANDARD.decode(val) {
+            Ok(val) => Some(val),
+            Err(_) => {
+                return crate::types::routes::Response::error(
+                    axum::http::StatusCode::BAD_REQUEST,
+                    "Invalid Last-Event-ID header",
+                )
+                .into_response()
+            }
+        },
+        None => None,
+    };
+
+    // Change streams are not clamped, so the base itself has to be in scope.
+    if let Err(message) = context::access().check_dn(&dn) {
+        return crate::types::routes::Response::error(axum::http::StatusCode::FORBIDDEN, message)
+            .into_response();
+    }
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err.into_response(),
+    };
+
+    let filter = params
+        .filter
+        .clone()
+        .unwrap_or_else(|| "(objectClass=*)".to_string());
+    let command = QueryCommand::Search(SearchCommand {
+        base: dn.clone(),
+        scope: Scope::Subtree,
+        filter: filter.clone(),
+        attrs: params.attrs(),
+        format: None,
+        page: None,
+    });
+    if let Err(message) = command.authorize(&mut ldap).await {
+        let _ = ldap.unbind().await;
+        return crate::types::routes::Response::error(axum::http::StatusCode::FORBIDDEN, message)
+            .into_response();
+    }
+    // Entries are checked against the policy over a second handle of the connection
+    // while the search runs.
+    let (tx, changes) = mpsc::channel(64);
+    let (permitted_tx, rx) = mpsc::channel(64);
+    tokio::spawn(context::scope(
+        context::current(),
+        forward(
+            state,
+            ldap.clone(),
+            dn.clone(),
+            params.attrs(),
+            changes,
+            permitted_tx,
+        ),
+    ));
+    tokio::spawn(context::scope(
+        context::current(),
+        watch(ldap, dn, filter, params.attrs(), cookie, tx),
+    ));
+
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|message| (Ok::<_, Infallible>(Event::from(message)), rx))
+    });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
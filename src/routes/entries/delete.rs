@@ -0,0 +1,30 @@
+use axum::{extract::Path, http::StatusCode};
+
+use crate::{
+    access,
+    auth::SignedRequest,
+    types::{
+        query::{DeleteCommand, QueryCommand, QueryResult},
+        routes::Response,
+    },
+};
+
+use super::{execute, open, write_response};
+
+pub async fn delete(Path(dn): Path<String>, _: SignedRequest) -> Response {
+    let command = QueryCommand::Delete(DeleteCommand { dn });
+    if let Err(err) = access::authorize(&command) {
+        return err;
+    }
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    match execute(&mut ldap, command).await {
+        Ok(QueryResult::Common(res)) => write_response(res),
+        Ok(_) => Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result"),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use ldap3_serde::{Scope, SearchEntry, SearchResult};
+
+use crate::{
+    access,
+    auth::SignedRequest,
+    types::{
+        entry::Entry,
+        query::{QueryCommand, QueryResult, SearchCommand},
+        routes::{DataResponse, Response},
+    },
+};
+
+use super::{execute, ldap_error, open, EntryParams};
+
+pub async fn get(
+    Path(dn): Path<String>,
+    Query(params): Query<EntryParams>,
+    _: SignedRequest,
+) -> Response {
+    let command = QueryCommand::Search(SearchCommand {
+        base: dn,
+        scope: Scope::Base,
+        filter: params
+            .filter
+            .clone()
+            .unwrap_or_else(|| "(objectClass=*)".to_string()),
+        attrs: params.attrs(),
+        format: None,
+        page: None,
+    });
+
+    if let Err(err) = access::authorize(&command) {
+        return err;
+    }
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let SearchResult(entries, res) = match execute(&mut ldap, command).await {
+        Ok(QueryResult::Search(val)) => val,
+        Ok(_) => return Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result"),
+        Err(err) => return err,
+    };
+
+    if res.rc != 0 {
+        return ldap_error(&res);
+    }
+
+    match entries.into_iter().find(|entry| !entry.is_ref()) {
+        Some(entry) => Response {
+            status: StatusCode::OK,
+            body: Box::new(DataResponse {
+                result: true,
+                data: Entry::from(SearchEntry::construct(entry)),
+            }),
+        },
+        None => Response::error(StatusCode::NOT_FOUND, "Entry not found"),
+    }
+}
@@ -0,0 +1,100 @@
+use axum::{
+    extract::Path,
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    access,
+    auth::SignedRequest,
+    types::{
+        patch::{self, PatchOperation},
+        query::{Mod, ModifyCommand, QueryCommand, QueryResult},
+        routes::Response,
+    },
+};
+
+use super::{execute, open, write_response};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PatchBody {
+    pub changes: Vec<Mod>,
+}
+
+/// Parses the body according to its content type into a list of modifications.
+///
+/// `application/json-patch+json` and `application/merge-patch+json` bodies are
+/// translated from the `Entry` document; anything else must be `{"changes": [...]}`.
+fn parse_changes(headers: &HeaderMap, body: &[u8]) -> Result<Vec<Mod>, Response> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.split(';').next())
+        .map(|val| val.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let changes = match content_type.as_str() {
+        "application/json-patch+json" => {
+            match serde_json::from_slice::<Vec<PatchOperation>>(body) {
+                Ok(val) => patch::from_json_patch(val),
+                Err(err) => {
+                    return Err(Response::error(
+                        StatusCode::BAD_REQUEST,
+                        format!("Failed to parse request: {:?}", err),
+                    ))
+                }
+            }
+        }
+        "application/merge-patch+json" => match serde_json::from_slice::<Value>(body) {
+            Ok(val) => patch::from_merge_patch(val),
+            Err(err) => {
+                return Err(Response::error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to parse request: {:?}", err),
+                ))
+            }
+        },
+        _ => match serde_json::from_slice::<PatchBody>(body) {
+            Ok(val) => Ok(val.changes),
+            Err(err) => {
+                return Err(Response::error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to parse request: {:?}", err),
+                ))
+            }
+        },
+    };
+
+    match changes {
+        Ok(val) => Ok(val),
+        Err(err) => Err(Response::error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            err.to_string(),
+        )),
+    }
+}
+
+pub async fn patch(Path(dn): Path<String>, headers: HeaderMap, request: SignedRequest) -> Response {
+    let changes = match parse_changes(&headers, &request.body) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let command = QueryCommand::Modify(ModifyCommand { dn, changes });
+    if let Err(err) = access::authorize(&command) {
+        return err;
+    }
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    match execute(&mut ldap, command).await {
+        Ok(QueryResult::Common(res)) => write_response(res),
+        Ok(_) => Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result"),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::Path, http::StatusCode};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    access,
+    auth::SignedRequest,
+    types::{
+        query::{EnsureAction, EnsureAttr, EnsureCommand, EnsureMode, QueryCommand, QueryResult},
+        routes::{DataResponse, Response},
+    },
+};
+
+use super::{execute, ldap_error, open};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PutBody {
+    pub attrs: HashMap<String, HashSet<String>>,
+}
+
+/// Creates the entry, or brings the given attributes to exactly the given values.
+/// Attributes not mentioned in the body are left untouched.
+pub async fn put(Path(dn): Path<String>, request: SignedRequest) -> Response {
+    let body = match serde_json::from_slice::<PutBody>(&request.body) {
+        Ok(val) => val,
+        Err(err) => {
+            return Response::error(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to parse request: {:?}", err),
+            )
+        }
+    };
+
+    let command = QueryCommand::Ensure(EnsureCommand {
+        dn,
+        attrs: body
+            .attrs
+            .into_iter()
+            .map(|(attr, values)| EnsureAttr {
+                attr,
+                values,
+                mode: EnsureMode::Exact,
+            })
+            .collect(),
+    });
+
+    if let Err(err) = access::authorize(&command) {
+        return err;
+    }
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let res = match execute(&mut ldap, command).await {
+        Ok(QueryResult::Ensure(val)) => val,
+        Ok(_) => return Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result"),
+        Err(err) => return err,
+    };
+
+    if let Some(result) = res.result.as_ref().filter(|result| result.rc != 0) {
+        return ldap_error(result);
+    }
+
+    Response {
+        status: match res.action {
+            EnsureAction::Add => StatusCode::CREATED,
+            _ => StatusCode::OK,
+        },
+        body: Box::new(DataResponse {
+            result: true,
+            data: res,
+        }),
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use async_graphql::dataloader::DataLoader;
+use axum::{extract::State, http::StatusCode};
+
+use crate::{
+    auth::SignedRequest, context, graphql::EntryLoader, types::routes::Response, upstream, AppState,
+};
+
+pub async fn post(State(state): State<Arc<AppState>>, request: SignedRequest) -> Response {
+    let query = match serde_json::from_slice::<async_graphql::Request>(&request.body) {
+        Ok(val) => val,
+        Err(err) => {
+            return Response::error(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to parse request: {:?}", err),
+            )
+        }
+    };
+
+    let ldap = match upstream::connect_service().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    // Batched loads run in their own tasks, which need the request context to
+    // apply the restrictions of the signing key.
+    let ctx = context::current();
+    let loader = DataLoader::new(EntryLoader { ldap: ldap.clone() }, move |fut| {
+        tokio::spawn(context::scope(ctx.clone(), fut))
+    });
+    let response = state.graphql.execute(query.data(ldap).data(loader)).await;
+
+    Response {
+        status: StatusCode::OK,
+        body: Box::new(response),
+    }
+}
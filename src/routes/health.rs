@@ -0,0 +1,4 @@
+mod live;
+mod ready;
+
+pub use self::{live::live, ready::ready};
@@ -0,0 +1,7 @@
+use axum::Json;
+
+use crate::routes::index::StatusResponse;
+
+pub async fn live() -> Json<StatusResponse> {
+    Json(StatusResponse { result: true })
+}
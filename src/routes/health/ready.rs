@@ -0,0 +1,17 @@
+use std::sync::Arc;
+
+use axum::{extract, http::StatusCode};
+
+use crate::{health, types::routes::Response, AppState};
+
+pub async fn ready(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    let readiness = health::readiness(&state).await;
+
+    Response {
+        status: match readiness.result {
+            true => StatusCode::OK,
+            false => StatusCode::SERVICE_UNAVAILABLE,
+        },
+        body: Box::new(readiness),
+    }
+}
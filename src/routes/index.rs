@@ -1,3 +1,3 @@
 mod get;
 
-pub use self::get::get;
+pub use self::get::{get, StatusResponse};
@@ -1,7 +1,8 @@
 use axum::Json;
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct StatusResponse {
     pub result: bool,
 }
@@ -0,0 +1,27 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::metrics::METRICS;
+
+/// Serves the metrics when `METRICS_ENABLED` is `true`. They are not signed or
+/// authenticated, so the endpoint is off unless it is enabled.
+pub async fn get() -> Response {
+    if !dotenv::var("METRICS_ENABLED").is_ok_and(|val| val == "true") {
+        return crate::types::routes::Response::error(
+            StatusCode::NOT_FOUND,
+            "Metrics are not enabled",
+        )
+        .into_response();
+    }
+
+    match METRICS.render() {
+        Ok(val) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], val).into_response(),
+        Err(err) => crate::types::routes::Response::error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to render metrics: {:?}", err),
+        )
+        .into_response(),
+    }
+}
@@ -0,0 +1,8 @@
+use axum::Json;
+use serde_json::Value;
+
+use crate::openapi;
+
+pub async fn get() -> Json<Value> {
+    Json(openapi::document())
+}
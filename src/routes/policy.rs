@@ -0,0 +1,3 @@
+mod explain;
+
+pub use self::explain::explain;
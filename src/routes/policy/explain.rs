@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use axum::{extract, http::StatusCode};
+use axum_extra::extract::WithRejection;
+
+use crate::{
+    auth::SignatureTarget,
+    policy::{self, Decision, Effect},
+    routes::query::{verify, QueryData, QueryRequest},
+    types::routes::{DataResponse, RejectionError, Response},
+    upstream, AppState,
+};
+
+/// Evaluates the policy for a signed query without running its commands, returning
+/// the rule that allows or denies each one. Group memberships are looked up over a
+/// fresh connection, since `bind` commands of the query are not run either.
+pub async fn explain(
+    extract::State(state): extract::State<Arc<AppState>>,
+    target: SignatureTarget,
+    WithRejection(extract::Json(payload), _): WithRejection<
+        extract::Json<QueryRequest>,
+        RejectionError,
+    >,
+) -> Response {
+    if let Err(err) = verify(&state, &target, &payload) {
+        return err;
+    }
+
+    let query = match serde_json::from_str::<QueryData>(&payload.data) {
+        Ok(val) => val,
+        Err(err) => {
+            return Response::error(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to parse request: {:?}", err),
+            )
+        }
+    };
+
+    let policy = match policy::current() {
+        Some(val) => val,
+        None => {
+            let decisions = query
+                .commands
+                .iter()
+                .map(|command| Decision {
+                    command: command.name().to_string(),
+                    effect: Effect::Allow,
+                    rule: None,
+                    reason: "No policy is configured".to_string(),
+                    approvers: Vec::new(),
+                    any_approver: false,
+                    skipped: Vec::new(),
+                })
+                .collect::<Vec<_>>();
+            return Response {
+                status: StatusCode::OK,
+                body: Box::new(DataResponse {
+                    result: true,
+                    data: decisions,
+                }),
+            };
+        }
+    };
+
+    let mut ldap = match upstream::connect_named(query.host, query.port).await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let decisions = policy.review(&mut ldap, &query.commands).await;
+    let _ = ldap.unbind().await;
+
+    Response {
+        status: StatusCode::OK,
+        body: Box::new(DataResponse {
+            result: true,
+            data: decisions,
+        }),
+    }
+}
@@ -1,4 +1,4 @@
 mod post;
 pub mod types;
 
-pub use self::post::post;
+pub use self::post::{execute, post, verify, QueryData, QueryRequest, SuccessResponse};
@@ -2,35 +2,47 @@ use std::sync::Arc;
 
 use axum::{extract, http::StatusCode};
 use axum_extra::extract::WithRejection;
-use chrono::{DateTime, Utc};
-use ldap3_serde::LdapConnAsync;
+use ldap3_serde::Ldap;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use ssh_key::{Algorithm, PublicKey, SshSig};
 
 use crate::{
+    access,
+    approvals::PendingResponse,
+    audit,
+    auth::{self, SignatureTarget},
+    context,
+    policy::{self, Effect},
     types::{
         query::{Command, QueryCommand, QueryResult},
-        routes::{ErrorResponse, RejectionError, Response},
+        routes::{DataResponse, ErrorResponse, RejectionError, Response},
     },
-    AppState,
+    upstream, AppState,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct QueryRequest {
+    /// Version of the signed message; requests without one were signed in version 1.
+    #[serde(default = "legacy_version")]
+    pub version: u8,
+    /// Signing key; the signature fields may be left out by callers authenticated
+    /// with a client certificate.
+    #[serde(default)]
     pub public_key: String,
+    /// JSON serialization of `QueryData`.
+    #[schemars(schema_with = "crate::openapi::json_string::<QueryData>")]
     pub data: String,
+    #[serde(default)]
     pub timestamp: i64,
+    #[serde(default)]
     pub signature: String,
 }
 
-#[derive(Serialize)]
-pub struct SignatureData {
-    pub data: String,
-    pub timestamp: i64,
-    pub public_key: String,
+fn legacy_version() -> u8 {
+    1
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(bound(deserialize = "'de: 'a"))]
 pub struct QueryData<'a> {
     pub host: Option<String>,
@@ -38,174 +50,118 @@ pub struct QueryData<'a> {
     pub commands: Vec<QueryCommand<'a>>,
 }
 
-#[derive(Serialize)]
-struct SuccessResponse {
-    result: bool,
-    data: String,
+#[derive(Serialize, JsonSchema)]
+pub struct SuccessResponse {
+    pub result: bool,
+    /// JSON serialization of the result of every command, in order.
+    #[schemars(schema_with = "crate::openapi::json_string::<Vec<Option<QueryResult>>>")]
+    pub data: String,
+}
+
+/// Checks that a request is recent and its `data` signed by an authorized key for
+/// `target`, unless it was authenticated with a client certificate.
+pub fn verify(
+    state: &AppState,
+    target: &SignatureTarget,
+    payload: &QueryRequest,
+) -> Result<(), Response> {
+    if context::current().certificate.is_some() {
+        return Ok(());
+    }
+
+    auth::check_timestamp(payload.timestamp)?;
+    let public_key = auth::authorize_key(state, &payload.public_key)?;
+
+    let message = target.message(
+        payload.version,
+        payload.data.as_bytes(),
+        payload.timestamp,
+        &payload.public_key,
+    )?;
+    auth::verify_signature(&public_key, &payload.signature, message.as_bytes())
 }
 
 pub async fn post(
     extract::State(state): extract::State<Arc<AppState>>,
+    target: SignatureTarget,
     WithRejection(extract::Json(payload), _): WithRejection<
         extract::Json<QueryRequest>,
         RejectionError,
     >,
 ) -> Response {
-    let time_request = match DateTime::from_timestamp(payload.timestamp, 0) {
-        Some(val) => val,
-        None => {
-            return Response {
-                status: StatusCode::BAD_REQUEST,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: "Invalid timestamp".to_string(),
-                }),
-            }
-        }
-    };
-
-    if Utc::now() - time_request > chrono::Duration::minutes(5) {
-        return Response {
-            status: StatusCode::BAD_REQUEST,
-            body: Box::new(ErrorResponse {
-                result: false,
-                message: "Timestamp is too old".to_string(),
-            }),
-        };
+    if let Err(err) = verify(&state, &target, &payload) {
+        return err;
     }
 
-    let padded_key = format!("ssh-ed25519 {} request", &payload.public_key).to_string();
-    let public_key = match PublicKey::from_openssh(&padded_key) {
+    let query = match serde_json::from_str::<QueryData>(&payload.data) {
         Ok(val) => val,
         Err(err) => {
             return Response {
                 status: StatusCode::BAD_REQUEST,
                 body: Box::new(ErrorResponse {
                     result: false,
-                    message: format!("Invalid public key: {:?}", err),
+                    message: format!("Failed to parse request: {:?}", err),
                 }),
             };
         }
     };
 
-    if public_key.algorithm() != Algorithm::Ed25519 {
-        return Response {
-            status: StatusCode::BAD_REQUEST,
-            body: Box::new(ErrorResponse {
-                result: false,
-                message: "Invalid public key algorithm".to_string(),
-            }),
-        };
-    }
+    let commands = query
+        .commands
+        .iter()
+        .map(|command| command.name())
+        .collect::<Vec<_>>();
+    tracing::Span::current().record("commands", commands.join(","));
 
-    match state.authorized_keys.lock() {
-        Ok(val) => {
-            if !val.iter().any(|entry| *entry.public_key() == public_key) {
-                return Response {
-                    status: StatusCode::UNAUTHORIZED,
-                    body: Box::new(ErrorResponse {
-                        result: false,
-                        message: "Unauthorized".to_string(),
-                    }),
-                };
-            }
-        }
-        Err(_) => {
-            tracing::error!("Failed to acquire lock on authorized keys");
-            return Response {
-                status: StatusCode::INTERNAL_SERVER_ERROR,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: "Internal Server Error".to_string(),
-                }),
-            };
-        }
-    };
-
-    let padded_signature = format!(
-        "-----BEGIN SSH SIGNATURE-----\n{}\n-----END SSH SIGNATURE-----",
-        &payload.signature
-    )
-    .to_string();
-    let signature = match padded_signature.parse::<SshSig>() {
-        Ok(val) => val,
-        Err(err) => {
-            return Response {
-                status: StatusCode::BAD_REQUEST,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: format!("Invalid signature: {:?}", err),
-                }),
-            };
+    for command in query.commands.iter() {
+        if let Err(err) = access::authorize(command) {
+            return err;
         }
-    };
+    }
 
-    let signature_data = SignatureData {
-        data: payload.data.clone(),
-        timestamp: payload.timestamp,
-        public_key: payload.public_key,
-    };
-    let message = match serde_json::to_string(&signature_data) {
+    let mut ldap = match upstream::connect_named(query.host.clone(), query.port).await {
         Ok(val) => val,
-        Err(err) => {
-            return Response {
-                status: StatusCode::INTERNAL_SERVER_ERROR,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: format!("Failed to serialize payload: {:?}", err),
-                }),
-            };
-        }
+        Err(err) => return err,
     };
 
-    let namespace = dotenv::var("NAMESPACE").unwrap_or_else(|_| "ldap-rest".to_string());
-    match public_key.verify(namespace.as_str(), message.as_bytes(), &signature) {
-        Ok(()) => {}
-        Err(err) => {
+    if let Some(policy) = policy::current() {
+        let held = policy
+            .review(&mut ldap, &query.commands)
+            .await
+            .into_iter()
+            .filter(|decision| decision.effect == Effect::Approve)
+            .collect::<Vec<_>>();
+        if !held.is_empty() {
+            let _ = ldap.unbind().await;
+            let commands = query.commands.iter().map(audit::command_value).collect();
+            let request = state
+                .approvals
+                .hold(
+                    context::current(),
+                    payload.data.clone(),
+                    commands,
+                    &policy,
+                    held,
+                )
+                .await;
             return Response {
-                status: StatusCode::BAD_REQUEST,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: format!("Failed to verify signature: {:?}", err),
+                status: StatusCode::ACCEPTED,
+                body: Box::new(DataResponse {
+                    result: true,
+                    data: PendingResponse::from(&request),
                 }),
             };
         }
-    };
-
-    let query = match serde_json::from_str::<QueryData>(&payload.data) {
-        Ok(val) => val,
-        Err(err) => {
-            return Response {
-                status: StatusCode::BAD_REQUEST,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: format!("Failed to parse request: {:?}", err),
-                }),
-            };
-        }
-    };
-
-    let host = query.host.unwrap_or("localhost".to_string());
-    let port = query.port.unwrap_or(389);
-    let url = format!("ldap://{}:{}", host, port);
+    }
 
-    let (conn, mut ldap) = match LdapConnAsync::new(url.as_str()).await {
-        Ok(val) => val,
-        Err(err) => {
-            return Response {
-                status: StatusCode::BAD_GATEWAY,
-                body: Box::new(ErrorResponse {
-                    result: false,
-                    message: format!("Failed to connect to LDAP server: {:?}", err),
-                }),
-            };
-        }
-    };
-    ldap3_serde::drive!(conn);
+    execute(&mut ldap, &query.commands).await
+}
 
-    let mut result = Vec::<Option<QueryResult>>::with_capacity(query.commands.len());
-    for command in query.commands.iter() {
-        let res = match command.execute(&mut ldap).await {
+/// Runs the commands of a query in order, stopping at the first that fails.
+pub async fn execute(ldap: &mut Ldap, commands: &[QueryCommand<'_>]) -> Response {
+    let mut result = Vec::<Option<QueryResult>>::with_capacity(commands.len());
+    for command in commands.iter() {
+        let res = match command.execute(ldap).await {
             Ok(value) => value,
             Err(err) => {
                 return Response {
@@ -217,10 +173,7 @@ pub async fn post(
                 };
             }
         };
-        result.push(match res {
-            Some(val) => Some(val),
-            None => None,
-        });
+        result.push(res);
     }
 
     let result_str = match serde_json::to_string(&result) {
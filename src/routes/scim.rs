@@ -0,0 +1,246 @@
+mod delete;
+mod get;
+mod meta;
+mod patch;
+mod post;
+mod put;
+
+pub use self::delete::delete;
+pub use self::get::{get, list};
+pub use self::meta::{resource_types, schemas, service_provider_config};
+pub use self::patch::patch;
+pub use self::post::post;
+pub use self::put::put;
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header::AUTHORIZATION, StatusCode},
+};
+use ldap3_serde::{ldap_escape, Ldap, LdapResult, Scope, SearchEntry, SearchResult};
+use serde_json::Value;
+
+use crate::{
+    auth::{self, SignedRequest},
+    context::{self, Credential},
+    scim::{
+        mapping::{ResourceKind, ScimConfig},
+        token, ScimError,
+    },
+    types::{
+        query::{Command, QueryCommand, QueryResult, SearchCommand, SearchPage},
+        routes::Response,
+    },
+    upstream, AppState,
+};
+
+/// Request authenticated by a bearer token of `SCIM_TOKENS_PATH`, as provisioning
+/// clients send them, or else like any other route: by client certificate or signature.
+pub struct ScimRequest {
+    pub body: Bytes,
+}
+
+#[async_trait]
+impl FromRequest<Arc<AppState>> for ScimRequest {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let bearer = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|val| val.to_str().ok())
+            .and_then(|val| {
+                val.split_once(' ')
+                    .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+                    .map(|(_, token)| token.trim().to_string())
+            });
+        let bearer = match bearer {
+            Some(val) => val,
+            None => {
+                return SignedRequest::from_request(req, state)
+                    .await
+                    .map(|request| ScimRequest { body: request.body })
+            }
+        };
+
+        let identity = match state.scim_tokens.lock() {
+            Ok(val) => token::find(&val, &bearer).map(|entry| {
+                (
+                    entry.fingerprint(),
+                    entry.identity.clone(),
+                    entry.access.clone(),
+                )
+            }),
+            Err(_) => {
+                tracing::error!("Failed to acquire lock on SCIM tokens");
+                return Err(Response::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error",
+                ));
+            }
+        };
+        match identity {
+            Some((fingerprint, identity, access)) => {
+                let span = tracing::Span::current();
+                span.record("key_fingerprint", &fingerprint);
+                span.record("key_comment", &identity);
+                context::set_key(Credential::Token, fingerprint, identity, access);
+            }
+            None => {
+                return Err(auth::reject(
+                    "unknown_token",
+                    StatusCode::UNAUTHORIZED,
+                    "Unauthorized",
+                ))
+            }
+        }
+
+        match Bytes::from_request(req, state).await {
+            Ok(body) => Ok(ScimRequest { body }),
+            Err(err) => Err(ScimError::bad_request(
+                "invalidSyntax",
+                format!("Failed to read request body: {}", err),
+            )
+            .into()),
+        }
+    }
+}
+
+fn kind(endpoint: &str) -> Result<ResourceKind, Response> {
+    match ResourceKind::from_endpoint(endpoint) {
+        Some(val) => Ok(val),
+        None => Err(ScimError::new(
+            StatusCode::NOT_FOUND,
+            None,
+            format!("Unknown resource type \"{}\"", endpoint),
+        )
+        .into()),
+    }
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, Response> {
+    match serde_json::from_slice(body) {
+        Ok(val) => Ok(val),
+        Err(err) => Err(ScimError::bad_request("invalidSyntax", err.to_string()).into()),
+    }
+}
+
+fn resource_response(status: StatusCode, resource: Value) -> Response {
+    Response {
+        status,
+        body: Box::new(resource),
+    }
+}
+
+/// Connects to the configured upstream and binds the service account.
+async fn open() -> Result<Ldap, Response> {
+    upstream::connect_service().await
+}
+
+/// Maps an unsuccessful LDAP result to a SCIM error.
+fn check(res: LdapResult) -> Result<(), Response> {
+    let (code, scim_type) = match res.rc {
+        0 => return Ok(()),
+        32 => (StatusCode::NOT_FOUND, None),
+        50 => (StatusCode::FORBIDDEN, None),
+        68 => (StatusCode::CONFLICT, Some("uniqueness")),
+        16 | 17 | 19 | 21 | 64 | 65 | 67 | 69 => (StatusCode::BAD_REQUEST, Some("invalidValue")),
+        _ => (StatusCode::BAD_GATEWAY, None),
+    };
+    Err(ScimError::new(code, scim_type, res.to_string()).into())
+}
+
+fn command_error(err: ldap3_serde::LdapError) -> Response {
+    // Raised for commands outside the subtrees of the key or denied by the policy.
+    if let ldap3_serde::LdapError::LdapResult { result } = &err {
+        if result.rc == 50 {
+            return ScimError::new(StatusCode::FORBIDDEN, None, result.text.clone()).into();
+        }
+    }
+
+    ScimError::new(
+        StatusCode::BAD_GATEWAY,
+        None,
+        format!("Failed to execute command: {:?}", err),
+    )
+    .into()
+}
+
+/// Searches resources of `kind` matching an LDAP filter, returning those within
+/// `page`, or all of them, and the number of matching resources.
+async fn search(
+    ldap: &mut Ldap,
+    config: &ScimConfig,
+    kind: ResourceKind,
+    filter: &str,
+    page: Option<SearchPage>,
+) -> Result<(Vec<SearchEntry>, usize), Response> {
+    let mapping = config.mapping(kind);
+    let classes = mapping
+        .object_classes
+        .iter()
+        .map(|class| format!("(objectClass={})", ldap_escape(class.as_str())))
+        .collect::<String>();
+
+    let command = QueryCommand::Search(SearchCommand {
+        base: mapping.base.clone(),
+        scope: Scope::Subtree,
+        filter: format!("(&{}{})", classes, filter),
+        attrs: mapping.ldap_attrs(),
+        format: None,
+        page,
+    });
+
+    let (SearchResult(entries, res), total) = match command.execute(ldap).await {
+        Ok(Some(QueryResult::Search(val))) => {
+            let total = val.0.iter().filter(|entry| !entry.is_ref()).count();
+            (val, total)
+        }
+        Ok(Some(QueryResult::Page(val))) => (val.result, val.total),
+        Ok(_) => {
+            return Err(ScimError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                None,
+                "Unexpected result",
+            )
+            .into())
+        }
+        Err(err) => return Err(command_error(err)),
+    };
+    check(res)?;
+
+    let entries = entries
+        .into_iter()
+        .filter(|entry| !entry.is_ref())
+        .map(SearchEntry::construct)
+        .collect();
+    Ok((entries, total))
+}
+
+/// Finds the resource of `kind` with the given SCIM id.
+async fn find(
+    ldap: &mut Ldap,
+    config: &ScimConfig,
+    kind: ResourceKind,
+    id: &str,
+) -> Result<SearchEntry, Response> {
+    let filter = format!("({}={})", config.mapping(kind).id_attr, ldap_escape(id));
+
+    match search(ldap, config, kind, &filter, None)
+        .await?
+        .0
+        .into_iter()
+        .next()
+    {
+        Some(val) => Ok(val),
+        None => Err(ScimError::new(
+            StatusCode::NOT_FOUND,
+            None,
+            format!("{} \"{}\" not found", kind.name(), id),
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    types::{
+        query::{Command, DeleteCommand, QueryCommand, QueryResult},
+        routes::Response,
+    },
+    AppState,
+};
+
+use super::{check, command_error, find, kind, open, ScimRequest};
+
+pub async fn delete(
+    State(state): State<Arc<AppState>>,
+    Path((resource, id)): Path<(String, String)>,
+    _: ScimRequest,
+) -> Response {
+    let kind = match kind(&resource) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let entry = match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let command = QueryCommand::Delete(DeleteCommand { dn: entry.dn });
+    match command.execute(&mut ldap).await {
+        Ok(Some(QueryResult::Common(res))) => match check(res) {
+            Ok(()) => Response {
+                status: StatusCode::NO_CONTENT,
+                body: Box::new(()),
+            },
+            Err(err) => err,
+        },
+        Ok(_) => Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result"),
+        Err(err) => command_error(err),
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    scim::{filter, LIST_RESPONSE_SCHEMA},
+    types::{query::SearchPage, routes::Response},
+    AppState,
+};
+
+use super::{find, kind, open, resource_response, search, ScimRequest};
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    pub filter: Option<String>,
+    #[serde(rename = "startIndex")]
+    pub start_index: Option<usize>,
+    pub count: Option<usize>,
+}
+
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+    Path(resource): Path<String>,
+    Query(params): Query<ListParams>,
+    _: ScimRequest,
+) -> Response {
+    let kind = match kind(&resource) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let filter = match params.filter.as_deref() {
+        Some(val) => match filter::parse(val).and_then(|val| val.to_ldap(&state.scim, kind)) {
+            Ok(val) => val,
+            Err(err) => return err.into(),
+        },
+        None => "(objectClass=*)".to_string(),
+    };
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let start_index = params.start_index.unwrap_or(1).max(1);
+    let page = params.count.map(|count| SearchPage {
+        start: start_index,
+        count,
+    });
+    let (entries, total) = match search(&mut ldap, &state.scim, kind, &filter, page).await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    // Without a count, every resource from `startIndex` on is returned.
+    let page = entries
+        .iter()
+        .skip(match params.count {
+            Some(_) => 0,
+            None => start_index - 1,
+        })
+        .map(|entry| state.scim.to_resource(kind, entry))
+        .collect::<Vec<_>>();
+
+    resource_response(
+        StatusCode::OK,
+        json!({
+            "schemas": [LIST_RESPONSE_SCHEMA],
+            "totalResults": total,
+            "startIndex": start_index,
+            "itemsPerPage": page.len(),
+            "Resources": page,
+        }),
+    )
+}
+
+pub async fn get(
+    State(state): State<Arc<AppState>>,
+    Path((resource, id)): Path<(String, String)>,
+    _: ScimRequest,
+) -> Response {
+    let kind = match kind(&resource) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(entry) => resource_response(StatusCode::OK, state.scim.to_resource(kind, &entry)),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode};
+use serde_json::{json, Value};
+
+use crate::{
+    scim::{
+        mapping::{ResourceKind, ScimConfig},
+        ENTERPRISE_USER_SCHEMA, LIST_RESPONSE_SCHEMA,
+    },
+    types::routes::Response,
+    AppState,
+};
+
+use super::resource_response;
+
+const KINDS: [ResourceKind; 2] = [ResourceKind::User, ResourceKind::Group];
+
+fn list(resources: Vec<Value>) -> Response {
+    resource_response(
+        StatusCode::OK,
+        json!({
+            "schemas": [LIST_RESPONSE_SCHEMA],
+            "totalResults": resources.len(),
+            "startIndex": 1,
+            "itemsPerPage": resources.len(),
+            "Resources": resources,
+        }),
+    )
+}
+
+/// Describes the attributes of `kind` exposed by the configured mapping.
+fn schema(config: &ScimConfig, kind: ResourceKind) -> Value {
+    let attributes = config
+        .mapping(kind)
+        .attributes
+        .iter()
+        .filter(|attribute| !attribute.scim.starts_with("urn:"))
+        .map(|attribute| {
+            json!({
+                "name": attribute.scim,
+                "type": if attribute.reference.is_some() { "reference" } else { "string" },
+                "multiValued": attribute.multi_valued,
+                "required": false,
+                "mutability": "readWrite",
+                "returned": "default",
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Schema"],
+        "id": kind.schema(),
+        "name": kind.name(),
+        "attributes": attributes,
+    })
+}
+
+pub async fn service_provider_config() -> Response {
+    resource_response(
+        StatusCode::OK,
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+            "patch": { "supported": true },
+            "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+            "filter": { "supported": true, "maxResults": 0 },
+            "changePassword": { "supported": false },
+            "sort": { "supported": false },
+            "etag": { "supported": false },
+            "authenticationSchemes": [
+                {
+                    "type": "oauthbearertoken",
+                    "name": "Bearer token",
+                    "description": "Authorization header with a token of SCIM_TOKENS_PATH",
+                },
+                {
+                    "type": "httpsignature",
+                    "name": "SSH request signature",
+                    "description": "X-Public-Key, X-Timestamp and X-Signature headers",
+                },
+            ],
+        }),
+    )
+}
+
+pub async fn schemas(State(state): State<Arc<AppState>>) -> Response {
+    list(
+        KINDS
+            .iter()
+            .map(|kind| schema(&state.scim, *kind))
+            .collect(),
+    )
+}
+
+pub async fn resource_types() -> Response {
+    list(
+        KINDS
+            .iter()
+            .map(|kind| {
+                let mut resource_type = json!({
+                    "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ResourceType"],
+                    "id": kind.name(),
+                    "name": kind.name(),
+                    "endpoint": format!("/{}", kind.endpoint()),
+                    "schema": kind.schema(),
+                });
+                if *kind == ResourceKind::User {
+                    resource_type["schemaExtensions"] =
+                        json!([{ "schema": ENTERPRISE_USER_SCHEMA, "required": false }]);
+                }
+                resource_type
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    scim::{
+        patch::{self, PatchRequest},
+        ScimError,
+    },
+    types::{
+        query::{Command, ModifyCommand, QueryCommand, QueryResult},
+        routes::Response,
+    },
+    AppState,
+};
+
+use super::{check, command_error, find, kind, open, parse_body, resource_response, ScimRequest};
+
+pub async fn patch(
+    State(state): State<Arc<AppState>>,
+    Path((resource, id)): Path<(String, String)>,
+    request: ScimRequest,
+) -> Response {
+    let kind = match kind(&resource) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+    let mapping = state.scim.mapping(kind);
+
+    let body = match parse_body::<PatchRequest>(&request.body) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let changes = match patch::to_mods(&state.scim, kind, body) {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    if changes
+        .iter()
+        .any(|change| change.attr().eq_ignore_ascii_case(&mapping.id_attr))
+    {
+        return ScimError::bad_request(
+            "mutability",
+            format!("Attribute mapped to \"{}\" is immutable", mapping.id_attr),
+        )
+        .into();
+    }
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let entry = match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    if !changes.is_empty() {
+        let command = QueryCommand::Modify(ModifyCommand {
+            dn: entry.dn,
+            changes,
+        });
+        match command.execute(&mut ldap).await {
+            Ok(Some(QueryResult::Common(res))) => {
+                if let Err(err) = check(res) {
+                    return err;
+                }
+            }
+            Ok(_) => {
+                return Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result")
+            }
+            Err(err) => return command_error(err),
+        };
+    }
+
+    match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(entry) => resource_response(StatusCode::OK, state.scim.to_resource(kind, &entry)),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde_json::Value;
+
+use crate::{
+    scim::ScimError,
+    types::{
+        query::{AddCommand, Command, QueryCommand, QueryResult},
+        routes::Response,
+    },
+    AppState,
+};
+
+use super::{check, command_error, find, kind, open, parse_body, resource_response, ScimRequest};
+
+pub async fn post(
+    State(state): State<Arc<AppState>>,
+    Path(resource): Path<String>,
+    request: ScimRequest,
+) -> Response {
+    let kind = match kind(&resource) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+    let mapping = state.scim.mapping(kind);
+
+    let body = match parse_body::<Value>(&request.body) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let mut attrs = match state.scim.to_attrs(kind, &body) {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    let id = match attrs
+        .iter()
+        .find(|(attr, _)| attr.eq_ignore_ascii_case(&mapping.id_attr))
+        .and_then(|(_, values)| values.iter().next())
+    {
+        Some(val) => val.clone(),
+        None => {
+            return ScimError::bad_request(
+                "invalidValue",
+                format!("Missing attribute mapped to \"{}\"", mapping.id_attr),
+            )
+            .into()
+        }
+    };
+
+    attrs.push((
+        "objectClass".to_string(),
+        mapping
+            .object_classes
+            .iter()
+            .cloned()
+            .collect::<HashSet<_>>(),
+    ));
+
+    let command = QueryCommand::Add(AddCommand {
+        dn: state.scim.dn_for(kind, &id),
+        attrs,
+    });
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    match command.execute(&mut ldap).await {
+        Ok(Some(QueryResult::Common(res))) => {
+            if let Err(err) = check(res) {
+                return err;
+            }
+        }
+        Ok(_) => return Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result"),
+        Err(err) => return command_error(err),
+    };
+
+    match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(entry) => resource_response(StatusCode::CREATED, state.scim.to_resource(kind, &entry)),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde_json::Value;
+
+use crate::{
+    types::{
+        query::{Command, Mod, ModifyCommand, QueryCommand, QueryResult, ReplaceMod},
+        routes::Response,
+    },
+    AppState,
+};
+
+use super::{check, command_error, find, kind, open, parse_body, resource_response, ScimRequest};
+
+/// Replaces the mapped attributes present in the body; `null` removes one. The id
+/// attribute cannot be changed.
+pub async fn put(
+    State(state): State<Arc<AppState>>,
+    Path((resource, id)): Path<(String, String)>,
+    request: ScimRequest,
+) -> Response {
+    let kind = match kind(&resource) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+    let mapping = state.scim.mapping(kind);
+
+    let body = match parse_body::<Value>(&request.body) {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let attrs = match state.scim.to_attrs(kind, &body) {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    // Attributes the body leaves out, or the mapping does not cover, are kept, so
+    // attributes the entry requires are not cleared by clients unaware of them.
+    let changes = attrs
+        .into_iter()
+        .filter(|(attr, _)| !attr.eq_ignore_ascii_case(&mapping.id_attr))
+        .map(|(attr, values)| Mod::Replace(ReplaceMod { attr, values }))
+        .collect::<Vec<_>>();
+
+    let mut ldap = match open().await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    let entry = match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(val) => val,
+        Err(err) => return err,
+    };
+
+    if !changes.is_empty() {
+        let command = QueryCommand::Modify(ModifyCommand {
+            dn: entry.dn,
+            changes,
+        });
+        match command.execute(&mut ldap).await {
+            Ok(Some(QueryResult::Common(res))) => {
+                if let Err(err) = check(res) {
+                    return err;
+                }
+            }
+            Ok(_) => {
+                return Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected result")
+            }
+            Err(err) => return command_error(err),
+        };
+    }
+
+    match find(&mut ldap, &state.scim, kind, &id).await {
+        Ok(entry) => resource_response(StatusCode::OK, state.scim.to_resource(kind, &entry)),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,216 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::IntoResponse,
+};
+use ldap3_serde::Ldap;
+use serde::Deserialize;
+use tracing::Instrument;
+
+use crate::{
+    access,
+    auth::{self, SignatureTarget},
+    context,
+    routes::query::{verify, QueryRequest},
+    types::{
+        query::{Command, QueryCommand},
+        routes::{DataResponse, ErrorResponse, Response},
+    },
+    upstream, AppState,
+};
+
+/// Connection parameters signed in the `data` of the opening message.
+#[derive(Deserialize)]
+struct SessionData {
+    host: Option<String>,
+    port: Option<u16>,
+}
+
+/// Upgrades to a WebSocket session. The first message must be a signed
+/// `QueryRequest` whose `data` holds `{"host", "port"}`; every following text
+/// message is a single `QueryCommand` run on the same connection.
+pub async fn get(
+    State(state): State<Arc<AppState>>,
+    target: SignatureTarget,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let span = tracing::Span::current();
+    let context = context::current();
+    ws.on_upgrade(move |socket| {
+        context::scope(context, session(socket, state, target)).instrument(span)
+    })
+}
+
+fn idle_timeout() -> Duration {
+    let secs = dotenv::var("SESSION_IDLE_TIMEOUT")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(300);
+    Duration::from_secs(secs)
+}
+
+fn encode(body: &dyn erased_serde::Serialize) -> Option<String> {
+    match serde_json::to_string(body) {
+        Ok(val) => Some(val),
+        Err(err) => {
+            tracing::error!("Failed to serialize session message: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Sends an encoded message, returning whether the session can go on.
+async fn send(socket: &mut WebSocket, text: Option<String>) -> bool {
+    match text {
+        Some(text) => socket.send(Message::Text(text)).await.is_ok(),
+        None => false,
+    }
+}
+
+async fn send_error(socket: &mut WebSocket, message: String) -> bool {
+    let text = encode(&ErrorResponse {
+        result: false,
+        message,
+    });
+    send(socket, text).await
+}
+
+async fn close(mut socket: WebSocket, code: u16, reason: &'static str) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    let _ = socket.send(Message::Close(Some(frame))).await;
+}
+
+/// Waits for the next text message, or `None` once the client goes away.
+async fn receive(socket: &mut WebSocket, timeout: Duration) -> Result<Option<String>, ()> {
+    loop {
+        match tokio::time::timeout(timeout, socket.recv()).await {
+            Err(_) => return Err(()),
+            Ok(None) | Ok(Some(Err(_))) | Ok(Some(Ok(Message::Close(_)))) => return Ok(None),
+            Ok(Some(Ok(Message::Text(text)))) => return Ok(Some(text)),
+            Ok(Some(Ok(Message::Binary(_)))) => {
+                if !send_error(socket, "Binary messages are not supported".to_string()).await {
+                    return Ok(None);
+                }
+            }
+            Ok(Some(Ok(_))) => {}
+        }
+    }
+}
+
+/// Authenticates the opening message and connects to the requested server.
+async fn open(state: &AppState, target: &SignatureTarget, text: &str) -> Result<Ldap, Response> {
+    let payload = match serde_json::from_str::<QueryRequest>(text) {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Response::error(
+                axum::http::StatusCode::BAD_REQUEST,
+                format!("Failed to parse request: {:?}", err),
+            ))
+        }
+    };
+    verify(state, target, &payload)?;
+
+    let data = match serde_json::from_str::<SessionData>(&payload.data) {
+        Ok(val) => val,
+        Err(err) => {
+            return Err(Response::error(
+                axum::http::StatusCode::BAD_REQUEST,
+                format!("Failed to parse request: {:?}", err),
+            ))
+        }
+    };
+
+    upstream::connect_named(data.host, data.port).await
+}
+
+async fn session(mut socket: WebSocket, state: Arc<AppState>, target: SignatureTarget) {
+    let timeout = idle_timeout();
+
+    let opened = match receive(&mut socket, timeout).await {
+        Ok(Some(text)) => open(&state, &target, &text)
+            .await
+            .map_err(|err| encode(err.body.as_ref())),
+        Ok(None) => return,
+        Err(()) => return close(socket, close_code::POLICY, "Authentication timed out").await,
+    };
+    let mut ldap = match opened {
+        Ok(val) => val,
+        Err(text) => {
+            send(&mut socket, text).await;
+            return close(socket, close_code::POLICY, "Authentication failed").await;
+        }
+    };
+
+    let text = encode(&DataResponse {
+        result: true,
+        data: (),
+    });
+    if !send(&mut socket, text).await {
+        return;
+    }
+
+    loop {
+        let text = match receive(&mut socket, timeout).await {
+            Ok(Some(val)) => val,
+            Ok(None) => break,
+            Err(()) => {
+                let _ = ldap.unbind().await;
+                return close(socket, close_code::NORMAL, "Idle timeout").await;
+            }
+        };
+
+        let command = match serde_json::from_str::<QueryCommand>(&text) {
+            Ok(val) => val,
+            Err(err) => {
+                if !send_error(&mut socket, format!("Failed to parse command: {:?}", err)).await {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // The key may have been revoked, removed or expired since the session opened.
+        let revoked = auth::reauthorize(&state).map_err(|err| encode(err.body.as_ref()));
+        if let Err(text) = revoked {
+            send(&mut socket, text).await;
+            let _ = ldap.unbind().await;
+            return close(socket, close_code::POLICY, "Key is no longer authorized").await;
+        }
+
+        // Encoded before awaiting, as the response body is not `Send`.
+        let rejected = access::authorize(&command).map_err(|err| encode(err.body.as_ref()));
+        if let Err(text) = rejected {
+            if !send(&mut socket, text).await {
+                break;
+            }
+            continue;
+        }
+
+        let sent = match command.execute(&mut ldap).await {
+            Ok(data) => {
+                let text = encode(&DataResponse { result: true, data });
+                send(&mut socket, text).await
+            }
+            Err(err) => {
+                send_error(&mut socket, format!("Failed to execute command: {:?}", err)).await
+            }
+        };
+        if !sent {
+            break;
+        }
+
+        // The connection cannot be reused after an unbind, so end the session.
+        if let QueryCommand::Unbind(_) = command {
+            return close(socket, close_code::NORMAL, "Unbound").await;
+        }
+    }
+
+    let _ = ldap.unbind().await;
+}
@@ -0,0 +1,3 @@
+mod server_key;
+
+pub use self::server_key::{server_key, ServerKeyResponse};
@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use axum::{extract, http::StatusCode};
+use schemars::JsonSchema;
+use serde::Serialize;
+use ssh_key::HashAlg;
+
+use crate::{
+    signing,
+    types::routes::{DataResponse, Response},
+    AppState,
+};
+
+#[derive(Serialize, JsonSchema)]
+pub struct ServerKeyResponse {
+    /// Public key in OpenSSH format.
+    pub public_key: String,
+    pub fingerprint: String,
+    /// SSHSIG namespace of the response signatures.
+    pub namespace: String,
+}
+
+/// Publishes the public key responses are signed with.
+pub async fn server_key(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    let key = match &state.server_key {
+        Some(val) => val.public_key(),
+        None => {
+            return Response::error(StatusCode::NOT_FOUND, "Responses are not signed");
+        }
+    };
+
+    let public_key = match key.to_openssh() {
+        Ok(val) => val,
+        Err(err) => {
+            return Response::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to encode server key: {}", err),
+            )
+        }
+    };
+
+    Response {
+        status: StatusCode::OK,
+        body: Box::new(DataResponse {
+            result: true,
+            data: ServerKeyResponse {
+                public_key,
+                fingerprint: key.fingerprint(HashAlg::Sha256).to_string(),
+                namespace: signing::namespace(),
+            },
+        }),
+    }
+}
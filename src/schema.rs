@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use ldap3_serde::{Ldap, LdapError, Scope, SearchEntry};
+
+/// Equality matching rules under which values differing only in case are equal.
+fn is_case_insensitive(rule: &str) -> bool {
+    let rule = rule.to_ascii_lowercase();
+    rule.starts_with("caseignore")
+        || rule == "objectidentifiermatch"
+        || rule == "distinguishednamematch"
+}
+
+/// Splits an RFC 4512 description into parentheses, quoted strings and bare words.
+fn tokenize(description: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = description.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | ')' => tokens.push(c.to_string()),
+            '\'' => {
+                let mut token = String::new();
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '(' && *c != ')')
+                {
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    tokens
+}
+
+/// Values of a field of a description: a single token, or a parenthesised list
+/// separated by `$`.
+fn field(tokens: &[String], name: &str) -> Vec<String> {
+    let start = match tokens.iter().position(|token| token == name) {
+        Some(val) => val + 1,
+        None => return Vec::new(),
+    };
+    match tokens.get(start).map(String::as_str) {
+        Some("(") => tokens[start + 1..]
+            .iter()
+            .take_while(|token| *token != ")")
+            .filter(|token| *token != "$")
+            .cloned()
+            .collect(),
+        Some(val) => vec![val.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Attribute type of a subschema entry.
+#[derive(Debug, Clone, Default)]
+struct AttributeType {
+    /// Lowercased names, the primary one first, followed by the OID.
+    names: Vec<String>,
+    equality: Option<String>,
+    sup: Option<String>,
+}
+
+/// Attribute types of a directory, by lowercased name and OID.
+#[derive(Debug, Default)]
+pub struct Subschema {
+    types: HashMap<String, AttributeType>,
+}
+
+/// Splits an attribute description into its type and options such as `;binary`.
+fn split_options(attr: &str) -> (&str, &str) {
+    match attr.find(';') {
+        Some(idx) => attr.split_at(idx),
+        None => (attr, ""),
+    }
+}
+
+impl Subschema {
+    /// Parses `attributeTypes` values.
+    pub fn parse(values: &[String]) -> Self {
+        let mut types = HashMap::new();
+        for value in values {
+            let tokens = tokenize(value);
+            let oid = match tokens.as_slice() {
+                [open, oid, ..] if open == "(" => oid.to_lowercase(),
+                _ => continue,
+            };
+            let mut names = field(&tokens, "NAME")
+                .into_iter()
+                .map(|name| name.to_lowercase())
+                .collect::<Vec<_>>();
+            names.push(oid);
+            let attribute_type = AttributeType {
+                names,
+                equality: field(&tokens, "EQUALITY").into_iter().next(),
+                sup: field(&tokens, "SUP").into_iter().next(),
+            };
+            for name in attribute_type.names.iter() {
+                types.insert(name.clone(), attribute_type.clone());
+            }
+        }
+        Subschema { types }
+    }
+
+    /// Reads the attribute types from the subschema entry named in the root DSE.
+    pub async fn read(ldap: &mut Ldap) -> Result<Self, LdapError> {
+        let subschema = match ldap
+            .search(
+                "",
+                Scope::Base,
+                "(objectClass=*)",
+                vec!["subschemaSubentry"],
+            )
+            .await
+            .and_then(|res| res.success())
+        {
+            Ok((entries, _)) => entries
+                .into_iter()
+                .next()
+                .map(SearchEntry::construct)
+                .and_then(|entry| entry.attrs.into_values().flatten().next()),
+            Err(err) => {
+                tracing::warn!("Failed to read root DSE: {}", err);
+                None
+            }
+        };
+        let subschema = subschema.unwrap_or_else(|| "cn=Subschema".to_string());
+
+        let (entries, _) = ldap
+            .search(
+                &subschema,
+                Scope::Base,
+                "(objectClass=subschema)",
+                vec!["attributeTypes"],
+            )
+            .await
+            .and_then(|res| res.success())?;
+        let values = entries
+            .into_iter()
+            .next()
+            .map(SearchEntry::construct)
+            .and_then(|entry| entry.attrs.into_values().next())
+            .unwrap_or_default();
+        Ok(Subschema::parse(&values))
+    }
+
+    /// Reads the subschema, falling back to an empty one, under which attributes
+    /// are only known by the names they are given.
+    pub async fn read_or_default(ldap: &mut Ldap) -> Self {
+        match Subschema::read(ldap).await {
+            Ok(val) => val,
+            Err(err) => {
+                tracing::warn!("Failed to read subschema: {}", err);
+                Subschema::default()
+            }
+        }
+    }
+
+    /// Lowercased primary name of an attribute given by any of its names or its
+    /// OID, keeping its options; the lowercased description if the type is unknown.
+    pub fn canonical(&self, attr: &str) -> String {
+        let (name, options) = split_options(attr);
+        let name = name.to_lowercase();
+        match self.types.get(&name).and_then(|val| val.names.first()) {
+            Some(primary) => format!("{}{}", primary, options.to_lowercase()),
+            None => format!("{}{}", name, options.to_lowercase()),
+        }
+    }
+
+    /// Lowercased names and OID of an attribute type; just the lowercased name if
+    /// the type is unknown.
+    pub fn names(&self, attr: &str) -> Vec<String> {
+        let name = attr.to_lowercase();
+        match self.types.get(&name) {
+            Some(val) => val.names.clone(),
+            None => vec![name],
+        }
+    }
+
+    /// Equality matching rule of an attribute, inherited from its supertypes.
+    fn equality(&self, attr: &str) -> Option<&str> {
+        let mut name = split_options(attr).0.to_lowercase();
+        // Bounded, in case of a cycle in a broken schema.
+        for _ in 0..16 {
+            let attribute_type = self.types.get(&name)?;
+            if let Some(val) = &attribute_type.equality {
+                return Some(val);
+            }
+            name = attribute_type.sup.as_ref()?.to_lowercase();
+        }
+        None
+    }
+
+    /// Whether the directory compares values of the attribute ignoring case.
+    pub fn is_case_insensitive(&self, attr: &str) -> bool {
+        self.equality(attr).is_some_and(is_case_insensitive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subschema() -> Subschema {
+        Subschema::parse(&[
+            "( 2.5.4.41 NAME 'name' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15{32768} )".to_string(),
+            "( 2.5.4.3 NAME ( 'cn' 'commonName' ) DESC 'RFC4519: common name(s)' SUP name )".to_string(),
+            "( 2.5.4.35 NAME 'userPassword' EQUALITY octetStringMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.40 )".to_string(),
+            "( 2.5.4.0 NAME 'objectClass' EQUALITY objectIdentifierMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 )".to_string(),
+        ])
+    }
+
+    #[test]
+    fn tokenizes_quoted_names() {
+        assert_eq!(
+            tokenize("( 1.2 NAME ( 'a b' 'c' ) SUP x)"),
+            vec!["(", "1.2", "NAME", "(", "a b", "c", ")", "SUP", "x", ")"]
+        );
+    }
+
+    #[test]
+    fn inherits_equality_from_supertype() {
+        let subschema = subschema();
+        assert_eq!(subschema.equality("commonName"), Some("caseIgnoreMatch"));
+        assert_eq!(subschema.equality("CN"), Some("caseIgnoreMatch"));
+        assert_eq!(subschema.equality("unknown"), None);
+    }
+
+    #[test]
+    fn selects_case_insensitive_attributes() {
+        let subschema = subschema();
+        assert!(subschema.is_case_insensitive("cn"));
+        assert!(subschema.is_case_insensitive("objectClass"));
+        assert!(!subschema.is_case_insensitive("userPassword"));
+        assert!(!subschema.is_case_insensitive("unknown"));
+    }
+
+    #[test]
+    fn resolves_aliases_and_oids() {
+        let subschema = subschema();
+        assert_eq!(subschema.canonical("commonName"), "cn");
+        assert_eq!(subschema.canonical("2.5.4.3"), "cn");
+        assert_eq!(subschema.canonical("CN;lang-EN"), "cn;lang-en");
+        assert_eq!(subschema.canonical("unknown"), "unknown");
+        assert_eq!(
+            subschema.names("2.5.4.35"),
+            vec!["userpassword", "2.5.4.35"]
+        );
+        assert_eq!(subschema.names("mail"), vec!["mail"]);
+    }
+}
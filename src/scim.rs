@@ -0,0 +1,57 @@
+pub mod filter;
+pub mod mapping;
+pub mod patch;
+pub mod token;
+
+use axum::http::StatusCode;
+use serde::Serialize;
+
+use crate::types::routes::Response;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const ENTERPRISE_USER_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User";
+pub const LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+/// SCIM error response (RFC 7644 section 3.12).
+#[derive(Debug, Serialize)]
+pub struct ScimError {
+    #[serde(skip)]
+    pub code: StatusCode,
+    pub schemas: Vec<&'static str>,
+    pub status: String,
+    #[serde(rename = "scimType", skip_serializing_if = "Option::is_none")]
+    pub scim_type: Option<&'static str>,
+    pub detail: String,
+}
+
+impl ScimError {
+    pub fn new(
+        code: StatusCode,
+        scim_type: Option<&'static str>,
+        detail: impl Into<String>,
+    ) -> Self {
+        ScimError {
+            code,
+            schemas: vec![ERROR_SCHEMA],
+            status: code.as_u16().to_string(),
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn bad_request(scim_type: &'static str, detail: impl Into<String>) -> Self {
+        ScimError::new(StatusCode::BAD_REQUEST, Some(scim_type), detail)
+    }
+}
+
+impl From<ScimError> for Response {
+    fn from(value: ScimError) -> Self {
+        Response {
+            status: value.code,
+            body: Box::new(value),
+        }
+    }
+}
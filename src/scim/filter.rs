@@ -0,0 +1,427 @@
+use ldap3_serde::ldap_escape;
+use serde_json::Value;
+
+use super::{
+    mapping::{ResourceKind, ScimConfig},
+    ScimError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/// Parsed SCIM filter (RFC 7644 section 3.4.2.2).
+#[derive(Debug, Clone)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Present(String),
+    Compare(String, CompareOp, Value),
+    /// `attr[filter]`, where `filter` addresses sub-attributes of `attr`.
+    ValuePath(String, Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Word(String),
+    Str(String),
+}
+
+fn invalid(detail: impl Into<String>) -> ScimError {
+    ScimError::bad_request("invalidFilter", detail)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ScimError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {}
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '[' => tokens.push(Token::LBracket),
+            ']' => tokens.push(Token::RBracket),
+            '"' => {
+                let mut end = None;
+                let mut escaped = false;
+                for (pos, c) in chars.by_ref() {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => {
+                            end = Some(pos);
+                            break;
+                        }
+                        _ => escaped = false,
+                    }
+                }
+                let end = match end {
+                    Some(val) => val,
+                    None => return Err(invalid("Unterminated string")),
+                };
+                match serde_json::from_str::<String>(&input[idx..=end]) {
+                    Ok(val) => tokens.push(Token::Str(val)),
+                    Err(_) => return Err(invalid("Invalid string literal")),
+                }
+            }
+            _ => {
+                let mut end = idx + c.len_utf8();
+                while let Some((pos, c)) = chars.peek() {
+                    if matches!(c, ' ' | '\t' | '\n' | '\r' | '(' | ')' | '[' | ']' | '"') {
+                        break;
+                    }
+                    end = pos + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(input[idx..end].to_string()));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Deepest a filter may nest, counting every `and`, `or`, `not`, group and value path,
+/// so that parsing and translating it cannot exhaust the stack.
+const MAX_DEPTH: usize = 100;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ScimError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(invalid(format!("Expected {:?}", expected))),
+        }
+    }
+
+    /// Goes one level deeper, which the caller undoes with `self.depth -= 1`.
+    fn descend(&mut self) -> Result<(), ScimError> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(invalid(format!(
+                "Filter is nested more than {} levels deep",
+                MAX_DEPTH
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, ScimError> {
+        let depth = self.depth;
+        let mut filter = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            // Each operator nests the filter parsed so far one level deeper.
+            self.descend()?;
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        self.depth = depth;
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, ScimError> {
+        let depth = self.depth;
+        let mut filter = self.parse_unary()?;
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            self.descend()?;
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_unary()?));
+        }
+        self.depth = depth;
+        Ok(filter)
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter, ScimError> {
+        if self.peek_keyword("not") {
+            self.pos += 1;
+            self.expect(Token::LParen)?;
+            self.descend()?;
+            let filter = self.parse_or()?;
+            self.depth -= 1;
+            self.expect(Token::RParen)?;
+            return Ok(Filter::Not(Box::new(filter)));
+        }
+
+        let attr = match self.next() {
+            Some(Token::LParen) => {
+                self.descend()?;
+                let filter = self.parse_or()?;
+                self.depth -= 1;
+                self.expect(Token::RParen)?;
+                return Ok(filter);
+            }
+            Some(Token::Word(word)) => word,
+            _ => return Err(invalid("Expected attribute path")),
+        };
+
+        if self.peek() == Some(&Token::LBracket) {
+            self.pos += 1;
+            self.descend()?;
+            let filter = self.parse_or()?;
+            self.depth -= 1;
+            self.expect(Token::RBracket)?;
+            return Ok(Filter::ValuePath(attr, Box::new(filter)));
+        }
+
+        let op = match self.next() {
+            Some(Token::Word(word)) => word.to_ascii_lowercase(),
+            _ => return Err(invalid(format!("Expected operator after \"{}\"", attr))),
+        };
+        let op = match op.as_str() {
+            "pr" => return Ok(Filter::Present(attr)),
+            "eq" => CompareOp::Eq,
+            "ne" => CompareOp::Ne,
+            "co" => CompareOp::Co,
+            "sw" => CompareOp::Sw,
+            "ew" => CompareOp::Ew,
+            "gt" => CompareOp::Gt,
+            "ge" => CompareOp::Ge,
+            "lt" => CompareOp::Lt,
+            "le" => CompareOp::Le,
+            _ => return Err(invalid(format!("Unknown operator \"{}\"", op))),
+        };
+
+        let value = match self.next() {
+            Some(Token::Str(val)) => Value::String(val),
+            Some(Token::Word(word)) => match serde_json::from_str::<Value>(&word) {
+                Ok(val @ (Value::Bool(_) | Value::Null | Value::Number(_))) => val,
+                _ => return Err(invalid(format!("Invalid value \"{}\"", word))),
+            },
+            _ => return Err(invalid(format!("Expected value after \"{}\"", attr))),
+        };
+
+        Ok(Filter::Compare(attr, op, value))
+    }
+}
+
+pub fn parse(input: &str) -> Result<Filter, ScimError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        depth: 0,
+    };
+    let filter = parser.parse_or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(invalid("Unexpected trailing input"));
+    }
+    Ok(filter)
+}
+
+impl Filter {
+    /// Translates the filter into an RFC 4515 LDAP filter for resources of `kind`.
+    pub fn to_ldap(&self, config: &ScimConfig, kind: ResourceKind) -> Result<String, ScimError> {
+        self.render(config, kind, None)
+    }
+
+    fn render(
+        &self,
+        config: &ScimConfig,
+        kind: ResourceKind,
+        prefix: Option<&str>,
+    ) -> Result<String, ScimError> {
+        let path = |attr: &str| match prefix {
+            Some(prefix) => format!("{}.{}", prefix, attr),
+            None => attr.to_string(),
+        };
+
+        match self {
+            Filter::And(a, b) => Ok(format!(
+                "(&{}{})",
+                a.render(config, kind, prefix)?,
+                b.render(config, kind, prefix)?
+            )),
+            Filter::Or(a, b) => Ok(format!(
+                "(|{}{})",
+                a.render(config, kind, prefix)?,
+                b.render(config, kind, prefix)?
+            )),
+            Filter::Not(a) => Ok(format!("(!{})", a.render(config, kind, prefix)?)),
+            Filter::ValuePath(attr, filter) => {
+                if prefix.is_some() {
+                    return Err(invalid("Nested value filters are not supported"));
+                }
+                filter.render(config, kind, Some(attr))
+            }
+            Filter::Present(attr) => {
+                let (ldap, _) = resolve(config, kind, &path(attr))?;
+                Ok(format!("({}=*)", ldap))
+            }
+            Filter::Compare(attr, op, value) => {
+                let (ldap, reference) = resolve(config, kind, &path(attr))?;
+
+                let value = match value {
+                    Value::String(val) => val.clone(),
+                    Value::Bool(val) => if *val { "TRUE" } else { "FALSE" }.to_string(),
+                    Value::Number(val) => val.to_string(),
+                    Value::Null => {
+                        return match op {
+                            CompareOp::Eq => Ok(format!("(!({}=*))", ldap)),
+                            CompareOp::Ne => Ok(format!("({}=*)", ldap)),
+                            _ => Err(invalid("null can only be compared with eq or ne")),
+                        }
+                    }
+                    _ => return Err(invalid("Invalid comparison value")),
+                };
+
+                let value = match reference {
+                    Some(kind) if matches!(op, CompareOp::Eq | CompareOp::Ne) => {
+                        config.dn_for(kind, &value)
+                    }
+                    Some(_) => {
+                        return Err(invalid(format!(
+                            "Only eq and ne are supported for \"{}\"",
+                            path(attr)
+                        )))
+                    }
+                    None => value,
+                };
+                let value = ldap_escape(value);
+
+                Ok(match op {
+                    CompareOp::Eq => format!("({}={})", ldap, value),
+                    CompareOp::Ne => format!("(!({}={}))", ldap, value),
+                    CompareOp::Co => format!("({}=*{}*)", ldap, value),
+                    CompareOp::Sw => format!("({}={}*)", ldap, value),
+                    CompareOp::Ew => format!("({}=*{})", ldap, value),
+                    CompareOp::Ge => format!("({}>={})", ldap, value),
+                    CompareOp::Le => format!("({}<={})", ldap, value),
+                    CompareOp::Gt => format!("(&({}>={})(!({}={})))", ldap, value, ldap, value),
+                    CompareOp::Lt => format!("(&({}<={})(!({}={})))", ldap, value, ldap, value),
+                })
+            }
+        }
+    }
+}
+
+/// Resolves a SCIM attribute path to its LDAP attribute and reference kind.
+fn resolve(
+    config: &ScimConfig,
+    kind: ResourceKind,
+    path: &str,
+) -> Result<(String, Option<ResourceKind>), ScimError> {
+    let mapping = config.mapping(kind);
+    if path.eq_ignore_ascii_case("id") {
+        return Ok((mapping.id_attr.clone(), None));
+    }
+
+    match mapping.find(path) {
+        Some(attribute) => Ok((attribute.ldap.clone(), attribute.reference)),
+        None => Err(invalid(format!("Unsupported attribute \"{}\"", path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ldap(filter: &str) -> Result<String, ScimError> {
+        parse(filter).and_then(|val| val.to_ldap(&ScimConfig::default(), ResourceKind::User))
+    }
+
+    #[test]
+    fn translates_comparisons() {
+        assert_eq!(ldap("userName eq \"bjensen\"").unwrap(), "(uid=bjensen)");
+        assert_eq!(
+            ldap("name.familyName sw \"J\" and not (title pr)").unwrap(),
+            "(&(sn=J*)(!(title=*)))"
+        );
+        assert_eq!(
+            ldap("emails[value co \"@example.com\"] or id eq \"a*b\"").unwrap(),
+            "(|(mail=*@example.com*)(uid=a\\2ab))"
+        );
+        assert_eq!(ldap("title gt \"b\"").unwrap(), "(&(title>=b)(!(title=b)))");
+        assert_eq!(ldap("title eq null").unwrap(), "(!(title=*))");
+    }
+
+    #[test]
+    fn resolves_references_to_dns() {
+        assert_eq!(
+            ldap("urn:ietf:params:scim:schemas:extension:enterprise:2.0:User:manager.value eq \"boss\"")
+                .unwrap(),
+            "(manager=uid=boss,ou=people,dc=example,dc=com)"
+        );
+        assert!(ldap(
+            "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User:manager.value sw \"b\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for filter in [
+            "userName eq",
+            "userName xx \"a\"",
+            "(userName eq \"a\"",
+            "userName eq \"a",
+            "userName eq \"a\" extra",
+            "unknown eq \"a\"",
+            "emails[value[value pr]]",
+            // Multi-byte characters must not be sliced on byte offsets.
+            "éaaaaa pr",
+            "aéaaaaa pr",
+        ] {
+            assert!(ldap(filter).is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| {
+            format!(
+                "{}userName eq \"a\"{}",
+                "not (".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(parse(&format!(
+            "{}userName pr{}",
+            "(".repeat(100_000),
+            ")".repeat(100_000)
+        ))
+        .is_err());
+
+        let chain = |terms: usize| vec!["userName pr"; terms].join(" or ");
+        assert!(parse(&chain(MAX_DEPTH + 1)).is_ok());
+        assert!(parse(&chain(MAX_DEPTH + 2)).is_err());
+
+        match parse(&nested(MAX_DEPTH + 1)) {
+            Err(err) => assert_eq!(err.scim_type, Some("invalidFilter")),
+            Ok(_) => panic!("nested filter was accepted"),
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashSet;
+
+use ldap3_serde::{dn_escape, SearchEntry};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::dn;
+
+use super::{ScimError, ENTERPRISE_USER_SCHEMA, GROUP_SCHEMA, USER_SCHEMA};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ResourceKind {
+    User,
+    Group,
+}
+
+impl ResourceKind {
+    /// Resolves the last path segment of a resource endpoint, e.g. `Users`.
+    pub fn from_endpoint(endpoint: &str) -> Option<Self> {
+        match endpoint {
+            "Users" => Some(ResourceKind::User),
+            "Groups" => Some(ResourceKind::Group),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResourceKind::User => "User",
+            ResourceKind::Group => "Group",
+        }
+    }
+
+    pub fn endpoint(&self) -> &'static str {
+        match self {
+            ResourceKind::User => "Users",
+            ResourceKind::Group => "Groups",
+        }
+    }
+
+    pub fn schema(&self) -> &'static str {
+        match self {
+            ResourceKind::User => USER_SCHEMA,
+            ResourceKind::Group => GROUP_SCHEMA,
+        }
+    }
+}
+
+/// Maps one SCIM attribute to one LDAP attribute.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttributeMapping {
+    /// SCIM attribute path, e.g. `name.givenName`, `emails`, or a fully qualified
+    /// extension attribute such as
+    /// `urn:ietf:params:scim:schemas:extension:enterprise:2.0:User:department`.
+    pub scim: String,
+    pub ldap: String,
+    /// Rendered as an array of `{"value": ...}` objects.
+    #[serde(default)]
+    pub multi_valued: bool,
+    /// Values are DNs of resources of this kind, exposed by their SCIM id.
+    #[serde(default)]
+    pub reference: Option<ResourceKind>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResourceMapping {
+    /// Entries of this resource type live below this DN.
+    pub base: String,
+    /// LDAP attribute holding the SCIM id, also used as the RDN of new entries.
+    pub id_attr: String,
+    pub object_classes: Vec<String>,
+    pub attributes: Vec<AttributeMapping>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScimConfig {
+    pub users: ResourceMapping,
+    pub groups: ResourceMapping,
+}
+
+fn attribute(scim: &str, ldap: &str) -> AttributeMapping {
+    AttributeMapping {
+        scim: scim.to_string(),
+        ldap: ldap.to_string(),
+        multi_valued: false,
+        reference: None,
+    }
+}
+
+impl Default for ScimConfig {
+    fn default() -> Self {
+        let base_dn =
+            dotenv::var("SCIM_BASE_DN").unwrap_or_else(|_| "dc=example,dc=com".to_string());
+
+        ScimConfig {
+            users: ResourceMapping {
+                base: format!("ou=people,{}", base_dn),
+                id_attr: "uid".to_string(),
+                object_classes: vec![
+                    "top".to_string(),
+                    "person".to_string(),
+                    "organizationalPerson".to_string(),
+                    "inetOrgPerson".to_string(),
+                ],
+                attributes: vec![
+                    attribute("userName", "uid"),
+                    attribute("name.formatted", "cn"),
+                    attribute("name.givenName", "givenName"),
+                    attribute("name.familyName", "sn"),
+                    attribute("displayName", "displayName"),
+                    attribute("title", "title"),
+                    AttributeMapping {
+                        multi_valued: true,
+                        ..attribute("emails", "mail")
+                    },
+                    AttributeMapping {
+                        multi_valued: true,
+                        ..attribute("phoneNumbers", "telephoneNumber")
+                    },
+                    attribute(
+                        &format!("{}:employeeNumber", ENTERPRISE_USER_SCHEMA),
+                        "employeeNumber",
+                    ),
+                    attribute(
+                        &format!("{}:department", ENTERPRISE_USER_SCHEMA),
+                        "departmentNumber",
+                    ),
+                    AttributeMapping {
+                        reference: Some(ResourceKind::User),
+                        ..attribute(&format!("{}:manager", ENTERPRISE_USER_SCHEMA), "manager")
+                    },
+                ],
+            },
+            groups: ResourceMapping {
+                base: format!("ou=groups,{}", base_dn),
+                id_attr: "cn".to_string(),
+                object_classes: vec!["top".to_string(), "groupOfNames".to_string()],
+                attributes: vec![
+                    attribute("displayName", "cn"),
+                    AttributeMapping {
+                        multi_valued: true,
+                        reference: Some(ResourceKind::User),
+                        ..attribute("members", "member")
+                    },
+                ],
+            },
+        }
+    }
+}
+
+/// Splits a SCIM attribute path into its schema URN (if fully qualified) and the
+/// dot-separated attribute names below it.
+fn split_path(path: &str) -> (Option<&str>, Vec<&str>) {
+    if !path.starts_with("urn:") {
+        return (None, path.split('.').collect());
+    }
+
+    match path.rfind(':') {
+        Some(idx) => (Some(&path[..idx]), path[idx + 1..].split('.').collect()),
+        None => (None, vec![path]),
+    }
+}
+
+fn get_ignore_case<'a>(object: &'a Value, key: &str) -> Option<&'a Value> {
+    object
+        .as_object()?
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value)
+}
+
+/// Finds the value at a SCIM attribute path within a resource document.
+pub fn lookup<'a>(resource: &'a Value, path: &str) -> Option<&'a Value> {
+    let (urn, names) = split_path(path);
+    let mut current = match urn {
+        Some(urn) if is_core_schema(urn) => resource,
+        Some(urn) => get_ignore_case(resource, urn)?,
+        None => resource,
+    };
+
+    for name in names {
+        current = get_ignore_case(current, name)?;
+    }
+
+    Some(current)
+}
+
+fn is_core_schema(urn: &str) -> bool {
+    urn.eq_ignore_ascii_case(USER_SCHEMA) || urn.eq_ignore_ascii_case(GROUP_SCHEMA)
+}
+
+fn insert(resource: &mut Map<String, Value>, path: &str, value: Value) {
+    let (urn, names) = split_path(path);
+    let (last, parents) = match names.split_last() {
+        Some(val) => val,
+        None => return,
+    };
+
+    let mut current = resource;
+    let keys = urn
+        .filter(|urn| !is_core_schema(urn))
+        .into_iter()
+        .chain(parents.iter().copied());
+    for key in keys {
+        current = match current
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(val) => val,
+            _ => return,
+        };
+    }
+
+    current.insert(last.to_string(), value);
+}
+
+/// Converts a scalar JSON value into an LDAP attribute value.
+fn scalar(value: &Value) -> Result<String, ScimError> {
+    match value {
+        Value::String(val) => Ok(val.clone()),
+        Value::Number(val) => Ok(val.to_string()),
+        Value::Bool(val) => Ok(if *val { "TRUE" } else { "FALSE" }.to_string()),
+        _ => Err(ScimError::bad_request(
+            "invalidValue",
+            format!("Expected a scalar value, found {}", value),
+        )),
+    }
+}
+
+impl ScimConfig {
+    pub fn load() -> Result<Self, String> {
+        let path = match dotenv::var("SCIM_MAPPING_PATH") {
+            Ok(val) => val,
+            Err(_) => return Ok(ScimConfig::default()),
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+        };
+
+        match serde_json::from_str(&content) {
+            Ok(val) => Ok(val),
+            Err(err) => Err(format!("Failed to parse {}: {}", path, err)),
+        }
+    }
+
+    pub fn mapping(&self, kind: ResourceKind) -> &ResourceMapping {
+        match kind {
+            ResourceKind::User => &self.users,
+            ResourceKind::Group => &self.groups,
+        }
+    }
+
+    pub fn dn_for(&self, kind: ResourceKind, id: &str) -> String {
+        let mapping = self.mapping(kind);
+        format!("{}={},{}", mapping.id_attr, dn_escape(id), mapping.base)
+    }
+
+    /// Returns the SCIM id of the resource at `dn`, taken from its leftmost RDN.
+    pub fn id_for_dn(&self, dn: &str) -> Option<String> {
+        let dn = dn::parse(dn).ok()?;
+        let rdn = dn.0.into_iter().next()?;
+        rdn.0.into_iter().next().map(|ava| ava.value)
+    }
+
+    /// Converts the JSON value of a mapped attribute into LDAP attribute values.
+    pub fn values(
+        &self,
+        attribute: &AttributeMapping,
+        value: &Value,
+    ) -> Result<HashSet<String>, ScimError> {
+        let items = match value {
+            Value::Array(val) => val.iter().collect::<Vec<_>>(),
+            Value::Null => vec![],
+            val => vec![val],
+        };
+
+        items
+            .into_iter()
+            .map(|item| {
+                let item = match item {
+                    Value::Object(_) => match get_ignore_case(item, "value") {
+                        Some(val) => val,
+                        None => {
+                            return Err(ScimError::bad_request(
+                                "invalidValue",
+                                format!("Missing \"value\" in {}", attribute.scim),
+                            ))
+                        }
+                    },
+                    val => val,
+                };
+                let value = scalar(item)?;
+                Ok(match attribute.reference {
+                    Some(kind) => self.dn_for(kind, &value),
+                    None => value,
+                })
+            })
+            .collect()
+    }
+
+    /// Renders an LDAP entry as a SCIM resource.
+    pub fn to_resource(&self, kind: ResourceKind, entry: &SearchEntry) -> Value {
+        let mapping = self.mapping(kind);
+        let find = |attr: &str| {
+            entry
+                .attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(attr))
+                .map(|(_, values)| values.clone())
+                .unwrap_or_default()
+        };
+
+        let id = find(&mapping.id_attr)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| entry.dn.clone());
+
+        let mut resource = Map::new();
+        resource.insert("schemas".to_string(), Value::from(vec![kind.schema()]));
+        resource.insert("id".to_string(), Value::from(id.clone()));
+        resource.insert(
+            "meta".to_string(),
+            serde_json::json!({
+                "resourceType": kind.name(),
+                "location": format!("/scim/v2/{}/{}", kind.endpoint(), id),
+            }),
+        );
+
+        for attribute in mapping.attributes.iter() {
+            let values = find(&attribute.ldap)
+                .into_iter()
+                .map(|value| match attribute.reference {
+                    Some(_) => self.id_for_dn(&value).unwrap_or(value),
+                    None => value,
+                })
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                continue;
+            }
+
+            let value = match (attribute.multi_valued, attribute.reference.is_some()) {
+                (true, _) => Value::from(
+                    values
+                        .into_iter()
+                        .map(|value| serde_json::json!({ "value": value }))
+                        .collect::<Vec<_>>(),
+                ),
+                (false, true) => serde_json::json!({ "value": values[0] }),
+                (false, false) => Value::from(values[0].clone()),
+            };
+            insert(&mut resource, &attribute.scim, value);
+        }
+
+        let extensions = resource
+            .keys()
+            .filter(|key| key.starts_with("urn:"))
+            .cloned()
+            .collect::<Vec<_>>();
+        if let Some(Value::Array(schemas)) = resource.get_mut("schemas") {
+            schemas.extend(extensions.into_iter().map(Value::from));
+        }
+
+        Value::Object(resource)
+    }
+
+    /// Converts a SCIM resource into LDAP attributes, skipping absent attributes.
+    pub fn to_attrs(
+        &self,
+        kind: ResourceKind,
+        resource: &Value,
+    ) -> Result<Vec<(String, HashSet<String>)>, ScimError> {
+        let mapping = self.mapping(kind);
+        let mut attrs = Vec::<(String, HashSet<String>)>::new();
+
+        for attribute in mapping.attributes.iter() {
+            let value = match lookup(resource, &attribute.scim) {
+                Some(val) => val,
+                None => continue,
+            };
+            let values = self.values(attribute, value)?;
+            match attrs
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&attribute.ldap))
+            {
+                Some((_, existing)) => existing.extend(values),
+                None => attrs.push((attribute.ldap.clone(), values)),
+            }
+        }
+
+        Ok(attrs)
+    }
+}
+
+impl ResourceMapping {
+    /// Finds the mapping for a SCIM attribute path. Sub-attribute `value` of a
+    /// multi-valued or reference attribute resolves to the attribute itself.
+    pub fn find(&self, path: &str) -> Option<&AttributeMapping> {
+        let path = [USER_SCHEMA, GROUP_SCHEMA]
+            .iter()
+            .find_map(|schema| {
+                path.get(..schema.len() + 1)
+                    .filter(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", schema)))
+                    .map(|_| &path[schema.len() + 1..])
+            })
+            .unwrap_or(path);
+
+        if let Some(attribute) = self
+            .attributes
+            .iter()
+            .find(|attribute| attribute.scim.eq_ignore_ascii_case(path))
+        {
+            return Some(attribute);
+        }
+
+        let parent = path
+            .len()
+            .checked_sub(".value".len())
+            .filter(|idx| {
+                path.get(*idx..)
+                    .is_some_and(|suffix| suffix.eq_ignore_ascii_case(".value"))
+            })
+            .map(|idx| &path[..idx])?;
+        self.attributes.iter().find(|attribute| {
+            (attribute.multi_valued || attribute.reference.is_some())
+                && attribute.scim.eq_ignore_ascii_case(parent)
+        })
+    }
+
+    /// LDAP attributes to request when reading resources of this type.
+    pub fn ldap_attrs(&self) -> Vec<String> {
+        let mut attrs = vec![self.id_attr.clone()];
+        for attribute in self.attributes.iter() {
+            if !attrs
+                .iter()
+                .any(|attr| attr.eq_ignore_ascii_case(&attribute.ldap))
+            {
+                attrs.push(attribute.ldap.clone());
+            }
+        }
+        attrs
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::types::query::{AddMod, DeleteMod, Mod, ReplaceMod};
+
+use super::{
+    filter::{self, CompareOp, Filter},
+    mapping::{lookup, AttributeMapping, ResourceKind, ScimConfig},
+    ScimError,
+};
+
+/// SCIM PATCH request body (RFC 7644 section 3.5.2).
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+/// Collects the values selected by a `value eq "..."` filter, possibly or-ed.
+fn selected_values(filter: &Filter, values: &mut Vec<String>) -> Result<(), ScimError> {
+    match filter {
+        Filter::Or(a, b) => {
+            selected_values(a, values)?;
+            selected_values(b, values)
+        }
+        Filter::Compare(attr, CompareOp::Eq, Value::String(value))
+            if attr.eq_ignore_ascii_case("value") =>
+        {
+            values.push(value.clone());
+            Ok(())
+        }
+        _ => Err(ScimError::bad_request(
+            "invalidPath",
+            "Only value eq filters are supported in PATCH paths",
+        )),
+    }
+}
+
+/// Splits `attr[filter]` into the attribute path and the values its filter selects.
+fn parse_path(path: &str) -> Result<(String, Option<Vec<String>>), ScimError> {
+    let (attr, rest) = match path.split_once('[') {
+        Some(val) => val,
+        None => return Ok((path.to_string(), None)),
+    };
+
+    let inner = match rest.strip_suffix(']') {
+        Some(val) => val,
+        None => {
+            return Err(ScimError::bad_request(
+                "invalidPath",
+                format!("Unsupported path \"{}\"", path),
+            ))
+        }
+    };
+
+    let mut values = Vec::new();
+    selected_values(&filter::parse(inner)?, &mut values)?;
+    Ok((attr.to_string(), Some(values)))
+}
+
+/// Returns the mapped attributes addressed by `path`, each paired with the path of
+/// its value relative to the operation value (`None` if the value itself).
+fn targets<'a>(
+    config: &'a ScimConfig,
+    kind: ResourceKind,
+    path: &str,
+) -> Vec<(&'a AttributeMapping, Option<String>)> {
+    let mapping = config.mapping(kind);
+    if let Some(attribute) = mapping.find(path) {
+        return vec![(attribute, None)];
+    }
+
+    let prefix = format!("{}.", path.to_ascii_lowercase());
+    mapping
+        .attributes
+        .iter()
+        .filter(|attribute| attribute.scim.to_ascii_lowercase().starts_with(&prefix))
+        .map(|attribute| (attribute, Some(attribute.scim[prefix.len()..].to_string())))
+        .collect()
+}
+
+fn modification(
+    op: &str,
+    attribute: &AttributeMapping,
+    values: HashSet<String>,
+) -> Result<Mod, ScimError> {
+    let attr = attribute.ldap.clone();
+    match op {
+        "add" if attribute.multi_valued => Ok(Mod::Add(AddMod { attr, values })),
+        "add" | "replace" => Ok(Mod::Replace(ReplaceMod { attr, values })),
+        "remove" if attribute.multi_valued => Ok(Mod::Delete(DeleteMod { attr, values })),
+        "remove" => Ok(Mod::Delete(DeleteMod {
+            attr,
+            values: HashSet::new(),
+        })),
+        _ => Err(ScimError::bad_request(
+            "invalidSyntax",
+            format!("Unknown operation \"{}\"", op),
+        )),
+    }
+}
+
+/// Translates SCIM PATCH operations into LDAP modifications.
+pub fn to_mods(
+    config: &ScimConfig,
+    kind: ResourceKind,
+    request: PatchRequest,
+) -> Result<Vec<Mod>, ScimError> {
+    let mut changes = Vec::new();
+
+    for operation in request.operations.into_iter() {
+        let op = operation.op.to_ascii_lowercase();
+
+        let path = match operation.path {
+            Some(val) => val,
+            None => {
+                let value = match operation.value {
+                    Some(val @ Value::Object(_)) => val,
+                    _ => {
+                        return Err(ScimError::bad_request(
+                            "invalidValue",
+                            "Operations without a path require an object value",
+                        ))
+                    }
+                };
+                for attribute in config.mapping(kind).attributes.iter() {
+                    if let Some(item) = lookup(&value, &attribute.scim) {
+                        changes.push(modification(
+                            &op,
+                            attribute,
+                            config.values(attribute, item)?,
+                        )?);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let (path, selected) = parse_path(&path)?;
+        if path.eq_ignore_ascii_case("id") {
+            return Err(ScimError::bad_request(
+                "mutability",
+                "Attribute \"id\" is immutable",
+            ));
+        }
+
+        let targets = targets(config, kind, &path);
+        if targets.is_empty() {
+            return Err(ScimError::bad_request(
+                "noTarget",
+                format!("Unsupported attribute \"{}\"", path),
+            ));
+        }
+
+        for (attribute, relative) in targets {
+            let values = match (&selected, &operation.value, &relative) {
+                (Some(selected), _, _) => {
+                    if op != "remove" {
+                        return Err(ScimError::bad_request(
+                            "invalidPath",
+                            "Value filters are only supported for remove",
+                        ));
+                    }
+                    config.values(attribute, &Value::from(selected.clone()))?
+                }
+                (None, Some(value), None) => config.values(attribute, value)?,
+                (None, Some(value), Some(relative)) => match lookup(value, relative) {
+                    Some(val) => config.values(attribute, val)?,
+                    None => continue,
+                },
+                (None, None, _) if op == "remove" => HashSet::new(),
+                (None, None, _) => {
+                    return Err(ScimError::bad_request(
+                        "invalidValue",
+                        format!("Operation \"{}\" requires a value", op),
+                    ))
+                }
+            };
+            changes.push(modification(&op, attribute, values)?);
+        }
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,90 @@
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use ssh_key::authorized_keys::ConfigOpts;
+
+use crate::access::KeyAccess;
+
+/// Entry of `SCIM_TOKENS_PATH`, naming a SCIM client authenticated by a bearer token.
+#[derive(Deserialize)]
+struct ScimTokenConfig {
+    /// Hex SHA-256 digest of the token, as printed by `sha256sum`, so the file holds
+    /// no usable secret.
+    token_sha256: String,
+    /// Name the client is known by, in place of a key comment.
+    identity: String,
+    /// Restrictions, in the syntax of authorized_keys options.
+    #[serde(default)]
+    options: String,
+}
+
+pub struct ScimToken {
+    digest: [u8; 32],
+    pub identity: String,
+    pub access: KeyAccess,
+}
+
+impl ScimToken {
+    fn from_config(config: ScimTokenConfig) -> Result<Self, String> {
+        let mut digest = [0; 32];
+        if hex::decode_to_slice(config.token_sha256.trim(), &mut digest).is_err() {
+            return Err(format!(
+                "Invalid token_sha256 of {}: expected 64 hex digits",
+                config.identity
+            ));
+        }
+
+        let options = match ConfigOpts::new(config.options) {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Invalid options of {}: {}", config.identity, err)),
+        };
+        let access = match KeyAccess::from_options(&options) {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Invalid options of {}: {}", config.identity, err)),
+        };
+
+        Ok(ScimToken {
+            digest,
+            identity: config.identity,
+            access,
+        })
+    }
+
+    /// Fingerprint standing in for a key fingerprint, derived from the token digest.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "SHA256:{}",
+            base64::engine::general_purpose::STANDARD_NO_PAD.encode(self.digest)
+        )
+    }
+}
+
+/// Loads the bearer tokens of SCIM clients from `SCIM_TOKENS_PATH`, a JSON list; none
+/// are accepted when it is not set.
+pub fn load() -> Result<Vec<ScimToken>, String> {
+    let path = match dotenv::var("SCIM_TOKENS_PATH") {
+        Ok(val) => val,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+
+    let configs = match serde_json::from_str::<Vec<ScimTokenConfig>>(&content) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to parse {}: {}", path, err)),
+    };
+
+    configs.into_iter().map(ScimToken::from_config).collect()
+}
+
+/// Finds the entry of a presented token. Tokens are compared by digest, so the
+/// comparison time says nothing about the configured tokens.
+pub fn find<'a>(tokens: &'a [ScimToken], token: &str) -> Option<&'a ScimToken> {
+    let digest = Sha256::digest(token.as_bytes());
+    tokens
+        .iter()
+        .find(|entry| entry.digest.as_slice() == digest.as_slice())
+}
@@ -0,0 +1,126 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::Serialize;
+use ssh_key::{HashAlg, LineEnding, PrivateKey};
+
+use crate::{auth, context, types::routes, AppState};
+
+/// Reads the key responses are signed with from `SERVER_KEY_PATH`, an unencrypted
+/// OpenSSH private key; responses are not signed when it is not set.
+pub fn load() -> Result<Option<PrivateKey>, String> {
+    let path = match dotenv::var("SERVER_KEY_PATH") {
+        Ok(val) => val,
+        Err(_) => return Ok(None),
+    };
+
+    let key = match PrivateKey::read_openssh_file(Path::new(&path)) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+    if key.is_encrypted() {
+        return Err(format!("{} is encrypted", path));
+    }
+    Ok(Some(key))
+}
+
+/// SSHSIG namespace of response signatures, kept apart from the one of requests.
+pub fn namespace() -> String {
+    let namespace = dotenv::var("NAMESPACE").unwrap_or_else(|_| "ldap-rest".to_string());
+    format!("{}-response", namespace)
+}
+
+/// Message signed by the bridge for each response.
+#[derive(Serialize)]
+pub struct ResponseSignatureData {
+    /// Digest of the response body: `sha-256=:<base64>:`.
+    pub digest: String,
+    /// Digest of the signature of the request answered, if it was signed.
+    pub request_signature: Option<String>,
+    pub timestamp: i64,
+}
+
+fn signature_headers(key: &PrivateKey, body: &[u8]) -> Result<(HeaderValue, HeaderValue), String> {
+    let timestamp = Utc::now().timestamp();
+    let signature_data = ResponseSignatureData {
+        digest: auth::content_digest(body),
+        request_signature: context::current().request_signature,
+        timestamp,
+    };
+    let message = match serde_json::to_string(&signature_data) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to serialize signature data: {}", err)),
+    };
+
+    let signature = match key.sign(&namespace(), HashAlg::Sha512, message.as_bytes()) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to sign response: {}", err)),
+    };
+    let pem = match signature.to_pem(LineEnding::LF) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to encode signature: {}", err)),
+    };
+    // Armor-less and on one line, like request signatures in `X-Signature`.
+    let encoded = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+
+    match HeaderValue::from_str(&encoded) {
+        Ok(val) => Ok((HeaderValue::from(timestamp), val)),
+        Err(err) => Err(format!("Failed to encode signature: {}", err)),
+    }
+}
+
+fn internal_error() -> Response {
+    routes::Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+        .into_response()
+}
+
+/// Signs response bodies with the server key, in `X-Response-Timestamp` and
+/// `X-Response-Signature` headers. Event streams and WebSocket upgrades are left
+/// unsigned, as they have no single body.
+pub async fn sign(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let res = next.run(req).await;
+    let key = match &state.server_key {
+        Some(val) => val,
+        None => return res,
+    };
+
+    let streaming = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .is_some_and(|val| val.starts_with("text/event-stream"));
+    if streaming || res.status() == StatusCode::SWITCHING_PROTOCOLS {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Failed to read response body: {}", err);
+            return internal_error();
+        }
+    };
+
+    match signature_headers(key, &body) {
+        Ok((timestamp, signature)) => {
+            parts.headers.insert("X-Response-Timestamp", timestamp);
+            parts.headers.insert("X-Response-Signature", signature);
+        }
+        Err(err) => {
+            tracing::error!("{}", err);
+            return internal_error();
+        }
+    }
+    Response::from_parts(parts, Body::from(body))
+}
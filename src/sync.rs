@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use ldap3_serde::{
+    controls::{
+        parse_syncinfo, Control, ControlType, EntryState, MakeCritical, RefreshMode, SyncInfo,
+        SyncRequest, SyncState,
+    },
+    Ldap, ResultEntry, Scope, SearchEntry,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    access,
+    types::{
+        change::{ChangeEvent, ChangeKind, SyncEvent},
+        entry::Entry,
+    },
+};
+
+/// Result of a content synchronization search, with the cookie to resume after it.
+pub enum SyncMessage {
+    Change(ChangeEvent, Option<Vec<u8>>),
+    Sync(SyncEvent, Option<Vec<u8>>),
+    Error(String),
+}
+
+fn format_uuid(bytes: &[u8]) -> String {
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    if hex.len() != 32 {
+        return hex;
+    }
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Tracks what the consumer has been told so renames and deletions by
+/// `entryUUID` can be reported with DNs.
+struct SyncTracker {
+    names: HashMap<Vec<u8>, String>,
+    refreshed: bool,
+}
+
+impl SyncTracker {
+    fn entry(&mut self, entry: ResultEntry) -> Option<SyncMessage> {
+        let state = entry.1.iter().find_map(|ctrl| match ctrl {
+            Control(Some(ControlType::SyncState), raw) => Some(raw.parse::<SyncState>()),
+            _ => None,
+        })?;
+        let entry = SearchEntry::construct(entry);
+
+        let (kind, old_dn) = match state.state {
+            EntryState::Present => (ChangeKind::Present, None),
+            EntryState::Add => (ChangeKind::Add, None),
+            EntryState::Delete => (ChangeKind::Delete, None),
+            EntryState::Modify => match self.names.get(&state.entry_uuid) {
+                Some(old) if !old.eq_ignore_ascii_case(&entry.dn) => {
+                    (ChangeKind::ModDn, Some(old.clone()))
+                }
+                _ => (ChangeKind::Modify, None),
+            },
+        };
+
+        match kind {
+            ChangeKind::Delete => self.names.remove(&state.entry_uuid),
+            _ => self
+                .names
+                .insert(state.entry_uuid.clone(), entry.dn.clone()),
+        };
+
+        let change = ChangeEvent {
+            kind,
+            entry_uuid: format_uuid(&state.entry_uuid),
+            dn: Some(entry.dn.clone()),
+            old_dn,
+            entry: match kind {
+                ChangeKind::Add | ChangeKind::Modify | ChangeKind::ModDn => {
+                    Some(Entry::from(entry))
+                }
+                _ => None,
+            },
+        };
+        Some(SyncMessage::Change(change, state.cookie))
+    }
+
+    fn info(&mut self, info: SyncInfo) -> Vec<SyncMessage> {
+        let (cookie, refresh_done) = match info {
+            SyncInfo::NewCookie(cookie) => (Some(cookie), false),
+            SyncInfo::RefreshDelete {
+                cookie,
+                refresh_done,
+            }
+            | SyncInfo::RefreshPresent {
+                cookie,
+                refresh_done,
+            } => (cookie, refresh_done),
+            SyncInfo::SyncIdSet {
+                cookie,
+                refresh_deletes,
+                sync_uuids,
+            } => {
+                let kind = match refresh_deletes {
+                    true => ChangeKind::Delete,
+                    false => ChangeKind::Present,
+                };
+                let mut events = sync_uuids
+                    .into_iter()
+                    .map(|uuid| {
+                        let dn = match kind {
+                            ChangeKind::Delete => self.names.remove(&uuid),
+                            _ => self.names.get(&uuid).cloned(),
+                        };
+                        let change = ChangeEvent {
+                            kind,
+                            entry_uuid: format_uuid(&uuid),
+                            dn,
+                            old_dn: None,
+                            entry: None,
+                        };
+                        SyncMessage::Change(change, None)
+                    })
+                    .collect::<Vec<_>>();
+                events.push(SyncMessage::Sync(
+                    SyncEvent {
+                        refresh_done: self.refreshed,
+                    },
+                    cookie,
+                ));
+                return events;
+            }
+        };
+
+        self.refreshed |= refresh_done;
+        vec![SyncMessage::Sync(
+            SyncEvent {
+                refresh_done: self.refreshed,
+            },
+            cookie,
+        )]
+    }
+}
+
+/// Runs a refreshAndPersist content synchronization (RFC 4533) search and
+/// forwards its results until the receiver goes away. Attributes the signing key
+/// may not read are left out.
+pub async fn watch(
+    mut ldap: Ldap,
+    base: String,
+    filter: String,
+    attrs: Vec<String>,
+    cookie: Option<Vec<u8>>,
+    tx: mpsc::Sender<SyncMessage>,
+) {
+    let access = match access::resolve(&mut ldap).await {
+        Ok(val) => val,
+        Err(err) => {
+            let _ = tx
+                .send(SyncMessage::Error(format!(
+                    "Failed to start search: {:?}",
+                    err
+                )))
+                .await;
+            return;
+        }
+    };
+    if let Err(err) = access.check_filter(&filter) {
+        let _ = tx
+            .send(SyncMessage::Error(format!(
+                "Failed to start search: {:?}",
+                err
+            )))
+            .await;
+        return;
+    }
+
+    let request = SyncRequest {
+        mode: RefreshMode::RefreshAndPersist,
+        cookie,
+        reload_hint: false,
+    };
+    let mut stream = match ldap
+        .with_controls(request.critical())
+        .streaming_search(
+            &base,
+            Scope::Subtree,
+            &filter,
+            access.requested_attrs(&attrs),
+        )
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => {
+            let _ = tx
+                .send(SyncMessage::Error(format!(
+                    "Failed to start search: {:?}",
+                    err
+                )))
+                .await;
+            return;
+        }
+    };
+
+    let mut tracker = SyncTracker {
+        names: HashMap::new(),
+        refreshed: false,
+    };
+
+    'stream: loop {
+        let entry = tokio::select! {
+            _ = tx.closed() => break,
+            res = stream.next() => match res {
+                Ok(Some(val)) => val,
+                Ok(None) => {
+                    let res = stream.finish().await;
+                    if res.rc != 0 {
+                        let _ = tx.send(SyncMessage::Error(res.to_string())).await;
+                    }
+                    break;
+                }
+                Err(err) => {
+                    let _ = tx.send(SyncMessage::Error(format!("Search failed: {:?}", err))).await;
+                    break;
+                }
+            },
+        };
+
+        let mut entry = entry;
+        let messages = match entry.is_intermediate() {
+            true => tracker.info(parse_syncinfo(entry)),
+            false => {
+                access.filter_result_entry(&mut entry);
+                tracker.entry(entry).into_iter().collect()
+            }
+        };
+        for message in messages {
+            if tx.send(message).await.is_err() {
+                break 'stream;
+            }
+        }
+    }
+
+    let _ = ldap.unbind().await;
+}
@@ -1,2 +1,6 @@
+pub mod change;
+pub mod entry;
+pub mod patch;
 pub mod query;
 pub mod routes;
+pub mod secret;
@@ -0,0 +1,48 @@
+use serde::Serialize;
+
+use super::entry::Entry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Entry unchanged since the cookie, reported while refreshing.
+    Present,
+    Add,
+    Modify,
+    Delete,
+    /// Entry renamed or moved; `old_dn` holds its previous name. Only reported for
+    /// entries already seen on the stream, so not for renames found while resuming.
+    ModDn,
+}
+
+impl ChangeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Present => "present",
+            ChangeKind::Add => "add",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Delete => "delete",
+            ChangeKind::ModDn => "moddn",
+        }
+    }
+}
+
+/// Directory change pushed to subscribers.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeEvent {
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    /// `entryUUID` of the changed entry, which stays the same across renames.
+    pub entry_uuid: String,
+    pub dn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_dn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<Entry>,
+}
+
+/// Progress of the initial refresh, sent whenever the server hands out a new cookie.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncEvent {
+    pub refresh_done: bool,
+}
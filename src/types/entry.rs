@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ldap3_serde::SearchEntry;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// JSON representation of a directory entry. Binary values are base64-encoded.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Entry {
+    pub dn: String,
+    pub attrs: HashMap<String, Vec<String>>,
+    pub bin_attrs: HashMap<String, Vec<String>>,
+}
+
+impl From<SearchEntry> for Entry {
+    fn from(value: SearchEntry) -> Self {
+        Entry {
+            dn: value.dn,
+            attrs: value.attrs,
+            bin_attrs: value
+                .bin_attrs
+                .into_iter()
+                .map(|(attr, values)| {
+                    (
+                        attr,
+                        values.iter().map(|value| STANDARD.encode(value)).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::{collections::HashSet, fmt::Display};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::query::{AddMod, DeleteMod, Mod, ReplaceMod};
+
+/// A patch that cannot be expressed as LDAP modifications.
+#[derive(Debug)]
+pub struct PatchError(pub String);
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// RFC 6902 JSON Patch operation against the `Entry` document of an entry.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String },
+}
+
+/// Location within the `attrs` object of an entry addressed by a JSON pointer.
+enum Target {
+    /// `/attrs/{attr}`
+    Attr(String),
+    /// `/attrs/{attr}/-` or `/attrs/{attr}/{index}`
+    Value(String, Option<usize>),
+}
+
+fn parse_pointer(pointer: &str) -> Result<Target, PatchError> {
+    let tokens = match pointer.strip_prefix('/') {
+        Some(val) => val
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect::<Vec<_>>(),
+        None => return Err(PatchError(format!("Invalid JSON pointer \"{}\"", pointer))),
+    };
+
+    match tokens.as_slice() {
+        [root, attr] if root == "attrs" && !attr.is_empty() => Ok(Target::Attr(attr.clone())),
+        [root, attr, index] if root == "attrs" && !attr.is_empty() => {
+            if index == "-" {
+                return Ok(Target::Value(attr.clone(), None));
+            }
+            match index.parse::<usize>() {
+                Ok(val) => Ok(Target::Value(attr.clone(), Some(val))),
+                Err(_) => Err(PatchError(format!(
+                    "Invalid array index in \"{}\"",
+                    pointer
+                ))),
+            }
+        }
+        _ => Err(PatchError(format!(
+            "Only \"/attrs/{{attr}}\" paths can be patched, found \"{}\"",
+            pointer
+        ))),
+    }
+}
+
+fn positional(op: &str, attr: &str, index: Option<usize>) -> PatchError {
+    match index {
+        Some(index) => PatchError(format!(
+            "Cannot {} value {} of \"{}\": attribute values are unordered",
+            op, index, attr
+        )),
+        None => PatchError(format!("Cannot {} the end of \"{}\"", op, attr)),
+    }
+}
+
+/// Converts a JSON string or array of strings into a set of attribute values.
+fn values(value: Value) -> Result<HashSet<String>, PatchError> {
+    match value {
+        Value::String(val) => Ok(HashSet::from([val])),
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Value::String(val) => Ok(val),
+                _ => Err(PatchError("Attribute values must be strings".to_string())),
+            })
+            .collect(),
+        _ => Err(PatchError(
+            "Attribute values must be a string or an array of strings".to_string(),
+        )),
+    }
+}
+
+/// Translates an RFC 6902 JSON Patch into LDAP modifications.
+///
+/// Values of an attribute form an unordered set, so operations addressing an
+/// existing value by index, as well as `move`, `copy` and `test`, are rejected.
+pub fn from_json_patch(operations: Vec<PatchOperation>) -> Result<Vec<Mod>, PatchError> {
+    let mut changes = Vec::with_capacity(operations.len());
+
+    for operation in operations.into_iter() {
+        changes.push(match operation {
+            PatchOperation::Add { path, value } => match parse_pointer(&path)? {
+                Target::Attr(attr) => Mod::Replace(ReplaceMod {
+                    attr,
+                    values: values(value)?,
+                }),
+                Target::Value(attr, _) => Mod::Add(AddMod {
+                    attr,
+                    values: values(value)?,
+                }),
+            },
+            PatchOperation::Remove { path } => match parse_pointer(&path)? {
+                Target::Attr(attr) => Mod::Delete(DeleteMod {
+                    attr,
+                    values: HashSet::new(),
+                }),
+                Target::Value(attr, index) => return Err(positional("remove", &attr, index)),
+            },
+            PatchOperation::Replace { path, value } => match parse_pointer(&path)? {
+                Target::Attr(attr) => Mod::Replace(ReplaceMod {
+                    attr,
+                    values: values(value)?,
+                }),
+                Target::Value(attr, index) => return Err(positional("replace", &attr, index)),
+            },
+            PatchOperation::Move { from, path } | PatchOperation::Copy { from, path } => {
+                return Err(PatchError(format!(
+                    "Cannot move or copy \"{}\" to \"{}\": operation is not supported",
+                    from, path
+                )))
+            }
+            PatchOperation::Test { path } => {
+                return Err(PatchError(format!(
+                    "Cannot test \"{}\": operation is not supported",
+                    path
+                )))
+            }
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Translates an RFC 7396 JSON Merge Patch into LDAP modifications.
+///
+/// Only the `attrs` member may be patched; `null` deletes an attribute and any
+/// other value replaces it.
+pub fn from_merge_patch(patch: Value) -> Result<Vec<Mod>, PatchError> {
+    let mut patch = match patch {
+        Value::Object(val) => val,
+        _ => return Err(PatchError("Merge patch must be an object".to_string())),
+    };
+
+    let attrs = match patch.remove("attrs") {
+        Some(Value::Object(val)) => val,
+        Some(_) => return Err(PatchError("\"attrs\" must be an object".to_string())),
+        None => serde_json::Map::new(),
+    };
+
+    if let Some(key) = patch.keys().next() {
+        return Err(PatchError(format!("Cannot patch \"{}\"", key)));
+    }
+
+    attrs
+        .into_iter()
+        .map(|(attr, value)| match value {
+            Value::Null => Ok(Mod::Delete(DeleteMod {
+                attr,
+                values: HashSet::new(),
+            })),
+            value => Ok(Mod::Replace(ReplaceMod {
+                attr,
+                values: values(value)?,
+            })),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn operations(value: Value) -> Vec<PatchOperation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn translates_json_patch() {
+        let changes = from_json_patch(operations(json!([
+            { "op": "add", "path": "/attrs/mail/-", "value": "a@example.com" },
+            { "op": "add", "path": "/attrs/cn", "value": ["a", "b"] },
+            { "op": "replace", "path": "/attrs/a~1b~0c", "value": "x" },
+            { "op": "remove", "path": "/attrs/description" },
+        ])))
+        .unwrap();
+
+        assert_eq!(changes.len(), 4);
+        match &changes[0] {
+            Mod::Add(add) => {
+                assert_eq!(add.attr, "mail");
+                assert_eq!(add.values, HashSet::from(["a@example.com".to_string()]));
+            }
+            _ => panic!("Expected an add"),
+        }
+        match &changes[1] {
+            Mod::Replace(replace) => assert_eq!(replace.values.len(), 2),
+            _ => panic!("Expected a replace"),
+        }
+        match &changes[2] {
+            Mod::Replace(replace) => assert_eq!(replace.attr, "a/b~c"),
+            _ => panic!("Expected a replace"),
+        }
+        match &changes[3] {
+            Mod::Delete(delete) => {
+                assert_eq!(delete.attr, "description");
+                assert!(delete.values.is_empty());
+            }
+            _ => panic!("Expected a delete"),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_json_patch() {
+        for operation in [
+            json!({ "op": "remove", "path": "/attrs/mail/0" }),
+            json!({ "op": "replace", "path": "/dn", "value": "cn=a" }),
+            json!({ "op": "move", "from": "/attrs/a", "path": "/attrs/b" }),
+            json!({ "op": "test", "path": "/attrs/a" }),
+            json!({ "op": "add", "path": "/attrs/a", "value": 1 }),
+            json!({ "op": "add", "path": "attrs/a", "value": "x" }),
+        ] {
+            assert!(from_json_patch(operations(json!([operation]))).is_err());
+        }
+    }
+
+    #[test]
+    fn translates_merge_patch() {
+        let mut changes =
+            from_merge_patch(json!({ "attrs": { "mail": "a@example.com", "description": null } }))
+                .unwrap();
+        changes.sort_by_key(|change| matches!(change, Mod::Replace(_)));
+
+        match &changes[..] {
+            [Mod::Delete(delete), Mod::Replace(replace)] => {
+                assert_eq!(delete.attr, "description");
+                assert_eq!(replace.attr, "mail");
+            }
+            _ => panic!("Expected a delete and a replace"),
+        }
+
+        assert!(from_merge_patch(json!({ "dn": "cn=a" })).is_err());
+        assert!(from_merge_patch(json!([])).is_err());
+    }
+}
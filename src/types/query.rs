@@ -2,23 +2,33 @@ mod add;
 mod bind;
 mod compare;
 mod delete;
+mod ensure;
 mod extended;
+mod ldif;
 mod modify;
 mod pwdmod;
+pub mod remote;
 mod search;
 mod whoami;
 
-use self::{
+pub use self::{
     add::AddCommand,
     bind::{BindCommand, UnbindCommand},
     compare::CompareCommand,
     delete::DeleteCommand,
-    modify::{ModifyCommand, ModifyDnCommand},
+    ensure::{EnsureAction, EnsureAttr, EnsureCommand, EnsureMode, EnsureResult},
+    ldif::LdifCommand,
+    modify::{AddMod, DeleteMod, IncrementMod, Mod, ModifyCommand, ModifyDnCommand, ReplaceMod},
     pwdmod::PasswordModifyCommand,
-    search::SearchCommand,
+    search::{ExportResult, PageResult, SearchCommand, SearchPage},
     whoami::WhoAmICommand,
 };
 
+use std::time::Instant;
+
+use tracing::{field::Empty, Instrument};
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use ldap3_serde::{
@@ -27,7 +37,14 @@ use ldap3_serde::{
     Ldap, LdapError, LdapResult, SearchResult,
 };
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::{
+    access, audit, context,
+    metrics::METRICS,
+    policy::{self, Effect},
+    webhooks,
+};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(bound(deserialize = "'de: 'a"))]
 #[serde(tag = "type")]
 pub enum QueryCommand<'a> {
@@ -55,6 +72,12 @@ pub enum QueryCommand<'a> {
     #[serde(rename = "modifydn")]
     ModifyDn(ModifyDnCommand),
 
+    #[serde(rename = "ensure")]
+    Ensure(EnsureCommand),
+
+    #[serde(rename = "ldif")]
+    Ldif(LdifCommand),
+
     #[serde(rename = "whoami")]
     WhoAmI(WhoAmICommand),
 
@@ -62,36 +85,154 @@ pub enum QueryCommand<'a> {
     PasswordModify(PasswordModifyCommand<'a>),
 
     #[serde(rename = "extended")]
-    ExtendedOperation(Exop),
+    ExtendedOperation(#[schemars(with = "remote::ExopDef")] Exop),
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum QueryResult {
-    Common(LdapResult),
-    Search(SearchResult),
-    Compare(CompareResult),
-    Extended(ExopResult),
+    Common(#[schemars(with = "remote::LdapResultDef")] LdapResult),
+    Search(#[schemars(with = "remote::SearchResultDef")] SearchResult),
+    Compare(#[schemars(with = "remote::LdapResultDef")] CompareResult),
+    Extended(#[schemars(with = "remote::ExopResultDef")] ExopResult),
+    Ensure(EnsureResult),
+    Batch(Vec<Option<QueryResult>>),
+    Export(ExportResult),
+    Page(PageResult),
+}
+
+impl QueryResult {
+    /// Result of the LDAP operation behind this result, if it has one.
+    pub fn ldap_result(&self) -> Option<&LdapResult> {
+        match self {
+            QueryResult::Common(res) => Some(res),
+            QueryResult::Search(res) => Some(&res.1),
+            QueryResult::Compare(res) => Some(&res.0),
+            QueryResult::Extended(res) => Some(&res.1),
+            QueryResult::Ensure(res) => res.result.as_ref(),
+            QueryResult::Export(res) => Some(&res.result),
+            QueryResult::Page(res) => Some(&res.result.1),
+            QueryResult::Batch(_) => None,
+        }
+    }
 }
 
 pub trait Command {
     async fn execute(&self, ldap: &mut Ldap) -> Result<Option<QueryResult>, LdapError>;
 }
 
+impl QueryCommand<'_> {
+    /// Name of the command as it appears in the `type` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryCommand::Bind(_) => "bind",
+            QueryCommand::Unbind(_) => "unbind",
+            QueryCommand::Search(_) => "search",
+            QueryCommand::Add(_) => "add",
+            QueryCommand::Compare(_) => "compare",
+            QueryCommand::Delete(_) => "delete",
+            QueryCommand::Modify(_) => "modify",
+            QueryCommand::ModifyDn(_) => "modifydn",
+            QueryCommand::Ensure(_) => "ensure",
+            QueryCommand::Ldif(_) => "ldif",
+            QueryCommand::WhoAmI(_) => "whoami",
+            QueryCommand::PasswordModify(_) => "passwd",
+            QueryCommand::ExtendedOperation(_) => "extended",
+        }
+    }
+
+    /// Checks the command against the restrictions of the signing key and the policy,
+    /// without running it. Group memberships are looked up over `ldap`.
+    pub async fn authorize(&self, ldap: &mut Ldap) -> Result<(), String> {
+        if let Err(message) = context::access().check_command(self) {
+            tracing::info!(error = %message, "Command rejected");
+            return Err(message);
+        }
+        if let Some(policy) = policy::current() {
+            let decision = policy.evaluate(ldap, self).await;
+            let message = match decision.effect {
+                Effect::Allow => None,
+                Effect::Approve => match &context::current().approval {
+                    Some(approval) if approval.covers(&policy, &decision) => None,
+                    Some(_) => Some(format!(
+                        "{}; the batch was approved under another policy or rule, submit it again",
+                        decision
+                    )),
+                    None => Some(format!(
+                        "{}; submit it through /query to request approval",
+                        decision
+                    )),
+                },
+                Effect::Deny => Some(decision.to_string()),
+            };
+            if let Some(message) = message {
+                tracing::info!(rule = ?decision.rule, "{}", message);
+                return Err(message);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<'a> Command for QueryCommand<'a> {
     async fn execute(&self, ldap: &mut Ldap) -> Result<Option<QueryResult>, LdapError> {
-        match self {
-            QueryCommand::Bind(cmd) => cmd.execute(ldap).await,
-            QueryCommand::Unbind(cmd) => cmd.execute(ldap).await,
-            QueryCommand::Search(cmd) => cmd.execute(ldap).await,
-            QueryCommand::Add(cmd) => cmd.execute(ldap).await,
-            QueryCommand::Compare(cmd) => cmd.execute(ldap).await,
-            QueryCommand::Delete(cmd) => cmd.execute(ldap).await,
-            QueryCommand::Modify(cmd) => cmd.execute(ldap).await,
-            QueryCommand::ModifyDn(cmd) => cmd.execute(ldap).await,
-            QueryCommand::WhoAmI(cmd) => cmd.execute(ldap).await,
-            QueryCommand::PasswordModify(cmd) => cmd.execute(ldap).await,
-            QueryCommand::ExtendedOperation(cmd) => cmd.execute(ldap).await,
+        let span = tracing::info_span!("command", command = self.name(), outcome = Empty);
+        if let Err(message) = self.authorize(ldap).instrument(span.clone()).await {
+            return Err(access::insufficient_access(message));
+        }
+
+        let audit_target = audit::target(self);
+        if audit_target.is_some() {
+            audit::check().instrument(span.clone()).await?;
+        }
+        let before = match &audit_target {
+            Some(target) => audit::snapshot(ldap, target, false).await,
+            None => Default::default(),
+        };
+        let write = webhooks::prepare(ldap, self).await;
+
+        let started = Instant::now();
+        let res = async {
+            match self {
+                QueryCommand::Bind(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Unbind(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Search(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Add(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Compare(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Delete(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Modify(cmd) => cmd.execute(ldap).await,
+                QueryCommand::ModifyDn(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Ensure(cmd) => cmd.execute(ldap).await,
+                QueryCommand::Ldif(cmd) => cmd.execute(ldap).await,
+                QueryCommand::WhoAmI(cmd) => cmd.execute(ldap).await,
+                QueryCommand::PasswordModify(cmd) => cmd.execute(ldap).await,
+                QueryCommand::ExtendedOperation(cmd) => cmd.execute(ldap).await,
+            }
+        }
+        .instrument(span.clone())
+        .await;
+
+        let outcome = METRICS.record_command(self.name(), started, &res);
+        if let Some(write) = write {
+            webhooks::bridge_write(write, &res);
         }
+        // A write whose record could not be written is reported as failed, even
+        // though the directory applied it.
+        let res = match audit_target {
+            Some(target) => {
+                let after = audit::snapshot(ldap, &target, true).await;
+                match audit::record(self, target, before, after, &res).await {
+                    Ok(()) => res,
+                    Err(err) => Err(err),
+                }
+            }
+            None => res,
+        };
+        span.record("outcome", outcome);
+        span.in_scope(|| match &res {
+            Err(err) => tracing::info!(error = %err, "Command failed"),
+            Ok(_) => tracing::info!("Command executed"),
+        });
+        res
     }
 }
@@ -1,20 +1,37 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt::Debug};
 
 use ldap3_serde::{Ldap, LdapError};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::types::secret::AttrValues;
+
 use super::{Command, QueryResult};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AddCommand {
     pub dn: String,
     pub attrs: Vec<(String, HashSet<String>)>,
 }
 
+impl Debug for AddCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let attrs = self
+            .attrs
+            .iter()
+            .map(|(attr, values)| (attr, AttrValues(attr, values)))
+            .collect::<Vec<_>>();
+        f.debug_struct("AddCommand")
+            .field("dn", &self.dn)
+            .field("attrs", &attrs)
+            .finish()
+    }
+}
+
 impl Command for AddCommand {
     async fn execute(&self, ldap: &mut Ldap) -> Result<Option<QueryResult>, LdapError> {
         match ldap.add(&self.dn, self.attrs.clone()).await {
-            Ok(val) => Ok(Some(QueryResult::Common(val.into()))),
+            Ok(val) => Ok(Some(QueryResult::Common(val))),
             Err(e) => Err(e),
         }
     }
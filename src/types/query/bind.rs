@@ -1,24 +1,32 @@
 use ldap3_serde::{Ldap, LdapError};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::{context, types::secret::Secret};
+
 use super::{Command, QueryResult};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BindCommand {
     pub dn: String,
-    pub pw: String,
+    pub pw: Secret,
 }
 
 impl Command for BindCommand {
     async fn execute(&self, ldap: &mut Ldap) -> Result<Option<QueryResult>, LdapError> {
-        match ldap.simple_bind(&self.dn, &self.pw).await {
-            Ok(val) => Ok(Some(QueryResult::Common(val.into()))),
+        match ldap.simple_bind(&self.dn, self.pw.expose()).await {
+            Ok(val) => {
+                if val.rc == 0 {
+                    context::set_bind_dn(self.dn.clone());
+                }
+                Ok(Some(QueryResult::Common(val)))
+            }
             Err(e) => Err(e),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UnbindCommand {}
 
 impl Command for UnbindCommand {
@@ -1,9 +1,11 @@
 use ldap3_serde::LdapError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use super::{Command, QueryResult};
+use crate::access;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CompareCommand {
     pub dn: String,
     pub attribute: String,
@@ -15,6 +17,13 @@ impl Command for CompareCommand {
         &self,
         ldap: &mut ldap3_serde::Ldap,
     ) -> Result<Option<QueryResult>, LdapError> {
+        if !access::resolve(ldap).await?.can_read(&self.attribute) {
+            return Err(access::insufficient_access(format!(
+                "Not allowed to read attribute \"{}\"",
+                self.attribute
+            )));
+        }
+
         match ldap.compare(&self.dn, &self.attribute, &self.value).await {
             Ok(val) => Ok(Some(QueryResult::Compare(val))),
             Err(e) => Err(e),
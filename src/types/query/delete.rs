@@ -1,8 +1,9 @@
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use super::{Command, QueryResult};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DeleteCommand {
     pub dn: String,
 }
@@ -13,7 +14,7 @@ impl Command for DeleteCommand {
         ldap: &mut ldap3_serde::Ldap,
     ) -> Result<Option<QueryResult>, ldap3_serde::LdapError> {
         match ldap.delete(&self.dn).await {
-            Ok(val) => Ok(Some(QueryResult::Common(val.into()))),
+            Ok(val) => Ok(Some(QueryResult::Common(val))),
             Err(e) => Err(e),
         }
     }
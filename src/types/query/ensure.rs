@@ -0,0 +1,351 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+
+use ldap3_serde::{LdapError, LdapResult, Scope, SearchEntry, SearchResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{schema::Subschema, types::secret::AttrValues};
+
+use super::{Command, QueryResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EnsureMode {
+    /// The attribute must have exactly the given values.
+    Exact,
+    /// The attribute must have at least the given values.
+    Superset,
+    /// The given values (or the whole attribute, if none are given) must not be present.
+    Absent,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EnsureAttr {
+    pub attr: String,
+    #[serde(default)]
+    pub values: HashSet<String>,
+    pub mode: EnsureMode,
+}
+
+impl Debug for EnsureAttr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnsureAttr")
+            .field("attr", &self.attr)
+            .field("values", &AttrValues(&self.attr, &self.values))
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EnsureCommand {
+    pub dn: String,
+    pub attrs: Vec<EnsureAttr>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EnsureAction {
+    None,
+    Add,
+    Modify,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct EnsureResult {
+    pub changed: bool,
+    pub action: EnsureAction,
+    #[schemars(with = "Option<super::remote::LdapResultDef>")]
+    pub result: Option<LdapResult>,
+    /// Attributes set by the addition or touched by the modification, for webhooks.
+    #[serde(skip)]
+    pub attributes: Vec<String>,
+}
+
+impl EnsureCommand {
+    /// Reads the current state of the entry, returning `None` if it does not exist.
+    async fn read(
+        &self,
+        ldap: &mut ldap3_serde::Ldap,
+    ) -> Result<Option<HashMap<String, HashSet<String>>>, LdapError> {
+        let attrs = self
+            .attrs
+            .iter()
+            .map(|attr| attr.attr.as_str())
+            .collect::<Vec<_>>();
+
+        let SearchResult(entries, res) = match ldap
+            .search(&self.dn, Scope::Base, "(objectClass=*)", attrs)
+            .await
+        {
+            Ok(val) => val,
+            Err(e) => return Err(e),
+        };
+
+        match res.rc {
+            0 => {}
+            32 => return Ok(None),
+            _ => return Err(LdapError::from(res)),
+        }
+
+        let entry = match entries.into_iter().next() {
+            Some(val) => SearchEntry::construct(val),
+            None => return Ok(None),
+        };
+
+        Ok(Some(
+            entry
+                .attrs
+                .into_iter()
+                .map(|(attr, values)| (attr.to_lowercase(), values.into_iter().collect()))
+                .collect(),
+        ))
+    }
+
+    /// Computes the modifications needed to bring `current` to the desired state.
+    /// Attributes are matched through `subschema`, whatever name or OID they are
+    /// given by, and values are compared ignoring case where their matching rule does.
+    fn diff(
+        &self,
+        current: &HashMap<String, HashSet<String>>,
+        subschema: &Subschema,
+    ) -> Vec<ldap3_serde::Mod<String>> {
+        let current = current
+            .iter()
+            .map(|(attr, values)| (subschema.canonical(attr), values))
+            .collect::<HashMap<_, _>>();
+        let empty = HashSet::new();
+        let mut mods = Vec::new();
+
+        for desired in self.attrs.iter() {
+            let case_insensitive = subschema.is_case_insensitive(&desired.attr);
+            let fold = |value: &String| match case_insensitive {
+                true => value.to_lowercase(),
+                false => value.clone(),
+            };
+            let present = current
+                .get(&subschema.canonical(&desired.attr))
+                .copied()
+                .unwrap_or(&empty)
+                .iter()
+                .map(fold)
+                .collect::<HashSet<_>>();
+
+            match desired.mode {
+                EnsureMode::Exact => {
+                    if present != desired.values.iter().map(fold).collect() {
+                        mods.push(ldap3_serde::Mod::Replace(
+                            desired.attr.clone(),
+                            desired.values.clone(),
+                        ));
+                    }
+                }
+                EnsureMode::Superset => {
+                    let missing = desired
+                        .values
+                        .iter()
+                        .filter(|value| !present.contains(&fold(value)))
+                        .cloned()
+                        .collect::<HashSet<_>>();
+                    if !missing.is_empty() {
+                        mods.push(ldap3_serde::Mod::Add(desired.attr.clone(), missing));
+                    }
+                }
+                EnsureMode::Absent => {
+                    if desired.values.is_empty() {
+                        if !present.is_empty() {
+                            mods.push(ldap3_serde::Mod::Delete(
+                                desired.attr.clone(),
+                                HashSet::new(),
+                            ));
+                        }
+                        continue;
+                    }
+
+                    let unwanted = desired
+                        .values
+                        .iter()
+                        .filter(|value| present.contains(&fold(value)))
+                        .cloned()
+                        .collect::<HashSet<_>>();
+                    if !unwanted.is_empty() {
+                        mods.push(ldap3_serde::Mod::Delete(desired.attr.clone(), unwanted));
+                    }
+                }
+            }
+        }
+
+        mods
+    }
+}
+
+impl Command for EnsureCommand {
+    async fn execute(
+        &self,
+        ldap: &mut ldap3_serde::Ldap,
+    ) -> Result<Option<QueryResult>, LdapError> {
+        let current = match self.read(ldap).await {
+            Ok(val) => val,
+            Err(e) => return Err(e),
+        };
+
+        let (action, res, attributes) = match current {
+            None => {
+                let attrs = self
+                    .attrs
+                    .iter()
+                    .filter(|attr| attr.mode != EnsureMode::Absent && !attr.values.is_empty())
+                    .map(|attr| (attr.attr.clone(), attr.values.clone()))
+                    .collect::<Vec<_>>();
+                if attrs.is_empty() {
+                    (EnsureAction::None, None, Vec::new())
+                } else {
+                    let attributes = attrs.iter().map(|(attr, _)| attr.clone()).collect();
+                    match ldap.add(&self.dn, attrs).await {
+                        Ok(val) => (EnsureAction::Add, Some(val), attributes),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Some(current) => {
+                let subschema = Subschema::read_or_default(ldap).await;
+                let mods = self.diff(&current, &subschema);
+                if mods.is_empty() {
+                    (EnsureAction::None, None, Vec::new())
+                } else {
+                    let attributes = mods
+                        .iter()
+                        .map(|m| match m {
+                            ldap3_serde::Mod::Add(attr, _)
+                            | ldap3_serde::Mod::Delete(attr, _)
+                            | ldap3_serde::Mod::Replace(attr, _)
+                            | ldap3_serde::Mod::Increment(attr, _) => attr.clone(),
+                        })
+                        .collect();
+                    match ldap.modify(&self.dn, mods).await {
+                        Ok(val) => (EnsureAction::Modify, Some(val), attributes),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        };
+
+        Ok(Some(QueryResult::Ensure(EnsureResult {
+            changed: res.as_ref().is_some_and(|res| res.rc == 0),
+            action,
+            result: res,
+            attributes,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subschema() -> Subschema {
+        Subschema::parse(&[
+            "( 2.5.4.41 NAME 'name' EQUALITY caseIgnoreMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )".to_string(),
+            "( 2.5.4.3 NAME ( 'cn' 'commonName' ) SUP name )".to_string(),
+            "( 2.5.4.35 NAME 'userPassword' EQUALITY octetStringMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.40 )".to_string(),
+        ])
+    }
+
+    fn values(values: &[&str]) -> HashSet<String> {
+        values.iter().map(|val| val.to_string()).collect()
+    }
+
+    fn ensure(attr: &str, mode: EnsureMode, desired: &[&str]) -> EnsureCommand {
+        EnsureCommand {
+            dn: "uid=alice,dc=example,dc=org".to_string(),
+            attrs: vec![EnsureAttr {
+                attr: attr.to_string(),
+                values: values(desired),
+                mode,
+            }],
+        }
+    }
+
+    fn current(attr: &str, present: &[&str]) -> HashMap<String, HashSet<String>> {
+        HashMap::from([(attr.to_string(), values(present))])
+    }
+
+    #[test]
+    fn exact_replaces_differing_values() {
+        let command = ensure("cn", EnsureMode::Exact, &["Alice"]);
+        assert_eq!(
+            command.diff(&current("cn", &["Bob"]), &subschema()),
+            vec![ldap3_serde::Mod::Replace(
+                "cn".to_string(),
+                values(&["Alice"])
+            )]
+        );
+        assert!(command
+            .diff(&current("cn", &["alice"]), &subschema())
+            .is_empty());
+    }
+
+    #[test]
+    fn exact_compares_case_sensitive_values_exactly() {
+        let command = ensure("userPassword", EnsureMode::Exact, &["Secret"]);
+        assert_eq!(
+            command.diff(&current("userpassword", &["secret"]), &subschema()),
+            vec![ldap3_serde::Mod::Replace(
+                "userPassword".to_string(),
+                values(&["Secret"])
+            )]
+        );
+    }
+
+    #[test]
+    fn exact_matches_aliases_and_oids() {
+        let present = current("cn", &["Alice"]);
+        for attr in ["commonName", "2.5.4.3", "CN"] {
+            let command = ensure(attr, EnsureMode::Exact, &["Alice"]);
+            assert!(command.diff(&present, &subschema()).is_empty(), "{}", attr);
+        }
+    }
+
+    #[test]
+    fn superset_adds_missing_values() {
+        let command = ensure("commonName", EnsureMode::Superset, &["Alice", "Al"]);
+        assert_eq!(
+            command.diff(&current("cn", &["ALICE"]), &subschema()),
+            vec![ldap3_serde::Mod::Add(
+                "commonName".to_string(),
+                values(&["Al"])
+            )]
+        );
+        assert!(command
+            .diff(&current("cn", &["alice", "al", "A"]), &subschema())
+            .is_empty());
+    }
+
+    #[test]
+    fn absent_deletes_present_values() {
+        let command = ensure("2.5.4.3", EnsureMode::Absent, &["Alice", "Bob"]);
+        assert_eq!(
+            command.diff(&current("cn", &["alice", "Carol"]), &subschema()),
+            vec![ldap3_serde::Mod::Delete(
+                "2.5.4.3".to_string(),
+                values(&["Alice"])
+            )]
+        );
+        assert!(command
+            .diff(&current("cn", &["Carol"]), &subschema())
+            .is_empty());
+    }
+
+    #[test]
+    fn absent_without_values_deletes_the_attribute() {
+        let command = ensure("cn", EnsureMode::Absent, &[]);
+        assert_eq!(
+            command.diff(&current("commonname", &["Alice"]), &subschema()),
+            vec![ldap3_serde::Mod::Delete("cn".to_string(), HashSet::new())]
+        );
+        assert!(command.diff(&HashMap::new(), &subschema()).is_empty());
+    }
+}
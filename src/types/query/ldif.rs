@@ -0,0 +1,55 @@
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::Deserialize;
+
+use crate::ldif::{parse, LdifError};
+
+use super::{Command, QueryCommand, QueryResult};
+
+#[derive(Deserialize, JsonSchema)]
+struct LdifSource {
+    ldif: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "LdifSource")]
+pub struct LdifCommand {
+    pub records: Vec<QueryCommand<'static>>,
+}
+
+impl TryFrom<LdifSource> for LdifCommand {
+    type Error = LdifError;
+
+    fn try_from(value: LdifSource) -> Result<Self, Self::Error> {
+        match parse(&value.ldif) {
+            Ok(records) => Ok(LdifCommand { records }),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Described by its wire form, `{"ldif": "..."}`.
+impl JsonSchema for LdifCommand {
+    fn schema_name() -> String {
+        "LdifCommand".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        LdifSource::json_schema(gen)
+    }
+}
+
+impl Command for LdifCommand {
+    async fn execute(
+        &self,
+        ldap: &mut ldap3_serde::Ldap,
+    ) -> Result<Option<QueryResult>, ldap3_serde::LdapError> {
+        let mut results = Vec::with_capacity(self.records.len());
+        for record in self.records.iter() {
+            match Box::pin(record.execute(ldap)).await {
+                Ok(val) => results.push(val),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(QueryResult::Batch(results)))
+    }
+}
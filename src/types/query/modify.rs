@@ -1,10 +1,13 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt::Debug};
 
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{dn, types::secret::AttrValues};
 
 use super::{Command, QueryResult};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum Mod {
     Add(AddMod),
@@ -13,34 +16,72 @@ pub enum Mod {
     Increment(IncrementMod),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AddMod {
     pub attr: String,
     pub values: HashSet<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Debug for AddMod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AddMod")
+            .field("attr", &self.attr)
+            .field("values", &AttrValues(&self.attr, &self.values))
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DeleteMod {
     pub attr: String,
     pub values: HashSet<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Debug for DeleteMod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeleteMod")
+            .field("attr", &self.attr)
+            .field("values", &AttrValues(&self.attr, &self.values))
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ReplaceMod {
     pub attr: String,
     pub values: HashSet<String>,
 }
 
+impl Debug for ReplaceMod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplaceMod")
+            .field("attr", &self.attr)
+            .field("values", &AttrValues(&self.attr, &self.values))
+            .finish()
+    }
+}
+
 // TODO: IncrementMod can be used for integer values
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct IncrementMod {
     pub attr: String,
     pub value: String,
 }
 
-impl Into<ldap3_serde::Mod<String>> for Mod {
-    fn into(self) -> ldap3_serde::Mod<String> {
+impl Mod {
+    pub fn attr(&self) -> &str {
         match self {
+            Mod::Add(add) => &add.attr,
+            Mod::Delete(delete) => &delete.attr,
+            Mod::Replace(replace) => &replace.attr,
+            Mod::Increment(increment) => &increment.attr,
+        }
+    }
+}
+
+impl From<Mod> for ldap3_serde::Mod<String> {
+    fn from(value: Mod) -> Self {
+        match value {
             Mod::Add(add) => ldap3_serde::Mod::Add(add.attr, add.values.into_iter().collect()),
             Mod::Delete(delete) => {
                 ldap3_serde::Mod::Delete(delete.attr, delete.values.into_iter().collect())
@@ -55,13 +96,13 @@ impl Into<ldap3_serde::Mod<String>> for Mod {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModifyCommand {
     pub dn: String,
     pub changes: Vec<Mod>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModifyDnCommand {
     pub dn: String,
     pub rdn: String,
@@ -69,6 +110,24 @@ pub struct ModifyDnCommand {
     pub new_superior: Option<String>,
 }
 
+impl ModifyDnCommand {
+    /// DN of the entry after the rename, under `new_superior` or its current parent.
+    pub fn new_dn(&self) -> String {
+        let superior = match &self.new_superior {
+            Some(val) => val.clone(),
+            None => dn::parse(&self.dn)
+                .ok()
+                .and_then(|dn| dn.parent())
+                .map(|parent| parent.to_string())
+                .unwrap_or_default(),
+        };
+        match superior.is_empty() {
+            true => self.rdn.clone(),
+            false => format!("{},{}", self.rdn, superior),
+        }
+    }
+}
+
 impl Command for ModifyCommand {
     async fn execute(
         &self,
@@ -81,7 +140,7 @@ impl Command for ModifyCommand {
             )
             .await
         {
-            Ok(val) => Ok(Some(QueryResult::Common(val.into()))),
+            Ok(val) => Ok(Some(QueryResult::Common(val))),
             Err(e) => Err(e),
         }
     }
@@ -101,7 +160,7 @@ impl Command for ModifyDnCommand {
             )
             .await
         {
-            Ok(val) => Ok(Some(QueryResult::Common(val.into()))),
+            Ok(val) => Ok(Some(QueryResult::Common(val))),
             Err(e) => Err(e),
         }
     }
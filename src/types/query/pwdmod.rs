@@ -1,21 +1,24 @@
 use ldap3_serde::exop::PasswordModify;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::types::secret::Secret;
 
 use super::{Command, QueryResult};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PasswordModifyCommand<'a> {
     pub user_id: Option<&'a str>,
-    pub old_pass: Option<&'a str>,
-    pub new_pass: Option<&'a str>,
+    pub old_pass: Option<Secret>,
+    pub new_pass: Option<Secret>,
 }
 
-impl<'a> Into<PasswordModify<'a>> for PasswordModifyCommand<'a> {
-    fn into(self) -> PasswordModify<'a> {
+impl<'a> From<&'a PasswordModifyCommand<'_>> for PasswordModify<'a> {
+    fn from(value: &'a PasswordModifyCommand<'_>) -> Self {
         PasswordModify {
-            user_id: self.user_id,
-            old_pass: self.old_pass,
-            new_pass: self.new_pass,
+            user_id: value.user_id,
+            old_pass: value.old_pass.as_ref().map(|val| val.expose()),
+            new_pass: value.new_pass.as_ref().map(|val| val.expose()),
         }
     }
 }
@@ -25,8 +28,8 @@ impl<'a> Command for PasswordModifyCommand<'a> {
         &self,
         ldap: &mut ldap3_serde::Ldap,
     ) -> Result<Option<QueryResult>, ldap3_serde::LdapError> {
-        match ldap.extended::<PasswordModify>(self.clone().into()).await {
-            Ok(val) => Ok(Some(QueryResult::Extended(val.into()))),
+        match ldap.extended::<PasswordModify>(self.into()).await {
+            Ok(val) => Ok(Some(QueryResult::Extended(val))),
             Err(e) => Err(e),
         }
     }
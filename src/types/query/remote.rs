@@ -0,0 +1,42 @@
+//! Schema-only mirrors of the `ldap3` types exchanged over the API, which do not
+//! implement `JsonSchema` themselves. Each matches the type's `Serialize` impl.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde_json::Value;
+
+#[derive(JsonSchema)]
+#[schemars(rename = "LdapResult")]
+pub struct LdapResultDef {
+    pub rc: u32,
+    pub matched: String,
+    pub text: String,
+    pub refs: Vec<String>,
+    /// Response controls, each a `[parsed control or null, raw control]` pair.
+    pub ctrls: Vec<Value>,
+}
+
+#[derive(JsonSchema)]
+#[schemars(rename = "Exop")]
+pub struct ExopDef {
+    pub name: Option<String>,
+    pub val: Option<Vec<u8>>,
+}
+
+/// Raw search result entry, as a BER structure with its response controls.
+#[derive(JsonSchema)]
+#[schemars(rename = "ResultEntry")]
+pub struct ResultEntryDef {
+    pub entry: HashMap<String, Value>,
+    pub controls: Vec<Value>,
+}
+
+#[derive(JsonSchema)]
+#[schemars(rename = "SearchResult")]
+pub struct SearchResultDef(pub Vec<ResultEntryDef>, pub LdapResultDef);
+
+#[derive(JsonSchema)]
+#[schemars(rename = "ExopResult")]
+pub struct ExopResultDef(pub ExopDef, pub LdapResultDef);
@@ -1,10 +1,15 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use ldap3_serde::{LdapError, Scope};
+use ldap3_serde::{
+    controls::{ControlType, PagedResults},
+    Ldap, LdapError, LdapResult, ResultEntry, Scope, SearchEntry, SearchResult,
+};
 
 use super::{Command, QueryResult};
+use crate::access;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(remote = "Scope")]
 pub enum ScopeDef {
     Base = 0,
@@ -12,13 +17,178 @@ pub enum ScopeDef {
     Subtree = 2,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn default_separator() -> String {
+    "|".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct CsvOptions {
+    /// Columns to emit after the DN; defaults to every attribute returned.
+    pub columns: Option<Vec<String>>,
+    /// String used to join the values of multi-valued attributes.
+    #[serde(default = "default_separator")]
+    pub separator: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SearchFormat {
+    Ldif,
+    Csv(CsvOptions),
+}
+
+impl SearchFormat {
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            SearchFormat::Ldif => "text/ldif",
+            SearchFormat::Csv(_) => "text/csv",
+        }
+    }
+
+    pub fn render(&self, entries: &[SearchEntry]) -> String {
+        match self {
+            SearchFormat::Ldif => crate::ldif::write(entries),
+            SearchFormat::Csv(options) => {
+                crate::csv::write(entries, options.columns.as_deref(), &options.separator)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ExportResult {
+    pub media_type: String,
+    pub content: String,
+    #[schemars(with = "super::remote::LdapResultDef")]
+    pub result: LdapResult,
+}
+
+/// Entries read per request when paging through results.
+const PAGE_SIZE: i32 = 500;
+
+fn default_start() -> usize {
+    1
+}
+
+/// Window of the matching entries to return.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SearchPage {
+    /// 1-based index of the first entry.
+    #[serde(default = "default_start")]
+    pub start: usize,
+    pub count: usize,
+}
+
+impl SearchPage {
+    fn contains(&self, index: usize) -> bool {
+        index >= self.start && index - self.start < self.count
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PageResult {
+    /// Number of matching entries, of which the search result holds the requested window.
+    pub total: usize,
+    #[schemars(with = "super::remote::SearchResultDef")]
+    pub result: SearchResult,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct SearchCommand {
     pub base: String,
     #[serde(with = "ScopeDef")]
     pub scope: Scope,
     pub filter: String,
     pub attrs: Vec<String>,
+    /// Renders the result entries as a document instead of raw search results.
+    #[serde(default)]
+    pub format: Option<SearchFormat>,
+    /// Returns only a window of the matching entries, reading them with the paged
+    /// results control (RFC 2696) so the others are counted but not kept.
+    #[serde(default)]
+    pub page: Option<SearchPage>,
+}
+
+/// Entries of a paged search, keeping those within the requested window.
+struct PageWindow<'a> {
+    page: &'a SearchPage,
+    /// Matching entries counted so far.
+    seen: usize,
+    entries: Vec<ResultEntry>,
+}
+
+impl PageWindow<'_> {
+    fn is_filled(&self) -> bool {
+        self.seen
+            >= self
+                .page
+                .start
+                .saturating_add(self.page.count)
+                .saturating_sub(1)
+    }
+}
+
+impl SearchCommand {
+    /// Searches `base` in pages, adding its entries to `window`. Once the window is
+    /// filled, the estimate of the result size returned by the server, if any, is
+    /// taken as the count of the base and its remaining pages are not read.
+    async fn search_paged(
+        &self,
+        ldap: &mut Ldap,
+        base: &str,
+        scope: Scope,
+        attrs: &[String],
+        window: &mut PageWindow<'_>,
+    ) -> Result<LdapResult, LdapError> {
+        let counted = window.seen;
+        let mut cookie = Vec::new();
+        loop {
+            let control = PagedResults {
+                size: PAGE_SIZE,
+                cookie,
+            };
+            let SearchResult(part, res) = match ldap
+                .with_controls(control)
+                .search(base, scope, &self.filter, attrs.to_vec())
+                .await
+            {
+                Ok(val) => val,
+                Err(e) => return Err(e),
+            };
+            for entry in part.into_iter().filter(|entry| !entry.is_ref()) {
+                window.seen += 1;
+                if window.page.contains(window.seen) {
+                    window.entries.push(entry);
+                }
+            }
+
+            let response = res
+                .ctrls
+                .iter()
+                .find(|ctrl| matches!(ctrl.0, Some(ControlType::PagedResults)))
+                .map(|ctrl| ctrl.1.parse::<PagedResults>());
+            let response = match response {
+                Some(val) if res.rc == 0 && !val.cookie.is_empty() => val,
+                _ => return Ok(res),
+            };
+
+            let estimate = usize::try_from(response.size).unwrap_or(0);
+            if window.is_filled() && counted + estimate > window.seen {
+                // A page size of zero abandons the paged search.
+                let control = PagedResults {
+                    size: 0,
+                    cookie: response.cookie,
+                };
+                let _ = ldap
+                    .with_controls(control)
+                    .search(base, scope, &self.filter, attrs.to_vec())
+                    .await;
+                window.seen = counted + estimate;
+                return Ok(res);
+            }
+            cookie = response.cookie;
+        }
+    }
 }
 
 impl Command for SearchCommand {
@@ -26,12 +196,84 @@ impl Command for SearchCommand {
         &self,
         ldap: &mut ldap3_serde::Ldap,
     ) -> Result<Option<QueryResult>, LdapError> {
-        match ldap
-            .search(&self.base, self.scope, &self.filter, self.attrs.clone())
-            .await
-        {
-            Ok(val) => Ok(Some(QueryResult::Search(val.into()))),
-            Err(e) => Err(e),
+        let access = access::resolve(ldap).await?;
+        access.check_filter(&self.filter)?;
+
+        let bases = match access.search_bases(&self.base, self.scope) {
+            Ok(val) => val,
+            Err(message) => return Err(access::insufficient_access(message)),
+        };
+        let clamped = bases.len() != 1 || bases[0].0 != self.base;
+        let attrs = access.requested_attrs(&self.attrs);
+
+        let mut entries = Vec::new();
+        let mut window = self.page.as_ref().map(|page| PageWindow {
+            page,
+            seen: 0,
+            entries: Vec::new(),
+        });
+        let mut result: Option<LdapResult> = None;
+        for (base, scope) in bases {
+            let part_result = match &mut window {
+                Some(window) => self.search_paged(ldap, &base, scope, &attrs, window).await,
+                None => match ldap.search(&base, scope, &self.filter, attrs.clone()).await {
+                    Ok(SearchResult(part, res)) => {
+                        entries.extend(part);
+                        Ok(res)
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+            let part_result = match part_result {
+                Ok(val) => val,
+                Err(e) => return Err(e),
+            };
+            // A subtree the search was clamped to does not have to exist.
+            if clamped && part_result.rc == 32 {
+                continue;
+            }
+            if result.as_ref().is_none_or(|res| res.rc == 0) {
+                result = Some(part_result);
+            }
+        }
+        let result = result.unwrap_or_else(|| LdapResult {
+            rc: 0,
+            matched: String::new(),
+            text: String::new(),
+            refs: Vec::new(),
+            ctrls: Vec::new(),
+        });
+        let total = match window {
+            Some(window) => {
+                entries = window.entries;
+                window.seen
+            }
+            None => entries.len(),
+        };
+        for entry in entries.iter_mut() {
+            access.filter_result_entry(entry);
         }
+        let res = SearchResult(entries, result);
+
+        let format = match (&self.format, &self.page) {
+            (Some(val), _) => val,
+            (None, Some(_)) => {
+                return Ok(Some(QueryResult::Page(PageResult { total, result: res })))
+            }
+            (None, None) => return Ok(Some(QueryResult::Search(res))),
+        };
+
+        let SearchResult(entries, result) = res;
+        let entries = entries
+            .into_iter()
+            .filter(|entry| !entry.is_ref())
+            .map(SearchEntry::construct)
+            .collect::<Vec<_>>();
+
+        Ok(Some(QueryResult::Export(ExportResult {
+            media_type: format.media_type().to_string(),
+            content: format.render(&entries),
+            result,
+        })))
     }
 }
@@ -1,8 +1,9 @@
 use super::{Command, QueryResult};
 use ldap3_serde::exop::WhoAmI;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct WhoAmICommand {}
 
 impl Command for WhoAmICommand {
@@ -11,7 +12,7 @@ impl Command for WhoAmICommand {
         ldap: &mut ldap3_serde::Ldap,
     ) -> Result<Option<QueryResult>, ldap3_serde::LdapError> {
         match ldap.extended::<WhoAmI>(WhoAmI {}).await {
-            Ok(val) => Ok(Some(QueryResult::Extended(val.into()))),
+            Ok(val) => Ok(Some(QueryResult::Extended(val))),
             Err(e) => Err(e),
         }
     }
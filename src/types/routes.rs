@@ -1,5 +1,6 @@
 use axum::{body::Body, extract::rejection::*, http::StatusCode, response::IntoResponse, Json};
 use erased_serde::Serialize;
+use schemars::JsonSchema;
 use thiserror::Error;
 
 pub struct Response {
@@ -7,6 +8,18 @@ pub struct Response {
     pub body: Box<dyn Serialize>,
 }
 
+impl Response {
+    pub fn error(status: StatusCode, message: impl Into<String>) -> Self {
+        Response {
+            status,
+            body: Box::new(ErrorResponse {
+                result: false,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
 impl IntoResponse for Response {
     fn into_response(self) -> axum::http::Response<Body> {
         let mut res =
@@ -16,13 +29,20 @@ impl IntoResponse for Response {
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, JsonSchema)]
 pub struct ErrorResponse {
     pub result: bool,
     pub message: String,
 }
 
+#[derive(serde::Serialize, JsonSchema)]
+pub struct DataResponse<T> {
+    pub result: bool,
+    pub data: T,
+}
+
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum RejectionError {
     #[error(transparent)]
     BytesExtractorRejection(#[from] BytesRejection),
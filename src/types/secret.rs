@@ -0,0 +1,121 @@
+use std::{collections::HashSet, fmt::Debug, sync::OnceLock};
+
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Credential that never shows up in `Debug`, `Display` or serialized output. Use
+/// `expose` to pass it to the directory.
+///
+/// Its own buffer is zeroed when dropped, but the request body and signed `data` it
+/// was parsed from still hold the plaintext until they are dropped, so this shortens
+/// how long the credential stays in memory rather than removing every copy.
+///
+/// Serializing writes `[REDACTED]`, so a serde round trip replaces the credential
+/// with that text. Commands are forwarded as their original JSON, as held approvals
+/// do, never by serializing them again.
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl JsonSchema for Secret {
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+const DEFAULT_SENSITIVE_ATTRIBUTES: &str =
+    "userPassword,authPassword,unicodePwd,sambaNTPassword,sambaLMPassword";
+
+/// Lowercase names of the attributes in `SENSITIVE_ATTRIBUTES` (comma-separated),
+/// whose values are kept out of logs and the audit log.
+fn sensitive_attributes() -> &'static HashSet<String> {
+    static ATTRIBUTES: OnceLock<HashSet<String>> = OnceLock::new();
+    ATTRIBUTES.get_or_init(|| {
+        dotenv::var("SENSITIVE_ATTRIBUTES")
+            .unwrap_or_else(|_| DEFAULT_SENSITIVE_ATTRIBUTES.to_string())
+            .split(',')
+            .map(|attr| attr.trim().to_lowercase())
+            .filter(|attr| !attr.is_empty())
+            .collect()
+    })
+}
+
+pub fn is_sensitive(attr: &str) -> bool {
+    sensitive_attributes().contains(&attr.to_lowercase())
+}
+
+/// Debug representation of the values of `attr`, redacted if it is sensitive.
+pub struct AttrValues<'a, T>(pub &'a str, pub &'a T);
+
+impl<T: Debug> Debug for AttrValues<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match is_sensitive(self.0) {
+            true => write!(f, "{}", REDACTED),
+            false => self.1.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_credential() {
+        let secret: Secret = serde_json::from_str("\"hunter2\"").unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+        assert_eq!(format!("{:?}", secret), REDACTED);
+        assert_eq!(secret.to_string(), REDACTED);
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"[REDACTED]\"");
+    }
+
+    #[test]
+    fn round_trip_loses_credential() {
+        let secret: Secret = serde_json::from_str("\"hunter2\"").unwrap();
+        let json = serde_json::to_string(&secret).unwrap();
+        let secret: Secret = serde_json::from_str(&json).unwrap();
+        assert_eq!(secret.expose(), REDACTED);
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::Instant;
+
+use axum::http::StatusCode;
+use ldap3_serde::{Ldap, LdapConnAsync};
+
+use crate::{context, health, metrics::METRICS, types::routes::Response};
+
+/// Label of an upstream in metrics: `default` for `LDAP_HOST` and `LDAP_PORT`, the
+/// address of upstreams listed in `HEALTH_UPSTREAMS`, and `other` for any other host a
+/// caller names, so callers cannot add label values.
+fn metrics_label(address: &str) -> String {
+    if address == default_address() {
+        return "default".to_string();
+    }
+    match health::upstreams()
+        .iter()
+        .any(|(host, port)| format!("{}:{}", host, port) == address)
+    {
+        true => address.to_string(),
+        false => "other".to_string(),
+    }
+}
+
+fn default_host() -> String {
+    dotenv::var("LDAP_HOST").unwrap_or_else(|_| "localhost".to_string())
+}
+
+fn default_port() -> u16 {
+    dotenv::var("LDAP_PORT")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(389)
+}
+
+fn default_address() -> String {
+    format!("{}:{}", default_host(), default_port())
+}
+
+/// Opens a connection to the upstream a signed request names in its `data`. Unlike
+/// [`connect`], this defaults to `localhost:389`, as `/query` always has, rather than
+/// to `LDAP_HOST` and `LDAP_PORT`.
+pub async fn connect_named(host: Option<String>, port: Option<u16>) -> Result<Ldap, Response> {
+    let host = host.unwrap_or("localhost".to_string());
+    let port = port.unwrap_or(389);
+    connect(Some(host), Some(port)).await
+}
+
+/// Opens a connection to an upstream directory, defaulting to `LDAP_HOST` and `LDAP_PORT`.
+pub async fn connect(host: Option<String>, port: Option<u16>) -> Result<Ldap, Response> {
+    let host = host.unwrap_or_else(default_host);
+    let port = port.unwrap_or_else(default_port);
+    let address = format!("{}:{}", host, port);
+    let url = format!("ldap://{}", address);
+
+    tracing::Span::current().record("upstream", &address);
+    context::set_upstream(address.clone());
+
+    let label = metrics_label(&address);
+    let started = Instant::now();
+    let res = LdapConnAsync::new(url.as_str()).await;
+    METRICS
+        .upstream_connect_duration
+        .with_label_values(&[&label])
+        .observe(started.elapsed().as_secs_f64());
+
+    let (conn, ldap) = match res {
+        Ok(val) => val,
+        Err(err) => {
+            METRICS
+                .upstream_connect_errors
+                .with_label_values(&[&label])
+                .inc();
+            return Err(Response::error(
+                StatusCode::BAD_GATEWAY,
+                format!("Failed to connect to LDAP server: {:?}", err),
+            ));
+        }
+    };
+    ldap3_serde::drive!(conn);
+
+    Ok(ldap)
+}
+
+/// Connects to `LDAP_HOST` and `LDAP_PORT` and binds the service account. Routes
+/// acting as the service account never connect to a host named by the caller, which
+/// would receive its password.
+pub async fn connect_service() -> Result<Ldap, Response> {
+    let mut ldap = connect(None, None).await?;
+    bind_service(&mut ldap).await?;
+    Ok(ldap)
+}
+
+/// Binds with the service account in `LDAP_BIND_DN` and `LDAP_BIND_PASSWORD`, if configured.
+async fn bind_service(ldap: &mut Ldap) -> Result<(), Response> {
+    let (dn, pw) = match (
+        dotenv::var("LDAP_BIND_DN"),
+        dotenv::var("LDAP_BIND_PASSWORD"),
+    ) {
+        (Ok(dn), Ok(pw)) => (dn, pw),
+        _ => return Ok(()),
+    };
+
+    match ldap.simple_bind(&dn, &pw).await {
+        Ok(res) if res.rc == 0 => Ok(()),
+        Ok(res) => Err(Response::error(
+            StatusCode::BAD_GATEWAY,
+            format!("Failed to bind service account: {}", res),
+        )),
+        Err(err) => Err(Response::error(
+            StatusCode::BAD_GATEWAY,
+            format!("Failed to bind service account: {:?}", err),
+        )),
+    }
+}
@@ -0,0 +1,590 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use ldap3_serde::{Ldap, LdapError, Scope, SearchEntry};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        Semaphore,
+    },
+};
+
+use crate::{
+    dn,
+    sync::{watch, SyncMessage},
+    types::{
+        change::{ChangeEvent, ChangeKind},
+        entry::Entry,
+        query::{EnsureAction, QueryCommand, QueryResult},
+        secret::is_sensitive,
+    },
+    upstream,
+};
+
+/// Endpoint notified about directory changes, as configured in `WEBHOOKS_PATH`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent in `X-Webhook-Signature`.
+    pub secret: String,
+    /// Only changes at or below this DN are delivered.
+    #[serde(default)]
+    pub base: Option<String>,
+    /// Only changes to entries with one of these object classes are delivered.
+    #[serde(default)]
+    pub object_classes: Vec<String>,
+    /// Only additions and modifications touching one of these attributes are delivered.
+    #[serde(default)]
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventSource {
+    /// Write executed through this service.
+    Bridge,
+    /// Change reported by the upstream content synchronization feed.
+    Upstream,
+}
+
+/// Payload POSTed to webhook targets.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    pub id: String,
+    pub source: EventSource,
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    pub dn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_dn: Option<String>,
+    pub object_classes: Vec<String>,
+    /// Attributes set by an addition or touched by a modification.
+    pub attributes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<Entry>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    url: &'a str,
+    attempts: u32,
+    error: String,
+    event: &'a WebhookEvent,
+}
+
+struct Webhooks {
+    targets: Arc<Vec<WebhookTarget>>,
+    tx: mpsc::Sender<WebhookEvent>,
+    /// Whether the upstream change feed is followed, which then reports writes
+    /// through this service as well.
+    follows_upstream: bool,
+}
+
+/// Events waiting to be dispatched; further writes are not published while it is full.
+const QUEUE_SIZE: usize = 1024;
+
+/// Deliveries, with their retries, running at once.
+const CONCURRENT_DELIVERIES: usize = 32;
+
+/// Commands run without access to the application state, so the dispatcher is
+/// reachable through a global set once at startup.
+static WEBHOOKS: OnceLock<Webhooks> = OnceLock::new();
+
+/// Reads the targets from `WEBHOOKS_PATH`; none are configured if it is unset.
+pub fn load() -> Result<Vec<WebhookTarget>, String> {
+    let path = match dotenv::var("WEBHOOKS_PATH") {
+        Ok(val) => val,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+    };
+
+    let targets: Vec<WebhookTarget> = match serde_json::from_str(&content) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to parse {}: {}", path, err)),
+    };
+
+    for target in targets.iter() {
+        if let Some(base) = &target.base {
+            if let Err(err) = dn::parse(base) {
+                return Err(format!("Invalid base of webhook {}: {}", target.url, err));
+            }
+        }
+    }
+
+    Ok(targets)
+}
+
+/// Starts delivering events to `targets`, and following upstream changes below
+/// `WEBHOOK_SYNC_BASE` if it is set.
+pub fn start(targets: Vec<WebhookTarget>) {
+    if targets.is_empty() {
+        return;
+    }
+
+    let targets = Arc::new(targets);
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    let base = dotenv::var("WEBHOOK_SYNC_BASE").ok();
+    if WEBHOOKS
+        .set(Webhooks {
+            targets: targets.clone(),
+            tx,
+            follows_upstream: base.is_some(),
+        })
+        .is_err()
+    {
+        return;
+    }
+
+    tracing::info!("Delivering changes to {} webhook(s)", targets.len());
+    tokio::spawn(dispatch(targets, rx));
+
+    if let Some(base) = base {
+        tokio::spawn(follow_upstream(base));
+    }
+}
+
+/// Values of `objectClass` among `attrs`, whatever the case of its name.
+fn object_classes_of<'a>(
+    attrs: impl IntoIterator<Item = (&'a String, &'a HashSet<String>)>,
+) -> Vec<String> {
+    attrs
+        .into_iter()
+        .filter(|(attr, _)| attr.eq_ignore_ascii_case("objectClass"))
+        .flat_map(|(_, values)| values.iter().cloned())
+        .collect()
+}
+
+/// Reads the object classes of `dn` before a write, so they can be matched once it
+/// is gone or renamed. Skipped unless some target filters by object class.
+async fn object_classes(webhooks: &Webhooks, ldap: &mut Ldap, dn: &str) -> Vec<String> {
+    if webhooks
+        .targets
+        .iter()
+        .all(|target| target.object_classes.is_empty())
+    {
+        return Vec::new();
+    }
+
+    let entries = match ldap
+        .search(dn, Scope::Base, "(objectClass=*)", vec!["objectClass"])
+        .await
+        .and_then(|res| res.success())
+    {
+        Ok((entries, _)) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .into_iter()
+        .next()
+        .map(SearchEntry::construct)
+        .map(|entry| {
+            entry
+                .attrs
+                .into_iter()
+                .filter(|(attr, _)| attr.eq_ignore_ascii_case("objectClass"))
+                .flat_map(|(_, values)| values)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Write a command is about to make, as far as webhook targets are concerned.
+pub struct BridgeWrite {
+    kind: ChangeKind,
+    dn: String,
+    old_dn: Option<String>,
+    object_classes: Vec<String>,
+    attributes: Vec<String>,
+}
+
+/// Describes the write of `command` before it runs, reading the object classes of
+/// the entry while it still has them. `None` for commands that do not write an entry,
+/// when no webhooks are configured, and when the upstream feed reports the write.
+pub async fn prepare(ldap: &mut Ldap, command: &QueryCommand<'_>) -> Option<BridgeWrite> {
+    let webhooks = WEBHOOKS.get()?;
+    if webhooks.follows_upstream {
+        return None;
+    }
+
+    let (kind, dn, old_dn, attributes) = match command {
+        QueryCommand::Add(cmd) => (
+            ChangeKind::Add,
+            cmd.dn.clone(),
+            None,
+            cmd.attrs.iter().map(|(attr, _)| attr.clone()).collect(),
+        ),
+        QueryCommand::Modify(cmd) => (
+            ChangeKind::Modify,
+            cmd.dn.clone(),
+            None,
+            cmd.changes.iter().map(|m| m.attr().to_string()).collect(),
+        ),
+        QueryCommand::Delete(cmd) => (ChangeKind::Delete, cmd.dn.clone(), None, Vec::new()),
+        QueryCommand::ModifyDn(cmd) => (
+            ChangeKind::ModDn,
+            cmd.new_dn(),
+            Some(cmd.dn.clone()),
+            Vec::new(),
+        ),
+        // Whether the entry is added or modified, and which attributes, is only known
+        // from the result.
+        QueryCommand::Ensure(cmd) => (ChangeKind::Modify, cmd.dn.clone(), None, Vec::new()),
+        _ => return None,
+    };
+
+    let object_classes = match command {
+        QueryCommand::Add(cmd) => {
+            object_classes_of(cmd.attrs.iter().map(|(attr, values)| (attr, values)))
+        }
+        QueryCommand::Ensure(cmd) => {
+            let current = object_classes(webhooks, ldap, &cmd.dn).await;
+            match current.is_empty() {
+                true => object_classes_of(cmd.attrs.iter().map(|attr| (&attr.attr, &attr.values))),
+                false => current,
+            }
+        }
+        _ => object_classes(webhooks, ldap, old_dn.as_deref().unwrap_or(&dn)).await,
+    };
+
+    Some(BridgeWrite {
+        kind,
+        dn,
+        old_dn,
+        object_classes,
+        attributes,
+    })
+}
+
+/// Publishes a prepared write if the command succeeded and changed the entry.
+pub fn bridge_write(write: BridgeWrite, res: &Result<Option<QueryResult>, LdapError>) {
+    let (kind, attributes) = match res {
+        Ok(Some(QueryResult::Common(res))) if res.rc == 0 => (write.kind, write.attributes),
+        Ok(Some(QueryResult::Ensure(res))) if res.changed => match res.action {
+            EnsureAction::Add => (ChangeKind::Add, res.attributes.clone()),
+            EnsureAction::Modify => (ChangeKind::Modify, res.attributes.clone()),
+            EnsureAction::None => return,
+        },
+        _ => return,
+    };
+
+    let event = WebhookEvent {
+        id: uuid::Uuid::new_v4().to_string(),
+        source: EventSource::Bridge,
+        kind,
+        dn: write.dn,
+        old_dn: write.old_dn,
+        object_classes: write.object_classes,
+        attributes,
+        entry: None,
+        timestamp: Utc::now(),
+    };
+    if let Some(webhooks) = WEBHOOKS.get() {
+        if let Err(TrySendError::Full(event)) = webhooks.tx.try_send(event) {
+            tracing::error!("Webhook queue is full, dropping event {}", event.id);
+        }
+    }
+}
+
+fn overlaps(wanted: &[String], present: &[String]) -> bool {
+    wanted
+        .iter()
+        .any(|wanted| present.iter().any(|val| val.eq_ignore_ascii_case(wanted)))
+}
+
+impl WebhookTarget {
+    fn matches(&self, event: &WebhookEvent) -> bool {
+        if let Some(base) = self.base.as_deref().and_then(|val| dn::parse(val).ok()) {
+            let within = |val: &str| dn::parse(val).is_ok_and(|val| val.is_within(&base));
+            if !within(&event.dn) && !event.old_dn.as_deref().is_some_and(within) {
+                return false;
+            }
+        }
+
+        if !self.object_classes.is_empty() && !overlaps(&self.object_classes, &event.object_classes)
+        {
+            return false;
+        }
+
+        match event.kind {
+            ChangeKind::Add | ChangeKind::Modify if !self.attributes.is_empty() => {
+                overlaps(&self.attributes, &event.attributes)
+            }
+            _ => true,
+        }
+    }
+}
+
+async fn dispatch(targets: Arc<Vec<WebhookTarget>>, mut rx: mpsc::Receiver<WebhookEvent>) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Failed to create webhook client: {:?}", err);
+            return;
+        }
+    };
+
+    // Waiting for a permit leaves further events in the queue while every delivery
+    // slot is taken.
+    let deliveries = Arc::new(Semaphore::new(CONCURRENT_DELIVERIES));
+    while let Some(event) = rx.recv().await {
+        let event = Arc::new(event);
+        for target in targets.iter().filter(|target| target.matches(&event)) {
+            let permit = match deliveries.clone().acquire_owned().await {
+                Ok(val) => val,
+                Err(_) => return,
+            };
+            let delivery = deliver(client.clone(), target.clone(), event.clone());
+            tokio::spawn(async move {
+                delivery.await;
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// Hex-encoded HMAC-SHA256 of `{timestamp}.{body}`.
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(val) => val,
+        Err(_) => unreachable!("HMAC accepts keys of any length"),
+    };
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// POSTs `event` to `target`, retrying with exponential backoff up to
+/// `WEBHOOK_MAX_ATTEMPTS` times before writing it to the dead-letter file.
+async fn deliver(client: reqwest::Client, target: WebhookTarget, event: Arc<WebhookEvent>) {
+    let body = match serde_json::to_string(event.as_ref()) {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Failed to serialize webhook event: {:?}", err);
+            return;
+        }
+    };
+    let max_attempts = dotenv::var("WEBHOOK_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|val| val.parse::<u32>().ok())
+        .unwrap_or(5)
+        .max(1);
+
+    let mut error = String::new();
+    for attempt in 1..=max_attempts {
+        let timestamp = Utc::now().timestamp();
+        let res = client
+            .post(&target.url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Id", &event.id)
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .header(
+                "X-Webhook-Signature",
+                format!("sha256={}", sign(&target.secret, timestamp, &body)),
+            )
+            .body(body.clone())
+            .send()
+            .await;
+
+        error = match res {
+            Ok(res) if res.status().is_success() => return,
+            Ok(res) => format!("Target responded with {}", res.status()),
+            Err(err) => format!("Request failed: {}", err),
+        };
+        tracing::warn!(
+            "Webhook {} delivery to {} failed (attempt {}/{}): {}",
+            event.id,
+            target.url,
+            attempt,
+            max_attempts,
+            error
+        );
+
+        if attempt < max_attempts {
+            let delay = 2u64.saturating_pow(attempt - 1).min(60);
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+        }
+    }
+
+    dead_letter(&target, &event, max_attempts, error).await;
+}
+
+/// Appends an undeliverable event to `WEBHOOK_DEAD_LETTER_PATH`, one JSON object per line.
+async fn dead_letter(target: &WebhookTarget, event: &WebhookEvent, attempts: u32, error: String) {
+    let path = dotenv::var("WEBHOOK_DEAD_LETTER_PATH")
+        .unwrap_or_else(|_| "webhooks.dead.jsonl".to_string());
+    let record = DeadLetter {
+        url: &target.url,
+        attempts,
+        error,
+        event,
+    };
+    let line = match serde_json::to_string(&record) {
+        Ok(val) => val + "\n",
+        Err(err) => {
+            tracing::error!("Failed to serialize dead letter: {:?}", err);
+            return;
+        }
+    };
+
+    let mut file = match tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => {
+            tracing::error!("Failed to open {}: {:?}", path, err);
+            return;
+        }
+    };
+    if let Err(err) = file.write_all(line.as_bytes()).await {
+        tracing::error!("Failed to write {}: {:?}", path, err);
+    }
+}
+
+fn upstream_event(mut change: ChangeEvent) -> Option<WebhookEvent> {
+    let dn = change.dn?;
+    if let Some(entry) = change.entry.as_mut() {
+        entry.attrs.retain(|attr, _| !is_sensitive(attr));
+        entry.bin_attrs.retain(|attr, _| !is_sensitive(attr));
+    }
+    let (object_classes, attributes) = match &change.entry {
+        Some(entry) => (
+            entry
+                .attrs
+                .iter()
+                .filter(|(attr, _)| attr.eq_ignore_ascii_case("objectClass"))
+                .flat_map(|(_, values)| values.iter().cloned())
+                .collect(),
+            entry
+                .attrs
+                .keys()
+                .chain(entry.bin_attrs.keys())
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+
+    Some(WebhookEvent {
+        id: uuid::Uuid::new_v4().to_string(),
+        source: EventSource::Upstream,
+        kind: change.kind,
+        dn,
+        old_dn: change.old_dn,
+        object_classes,
+        attributes,
+        entry: change.entry,
+        timestamp: Utc::now(),
+    })
+}
+
+/// Publishes changes below `base` reported by the upstream, resuming from the
+/// cookie stored in `WEBHOOK_SYNC_COOKIE_PATH` across reconnects and restarts.
+///
+/// Without a stored cookie the initial refresh only establishes one, so existing
+/// entries are not reported as additions. The upstream sends deletions without
+/// attributes, so they never match targets filtering by object class.
+async fn follow_upstream(base: String) {
+    let filter =
+        dotenv::var("WEBHOOK_SYNC_FILTER").unwrap_or_else(|_| "(objectClass=*)".to_string());
+    let cookie_path =
+        dotenv::var("WEBHOOK_SYNC_COOKIE_PATH").unwrap_or_else(|_| "webhooks.cookie".to_string());
+    let mut cookie = tokio::fs::read(&cookie_path).await.ok();
+
+    loop {
+        let ldap = match upstream::connect_service().await.ok() {
+            Some(val) => val,
+            None => {
+                tracing::warn!("Failed to connect to upstream for webhooks, retrying");
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let (tx, mut rx) = mpsc::channel(64);
+        tokio::spawn(watch(
+            ldap,
+            base.clone(),
+            filter.clone(),
+            vec!["*".to_string()],
+            cookie.clone(),
+            tx,
+        ));
+
+        let mut live = cookie.is_some();
+        while let Some(message) = rx.recv().await {
+            let next = match message {
+                SyncMessage::Change(change, next) => {
+                    if live && change.kind != ChangeKind::Present {
+                        if let Some(event) = upstream_event(change) {
+                            // Holds the feed, and its cookie, back while the queue is full.
+                            if let Some(webhooks) = WEBHOOKS.get() {
+                                let _ = webhooks.tx.send(event).await;
+                            }
+                        }
+                    }
+                    next
+                }
+                SyncMessage::Sync(sync, next) => {
+                    live |= sync.refresh_done;
+                    next
+                }
+                SyncMessage::Error(err) => {
+                    tracing::warn!("Upstream change feed failed: {}", err);
+                    None
+                }
+            };
+
+            // Cookies handed out during the initial refresh are not kept, so an
+            // interrupted refresh starts over instead of reporting existing entries.
+            if let (true, Some(next)) = (live, next) {
+                if let Err(err) = tokio::fs::write(&cookie_path, &next).await {
+                    tracing::error!("Failed to write {}: {:?}", cookie_path, err);
+                }
+                cookie = Some(next);
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_object_classes_whatever_the_case() {
+        let attrs = [
+            (
+                "objectclass".to_string(),
+                HashSet::from(["person".to_string()]),
+            ),
+            ("cn".to_string(), HashSet::from(["Alice".to_string()])),
+        ];
+        assert_eq!(
+            object_classes_of(attrs.iter().map(|(attr, values)| (attr, values))),
+            vec!["person".to_string()]
+        );
+    }
+}